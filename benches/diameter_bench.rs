@@ -4,20 +4,54 @@ extern crate test;
 use diameter::avp;
 use diameter::avp::flags::M;
 use diameter::avp::Avp;
+use diameter::avp::DecodeOptions;
 use diameter::avp::Enumerated;
 use diameter::avp::Grouped;
 use diameter::avp::Identity;
+use diameter::avp::OctetString;
 use diameter::avp::UTF8String;
 use diameter::avp::Unsigned32;
 use diameter::flags;
+use diameter::transport::Codec;
 use diameter::ApplicationId;
 use diameter::CommandCode;
 use diameter::DiameterHeader;
 use diameter::DiameterMessage;
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use test::black_box;
 use test::Bencher;
 
+// Counts heap allocations so the read benches can report allocations per message;
+// run with `cargo +nightly bench --features nightly -- --nocapture` to see them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_per_message<F: FnMut()>(mut f: F) -> f64 {
+    let n = 1000;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..n {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / n as f64
+}
+
 #[bench]
 fn bench_decode_header(b: &mut Bencher) {
     let data = test_data();
@@ -85,6 +119,57 @@ fn bench_encode_cca(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn bench_decode_large_message_copy(b: &mut Bencher) {
+    let data = large_message().to_bytes().unwrap();
+    b.iter(|| black_box(DiameterMessage::from_bytes(&data).unwrap()));
+}
+
+#[bench]
+fn bench_decode_large_message_zero_copy(b: &mut Bencher) {
+    let data = bytes::Bytes::from(large_message().to_bytes().unwrap());
+    let options = DecodeOptions::default();
+    b.iter(|| black_box(DiameterMessage::decode_from_shared(&data, &options).unwrap()));
+}
+
+#[bench]
+fn bench_read_message_fresh_buffer(b: &mut Bencher) {
+    let data = cca_message().to_bytes().unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut read = || {
+        let mut reader = &data[..];
+        black_box(rt.block_on(Codec::decode(&mut reader)).unwrap());
+    };
+    eprintln!(
+        "fresh buffer: {} allocations per message",
+        allocations_per_message(&mut read)
+    );
+    b.iter(read);
+}
+
+#[bench]
+fn bench_read_message_reused_buffer(b: &mut Bencher) {
+    let data = cca_message().to_bytes().unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut buf = bytes::BytesMut::new();
+    let mut read = || {
+        let mut reader = &data[..];
+        black_box(
+            rt.block_on(Codec::decode_with_buffer(&mut reader, &mut buf))
+                .unwrap(),
+        );
+    };
+    eprintln!(
+        "reused buffer: {} allocations per message",
+        allocations_per_message(&mut read)
+    );
+    b.iter(read);
+}
+
 fn test_data() -> &'static [u8] {
     return &[
         0x01, 0x00, 0x00, 0x14, // version, length
@@ -143,3 +228,18 @@ fn cca_message() -> DiameterMessage {
 }
 
 fn main() {}
+
+// A relay-style message carrying 64 Class AVPs of 1KB each.
+fn large_message() -> DiameterMessage {
+    let mut message = DiameterMessage::new(
+        CommandCode::CreditControl,
+        ApplicationId::CreditControl,
+        flags::REQUEST | flags::PROXYABLE,
+        1123158610,
+        3102381851,
+    );
+    for i in 0..64 {
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![i as u8; 1024])));
+    }
+    message
+}
@@ -30,6 +30,7 @@ async fn main() {
     let client_config = DiameterClientConfig {
         use_tls: false,
         verify_cert: false,
+        ..Default::default()
     };
     let mut client = DiameterClient::new("localhost:3868", client_config);
     let mut handler = client.connect().await.unwrap();
@@ -57,6 +57,7 @@ async fn main() {
             let client_config = DiameterClientConfig {
                 use_tls: false,
                 verify_cert: false,
+                ..Default::default()
             };
             let mut client = DiameterClient::new("localhost:3868", client_config);
             let mut handler = client.connect().await.unwrap();
@@ -68,12 +69,10 @@ async fn main() {
             send_cer(&mut client).await;
 
             // Send a batch of Credit-Control-Request Initial (CCR-I) Diameter message
-            let mut session_count = 0;
             let mut ccri_futures = vec![];
             let batch_size = 10;
-            for _ in 0..batch_size {
+            for session_count in 0..batch_size {
                 let session_id = format!("ses;{:09}", session_count);
-                session_count += 1;
                 let future = send_ccr_i(&mut client, &session_id).await;
                 ccri_futures.push(future);
             }
@@ -129,7 +128,7 @@ async fn send_ccr_i(client: &mut DiameterClient, session_id: &str) -> JoinHandle
     );
     ccr.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
     ccr.add_avp(avp!(296, None, M, Identity::new("realm.example.com")));
-    ccr.add_avp(avp!(263, None, M, UTF8String::new(&session_id)));
+    ccr.add_avp(avp!(263, None, M, UTF8String::new(session_id)));
     ccr.add_avp(avp!(416, None, M, Enumerated::new(1)));
     ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
     ccr.add_avp(avp!(
@@ -46,7 +46,7 @@ async fn main() {
         dictionary.load_xml(&xml);
     }
 
-    let config = DiameterServerConfig { native_tls: None };
+    let config = DiameterServerConfig::default();
 
     // Set up a Diameter server listening on a specific port
     let addr = "0.0.0.0:3868";
@@ -0,0 +1,528 @@
+//! Diameter Credit-Control for online charging (Gy/Ro)
+//!
+//! Typed support for the Multiple-Services-Credit-Control (456) grouped AVP
+//! defined in RFC 4006, section 8.16, and the service unit AVPs it carries,
+//! plus a builder for Credit-Control-Requests.
+
+use crate::avp::codes::{
+    AUTH_APPLICATION_ID, DESTINATION_REALM, ORIGIN_HOST, ORIGIN_REALM, RESULT_CODE, SESSION_ID,
+};
+use crate::avp::flags::M;
+use crate::avp::{Avp, Enumerated, Grouped, Identity, UTF8String, Unsigned32, Unsigned64};
+use crate::diameter::flags;
+use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+use crate::error::{Error, Result};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+pub const CC_REQUEST_TYPE: u32 = 416;
+pub const CC_REQUEST_NUMBER: u32 = 415;
+
+pub const MULTIPLE_SERVICES_CREDIT_CONTROL: u32 = 456;
+pub const RATING_GROUP: u32 = 432;
+pub const SERVICE_IDENTIFIER: u32 = 439;
+pub const REQUESTED_SERVICE_UNIT: u32 = 437;
+pub const GRANTED_SERVICE_UNIT: u32 = 431;
+pub const USED_SERVICE_UNIT: u32 = 446;
+pub const VALIDITY_TIME: u32 = 448;
+pub const CC_TIME: u32 = 420;
+pub const CC_TOTAL_OCTETS: u32 = 421;
+pub const CC_INPUT_OCTETS: u32 = 412;
+pub const CC_OUTPUT_OCTETS: u32 = 414;
+pub const CC_SERVICE_SPECIFIC_UNITS: u32 = 417;
+
+/// Enumerates the CC-Request-Type (416) values.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+pub enum CcRequestType {
+    Initial = 1,
+    Update = 2,
+    Termination = 3,
+    Event = 4,
+}
+
+impl CcRequestType {
+    pub fn from_i32(value: i32) -> Option<CcRequestType> {
+        FromPrimitive::from_i32(value)
+    }
+
+    pub fn as_i32(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Builds the CC-Request-Type AVP.
+    pub fn to_avp(&self) -> Avp {
+        Avp::new(
+            CC_REQUEST_TYPE,
+            None,
+            M,
+            Enumerated::new(self.as_i32()).into(),
+        )
+    }
+}
+
+/// The units of a Requested-, Granted- or Used-Service-Unit grouped AVP.
+///
+/// An empty `ServiceUnit` encodes as an empty grouped AVP, which in a
+/// Requested-Service-Unit asks the server to choose the amount.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServiceUnit {
+    pub time: Option<u32>,
+    pub total_octets: Option<u64>,
+    pub input_octets: Option<u64>,
+    pub output_octets: Option<u64>,
+    pub service_specific_units: Option<u64>,
+}
+
+/// A decoded Multiple-Services-Credit-Control grouped AVP.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mscc {
+    pub rating_group: Option<u32>,
+    pub service_identifiers: Vec<u32>,
+    pub requested_service_unit: Option<ServiceUnit>,
+    pub granted_service_unit: Option<ServiceUnit>,
+    pub used_service_units: Vec<ServiceUnit>,
+    pub validity_time: Option<u32>,
+    pub result_code: Option<u32>,
+}
+
+impl ServiceUnit {
+    /// Returns a service unit with no units set.
+    pub fn new() -> ServiceUnit {
+        ServiceUnit::default()
+    }
+
+    /// Sets the CC-Time (420), in seconds.
+    pub fn with_time(mut self, time: u32) -> ServiceUnit {
+        self.time = Some(time);
+        self
+    }
+
+    /// Sets the CC-Total-Octets (421).
+    pub fn with_total_octets(mut self, octets: u64) -> ServiceUnit {
+        self.total_octets = Some(octets);
+        self
+    }
+
+    /// Sets the CC-Input-Octets (412).
+    pub fn with_input_octets(mut self, octets: u64) -> ServiceUnit {
+        self.input_octets = Some(octets);
+        self
+    }
+
+    /// Sets the CC-Output-Octets (414).
+    pub fn with_output_octets(mut self, octets: u64) -> ServiceUnit {
+        self.output_octets = Some(octets);
+        self
+    }
+
+    /// Sets the CC-Service-Specific-Units (417).
+    pub fn with_service_specific_units(mut self, units: u64) -> ServiceUnit {
+        self.service_specific_units = Some(units);
+        self
+    }
+
+    /// Builds a Granted-Service-Unit (431) AVP.
+    pub fn to_granted_avp(&self) -> Avp {
+        self.to_avp(GRANTED_SERVICE_UNIT)
+    }
+
+    /// Builds a Used-Service-Unit (446) AVP.
+    pub fn to_used_avp(&self) -> Avp {
+        self.to_avp(USED_SERVICE_UNIT)
+    }
+
+    /// Builds the service unit grouped AVP with the given code, e.g.
+    /// `REQUESTED_SERVICE_UNIT`.
+    pub fn to_avp(&self, code: u32) -> Avp {
+        let mut avps = Vec::new();
+        if let Some(time) = self.time {
+            avps.push(Avp::new(CC_TIME, None, M, Unsigned32::new(time).into()));
+        }
+        let octets = [
+            (CC_TOTAL_OCTETS, self.total_octets),
+            (CC_INPUT_OCTETS, self.input_octets),
+            (CC_OUTPUT_OCTETS, self.output_octets),
+            (CC_SERVICE_SPECIFIC_UNITS, self.service_specific_units),
+        ];
+        for (code, value) in octets {
+            if let Some(value) = value {
+                avps.push(Avp::new(code, None, M, Unsigned64::new(value).into()));
+            }
+        }
+        Avp::new(code, None, M, Grouped::new(avps).into())
+    }
+
+    /// Reads a service unit from a grouped AVP.
+    pub fn from_avp(avp: &Avp) -> Result<ServiceUnit> {
+        let group = avp
+            .get_grouped()
+            .ok_or_else(|| Error::DecodeError("service unit is not a grouped AVP".into()))?;
+        let u64_child = |code| {
+            group
+                .avps()
+                .iter()
+                .find(|avp| avp.get_code() == code)
+                .and_then(|avp| avp.get_unsigned64())
+        };
+        Ok(ServiceUnit {
+            time: group
+                .avps()
+                .iter()
+                .find(|avp| avp.get_code() == CC_TIME)
+                .and_then(|avp| avp.get_unsigned32()),
+            total_octets: u64_child(CC_TOTAL_OCTETS),
+            input_octets: u64_child(CC_INPUT_OCTETS),
+            output_octets: u64_child(CC_OUTPUT_OCTETS),
+            service_specific_units: u64_child(CC_SERVICE_SPECIFIC_UNITS),
+        })
+    }
+}
+
+impl Mscc {
+    /// Builds the Multiple-Services-Credit-Control grouped AVP.
+    pub fn to_avp(&self) -> Avp {
+        let mut avps = Vec::new();
+        for service_identifier in &self.service_identifiers {
+            avps.push(Avp::new(
+                SERVICE_IDENTIFIER,
+                None,
+                M,
+                Unsigned32::new(*service_identifier).into(),
+            ));
+        }
+        if let Some(rating_group) = self.rating_group {
+            avps.push(Avp::new(
+                RATING_GROUP,
+                None,
+                M,
+                Unsigned32::new(rating_group).into(),
+            ));
+        }
+        if let Some(gsu) = &self.granted_service_unit {
+            avps.push(gsu.to_avp(GRANTED_SERVICE_UNIT));
+        }
+        if let Some(rsu) = &self.requested_service_unit {
+            avps.push(rsu.to_avp(REQUESTED_SERVICE_UNIT));
+        }
+        for usu in &self.used_service_units {
+            avps.push(usu.to_avp(USED_SERVICE_UNIT));
+        }
+        if let Some(validity_time) = self.validity_time {
+            avps.push(Avp::new(
+                VALIDITY_TIME,
+                None,
+                M,
+                Unsigned32::new(validity_time).into(),
+            ));
+        }
+        if let Some(result_code) = self.result_code {
+            avps.push(Avp::new(
+                RESULT_CODE,
+                None,
+                M,
+                Unsigned32::new(result_code).into(),
+            ));
+        }
+        Avp::new(
+            MULTIPLE_SERVICES_CREDIT_CONTROL,
+            None,
+            M,
+            Grouped::new(avps).into(),
+        )
+    }
+
+    /// Reads a Multiple-Services-Credit-Control from a grouped AVP.
+    pub fn from_avp(avp: &Avp) -> Result<Mscc> {
+        let group = avp.get_grouped().ok_or_else(|| {
+            Error::DecodeError("Multiple-Services-Credit-Control is not a grouped AVP".into())
+        })?;
+
+        let mut mscc = Mscc::default();
+        for child in group.avps() {
+            match child.get_code() {
+                RATING_GROUP => mscc.rating_group = child.get_unsigned32(),
+                SERVICE_IDENTIFIER => mscc.service_identifiers.extend(child.get_unsigned32()),
+                REQUESTED_SERVICE_UNIT => {
+                    mscc.requested_service_unit = Some(ServiceUnit::from_avp(child)?)
+                }
+                GRANTED_SERVICE_UNIT => {
+                    mscc.granted_service_unit = Some(ServiceUnit::from_avp(child)?)
+                }
+                USED_SERVICE_UNIT => mscc.used_service_units.push(ServiceUnit::from_avp(child)?),
+                VALIDITY_TIME => mscc.validity_time = child.get_unsigned32(),
+                RESULT_CODE => mscc.result_code = child.get_unsigned32(),
+                _ => {}
+            }
+        }
+        Ok(mscc)
+    }
+}
+
+/// A Credit-Control-Request (CCR) for the Gy/Ro application.
+pub struct CreditControlRequest;
+
+/// Builds a `CreditControlRequest`; see `CreditControlRequest::builder`.
+#[derive(Debug, Clone, Default)]
+pub struct CreditControlRequestBuilder {
+    session_id: Option<String>,
+    origin: Option<(String, String)>,
+    destination_realm: Option<String>,
+    request_type: Option<CcRequestType>,
+    request_number: u32,
+    mscc: Vec<Mscc>,
+}
+
+impl CreditControlRequest {
+    /// Starts building a Credit-Control-Request.
+    ///
+    /// Example:
+    ///    ```
+    ///    use diameter::applications::gy::{CcRequestType, CreditControlRequest, Mscc};
+    ///
+    ///    let ccr = CreditControlRequest::builder()
+    ///        .session_id("ses;1")
+    ///        .origin("client.example.com", "example.com")
+    ///        .destination_realm("operator.net")
+    ///        .request_type(CcRequestType::Initial)
+    ///        .request_number(0)
+    ///        .mscc(Mscc::default())
+    ///        .build();
+    ///    assert_eq!(ccr.get_avps().len(), 8);
+    ///    ```
+    pub fn builder() -> CreditControlRequestBuilder {
+        CreditControlRequestBuilder::default()
+    }
+}
+
+impl CreditControlRequestBuilder {
+    /// Sets the Session-Id (263).
+    pub fn session_id(mut self, session_id: &str) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    /// Sets the Origin-Host (264) and Origin-Realm (296).
+    pub fn origin(mut self, host: &str, realm: &str) -> Self {
+        self.origin = Some((host.into(), realm.into()));
+        self
+    }
+
+    /// Sets the Destination-Realm (283).
+    pub fn destination_realm(mut self, realm: &str) -> Self {
+        self.destination_realm = Some(realm.into());
+        self
+    }
+
+    /// Sets the CC-Request-Type (416).
+    pub fn request_type(mut self, request_type: CcRequestType) -> Self {
+        self.request_type = Some(request_type);
+        self
+    }
+
+    /// Sets the CC-Request-Number (415), 0 by default.
+    pub fn request_number(mut self, request_number: u32) -> Self {
+        self.request_number = request_number;
+        self
+    }
+
+    /// Adds a Multiple-Services-Credit-Control (456). May be called more
+    /// than once.
+    pub fn mscc(mut self, mscc: Mscc) -> Self {
+        self.mscc.push(mscc);
+        self
+    }
+
+    /// Returns the CCR, with Session-Id first and Auth-Application-Id set to
+    /// Credit Control. AVPs that were not set are left out.
+    pub fn build(self) -> DiameterMessage {
+        let mut ccr =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST | flags::PROXYABLE)
+                .build();
+        if let Some(session_id) = &self.session_id {
+            ccr.add_avp(Avp::new(
+                SESSION_ID,
+                None,
+                M,
+                UTF8String::new(session_id).into(),
+            ));
+        }
+        if let Some((host, realm)) = &self.origin {
+            ccr.add_avp(Avp::new(ORIGIN_HOST, None, M, Identity::new(host).into()));
+            ccr.add_avp(Avp::new(ORIGIN_REALM, None, M, Identity::new(realm).into()));
+        }
+        if let Some(realm) = &self.destination_realm {
+            ccr.add_avp(Avp::new(
+                DESTINATION_REALM,
+                None,
+                M,
+                Identity::new(realm).into(),
+            ));
+        }
+        ccr.add_avp(Avp::new(
+            AUTH_APPLICATION_ID,
+            None,
+            M,
+            Unsigned32::new(ApplicationId::CreditControl.as_u32()).into(),
+        ));
+        if let Some(request_type) = self.request_type {
+            ccr.add_avp(request_type.to_avp());
+        }
+        ccr.add_avp(Avp::new(
+            CC_REQUEST_NUMBER,
+            None,
+            M,
+            Unsigned32::new(self.request_number).into(),
+        ));
+        for mscc in &self.mscc {
+            ccr.add_avp(mscc.to_avp());
+        }
+        ccr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mscc_round_trip() {
+        let mscc = Mscc {
+            rating_group: Some(100),
+            requested_service_unit: Some(ServiceUnit {
+                total_octets: Some(1024 * 1024),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        ccr.add_avp(mscc.to_avp());
+
+        let bytes = ccr.to_bytes().unwrap();
+        let ccr = DiameterMessage::from_bytes(&bytes).unwrap();
+        let decoded =
+            Mscc::from_avp(ccr.get_avp(MULTIPLE_SERVICES_CREDIT_CONTROL).unwrap()).unwrap();
+        assert_eq!(decoded, mscc);
+        assert_eq!(decoded.rating_group, Some(100));
+        assert_eq!(
+            decoded.requested_service_unit.unwrap().total_octets,
+            Some(1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn test_granted_service_unit_round_trip() {
+        let gsu = ServiceUnit::new()
+            .with_time(3600)
+            .with_total_octets(5 * 1024 * 1024 * 1024);
+        let avp = gsu.to_granted_avp();
+        assert_eq!(avp.get_code(), GRANTED_SERVICE_UNIT);
+
+        let mut cca = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+        );
+        cca.add_avp(avp);
+        let cca = DiameterMessage::from_bytes(&cca.to_bytes().unwrap()).unwrap();
+        let decoded = ServiceUnit::from_avp(cca.get_avp(GRANTED_SERVICE_UNIT).unwrap()).unwrap();
+        assert_eq!(decoded, gsu);
+        assert_eq!(decoded.total_octets, Some(5 * 1024 * 1024 * 1024));
+        assert_eq!(decoded.input_octets, None);
+
+        let usu = ServiceUnit::new()
+            .with_input_octets(10)
+            .with_output_octets(20);
+        assert_eq!(usu.to_used_avp().get_code(), USED_SERVICE_UNIT);
+        assert_eq!(ServiceUnit::from_avp(&usu.to_used_avp()).unwrap(), usu);
+    }
+
+    #[test]
+    fn test_mscc_used_service_units() {
+        let mscc = Mscc {
+            rating_group: Some(200),
+            used_service_units: vec![
+                ServiceUnit {
+                    time: Some(60),
+                    input_octets: Some(10),
+                    output_octets: Some(20),
+                    ..Default::default()
+                },
+                ServiceUnit {
+                    time: Some(30),
+                    ..Default::default()
+                },
+            ],
+            result_code: Some(2001),
+            ..Default::default()
+        };
+        assert_eq!(Mscc::from_avp(&mscc.to_avp()).unwrap(), mscc);
+    }
+
+    #[test]
+    fn test_cc_request_type_avp() {
+        let avp = CcRequestType::Update.to_avp();
+        assert_eq!(avp.get_code(), CC_REQUEST_TYPE);
+        assert_eq!(avp.get_enumerated().unwrap().value(), 2);
+        assert_eq!(CcRequestType::from_i32(2), Some(CcRequestType::Update));
+        assert_eq!(CcRequestType::from_i32(5), None);
+    }
+
+    #[test]
+    fn test_ccr_builder() {
+        let ccr = CreditControlRequest::builder()
+            .session_id("ses;12345888")
+            .origin("host.example.com", "realm.example.com")
+            .destination_realm("operator.net")
+            .request_type(CcRequestType::Update)
+            .request_number(3)
+            .mscc(Mscc {
+                rating_group: Some(100),
+                ..Default::default()
+            })
+            .build();
+
+        assert_eq!(ccr.get_command_code(), CommandCode::CreditControl);
+        assert_eq!(ccr.get_application_id(), ApplicationId::CreditControl);
+        assert_eq!(ccr.get_flags(), flags::REQUEST | flags::PROXYABLE);
+        let codes: Vec<u32> = ccr.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(
+            codes,
+            vec![
+                263,
+                264,
+                296,
+                283,
+                258,
+                416,
+                415,
+                MULTIPLE_SERVICES_CREDIT_CONTROL
+            ]
+        );
+        assert_eq!(
+            ccr.get_avp(CC_REQUEST_TYPE)
+                .unwrap()
+                .get_enumerated()
+                .unwrap()
+                .value(),
+            CcRequestType::Update.as_i32()
+        );
+        assert_eq!(
+            ccr.get_avp(CC_REQUEST_NUMBER).unwrap().get_unsigned32(),
+            Some(3)
+        );
+
+        let ccr = DiameterMessage::from_bytes(&ccr.to_bytes().unwrap()).unwrap();
+        ccr.validate().unwrap();
+    }
+}
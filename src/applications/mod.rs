@@ -0,0 +1,8 @@
+//! Diameter Applications
+//!
+//! Typed support for application-specific AVPs.
+
+pub mod accounting;
+pub mod gy;
+pub mod reauth;
+pub mod session;
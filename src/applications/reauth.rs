@@ -0,0 +1,129 @@
+//! Diameter Re-Auth (RAR/RAA)
+//!
+//! Helpers for the Re-Auth-Request and Re-Auth-Answer messages defined in
+//! RFC 6733, section 8.3, which a server sends to ask the client to
+//! re-authorize a session. RARs arrive on `DiameterClient::incoming_requests`
+//! and the RAA is sent back with `DiameterClient::send_answer`.
+
+use crate::avp::codes::{ORIGIN_HOST, ORIGIN_REALM, RESULT_CODE};
+use crate::avp::flags::M;
+use crate::avp::{Avp, Identity, Unsigned32};
+use crate::diameter::DiameterMessage;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+pub const RE_AUTH_REQUEST_TYPE: u32 = 285;
+
+/// Enumerates the Re-Auth-Request-Type (285) values.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+pub enum ReAuthRequestType {
+    AuthorizeOnly = 0,
+    AuthorizeAuthenticate = 1,
+}
+
+impl ReAuthRequestType {
+    pub fn from_i32(value: i32) -> Option<ReAuthRequestType> {
+        FromPrimitive::from_i32(value)
+    }
+}
+
+/// Returns the Re-Auth-Request-Type of a RAR.
+pub fn request_type(rar: &DiameterMessage) -> Option<ReAuthRequestType> {
+    let value = rar.get_avp(RE_AUTH_REQUEST_TYPE)?.get_enumerated()?.value();
+    ReAuthRequestType::from_i32(value)
+}
+
+/// Builds the Re-Auth-Answer to a RAR.
+///
+/// The answer carries the Session-Id and Proxy-Info of the request, the
+/// given Result-Code, and the Origin-Host and Origin-Realm of the answering
+/// client.
+///
+/// Example:
+///    ```
+///    use diameter::applications::reauth::raa;
+///    use diameter::{ApplicationId, CommandCode, DiameterMessage};
+///    use diameter::flags;
+///
+///    let rar = DiameterMessage::new(
+///        CommandCode::ReAuth,
+///        ApplicationId::CreditControl,
+///        flags::REQUEST,
+///        1,
+///        1,
+///    );
+///    let raa = raa(&rar, 2001, "client.example.com", "example.com");
+///    assert_eq!(raa.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+///    ```
+pub fn raa(
+    rar: &DiameterMessage,
+    result_code: u32,
+    origin_host: &str,
+    origin_realm: &str,
+) -> DiameterMessage {
+    let mut raa = rar.to_answer();
+    raa.add_avp(Avp::new(
+        RESULT_CODE,
+        None,
+        M,
+        Unsigned32::new(result_code).into(),
+    ));
+    raa.add_avp(Avp::new(
+        ORIGIN_HOST,
+        None,
+        M,
+        Identity::new(origin_host).into(),
+    ));
+    raa.add_avp(Avp::new(
+        ORIGIN_REALM,
+        None,
+        M,
+        Identity::new(origin_realm).into(),
+    ));
+    raa
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp::{Enumerated, UTF8String};
+    use crate::diameter::flags;
+    use crate::diameter::{ApplicationId, CommandCode};
+
+    #[test]
+    fn test_raa() {
+        let mut rar = DiameterMessage::new(
+            CommandCode::ReAuth,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            42,
+            43,
+        );
+        rar.add_avp(Avp::new(
+            263,
+            None,
+            M,
+            UTF8String::new("ses;12345888").into(),
+        ));
+        rar.add_avp(Avp::new(
+            RE_AUTH_REQUEST_TYPE,
+            None,
+            M,
+            Enumerated::new(0).into(),
+        ));
+        let rar = DiameterMessage::from_bytes(&rar.to_bytes().unwrap()).unwrap();
+        assert_eq!(request_type(&rar), Some(ReAuthRequestType::AuthorizeOnly));
+
+        let raa = raa(&rar, 2001, "client.example.com", "example.com");
+        assert_eq!(raa.get_command_code(), CommandCode::ReAuth);
+        assert_eq!(raa.get_flags(), flags::PROXYABLE);
+        assert_eq!(raa.get_hop_by_hop_id(), 42);
+        let codes: Vec<u32> = raa.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, RESULT_CODE, ORIGIN_HOST, ORIGIN_REALM]);
+        DiameterMessage::from_bytes(&raa.to_bytes().unwrap())
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Diameter session management (STR/STA, ASR/ASA)
+//!
+//! Helpers for the Session-Termination and Abort-Session messages defined in
+//! RFC 6733, sections 8.4 and 8.5. Re-Auth is covered in `reauth`.
+
+use crate::avp::codes::{AUTH_APPLICATION_ID, RESULT_CODE, SESSION_ID};
+use crate::avp::flags::M;
+use crate::avp::{Avp, Enumerated, UTF8String, Unsigned32};
+use crate::diameter::flags;
+use crate::diameter::{ApplicationId, CommandCode, DiameterMessage, DiameterMessageBuilder};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+pub const TERMINATION_CAUSE: u32 = 295;
+
+/// Enumerates the Termination-Cause (295) values.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+pub enum TerminationCause {
+    DiameterLogout = 1,
+    DiameterServiceNotProvided = 2,
+    DiameterBadAnswer = 3,
+    DiameterAdministrative = 4,
+    DiameterLinkBroken = 5,
+    DiameterAuthExpired = 6,
+    DiameterUserMoved = 7,
+    DiameterSessionTimeout = 8,
+}
+
+impl TerminationCause {
+    pub fn from_i32(value: i32) -> Option<TerminationCause> {
+        FromPrimitive::from_i32(value)
+    }
+}
+
+// Starts a proxiable request with Session-Id and Auth-Application-Id.
+fn session_request(
+    code: CommandCode,
+    session_id: &str,
+    application_id: ApplicationId,
+) -> DiameterMessageBuilder {
+    DiameterMessage::builder(code, application_id)
+        .flags(flags::REQUEST | flags::PROXYABLE)
+        .avp(Avp::new(
+            SESSION_ID,
+            None,
+            M,
+            UTF8String::new(session_id).into(),
+        ))
+        .avp(Avp::new(
+            AUTH_APPLICATION_ID,
+            None,
+            M,
+            Unsigned32::new(application_id.as_u32()).into(),
+        ))
+}
+
+/// Starts building a Session-Termination-Request.
+///
+/// Adds Session-Id, Auth-Application-Id and Termination-Cause. The routing
+/// AVPs are left to the caller, e.g. with `Routing::apply`.
+///
+/// Example:
+///    ```
+///    use diameter::applications::session::{session_termination, TerminationCause};
+///    use diameter::ApplicationId;
+///
+///    let str = session_termination(
+///        "ses;1",
+///        ApplicationId::CreditControl,
+///        TerminationCause::DiameterLogout,
+///    )
+///    .origin("client.example.com", "example.com")
+///    .destination_realm("operator.net")
+///    .build();
+///    assert_eq!(str.get_avps().len(), 6);
+///    ```
+pub fn session_termination(
+    session_id: &str,
+    application_id: ApplicationId,
+    cause: TerminationCause,
+) -> DiameterMessageBuilder {
+    session_request(CommandCode::SessionTerminate, session_id, application_id).avp(Avp::new(
+        TERMINATION_CAUSE,
+        None,
+        M,
+        Enumerated::new(cause as i32).into(),
+    ))
+}
+
+/// Starts building an Abort-Session-Request.
+///
+/// Adds Session-Id and Auth-Application-Id. The routing AVPs, including the
+/// required Destination-Host, are left to the caller.
+pub fn abort_session(session_id: &str, application_id: ApplicationId) -> DiameterMessageBuilder {
+    session_request(CommandCode::AbortSession, session_id, application_id)
+}
+
+/// Returns the Termination-Cause of an STR.
+pub fn termination_cause(msg: &DiameterMessage) -> Option<TerminationCause> {
+    let value = msg.get_avp(TERMINATION_CAUSE)?.get_enumerated()?.value();
+    TerminationCause::from_i32(value)
+}
+
+/// Returns the Result-Code of an STA or ASA.
+pub fn result_code(answer: &DiameterMessage) -> Option<u32> {
+    answer.get_avp(RESULT_CODE)?.get_unsigned32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_str_round_trip() {
+        let str = session_termination(
+            "ses;12345888",
+            ApplicationId::CreditControl,
+            TerminationCause::DiameterLogout,
+        )
+        .origin("client.example.com", "example.com")
+        .destination_realm("operator.net")
+        .build();
+
+        let str = DiameterMessage::from_bytes(&str.to_bytes().unwrap()).unwrap();
+        str.validate().unwrap();
+        assert_eq!(str.get_command_code(), CommandCode::SessionTerminate);
+        assert_eq!(str.get_application_id(), ApplicationId::CreditControl);
+        assert_eq!(str.get_flags(), flags::REQUEST | flags::PROXYABLE);
+        assert_eq!(
+            termination_cause(&str),
+            Some(TerminationCause::DiameterLogout)
+        );
+        assert_eq!(
+            str.get_avp(AUTH_APPLICATION_ID).unwrap().get_unsigned32(),
+            Some(4)
+        );
+
+        let mut sta = str.to_answer();
+        sta.add_avp(Avp::new(RESULT_CODE, None, M, Unsigned32::new(2001).into()));
+        let sta = DiameterMessage::from_bytes(&sta.to_bytes().unwrap()).unwrap();
+        assert_eq!(result_code(&sta), Some(2001));
+    }
+
+    #[test]
+    fn test_asr() {
+        let asr = abort_session("ses;12345888", ApplicationId::CreditControl)
+            .origin("ocs.operator.net", "operator.net")
+            .destination_realm("example.com")
+            .destination_host("client.example.com")
+            .build();
+
+        let asr = DiameterMessage::from_bytes(&asr.to_bytes().unwrap()).unwrap();
+        asr.validate().unwrap();
+        assert_eq!(asr.get_command_code(), CommandCode::AbortSession);
+        assert!(termination_cause(&asr).is_none());
+
+        let mut asa = asr.to_answer();
+        assert_eq!(result_code(&asa), None);
+        // DIAMETER_UNKNOWN_SESSION_ID
+        asa.add_avp(Avp::new(RESULT_CODE, None, M, Unsigned32::new(5002).into()));
+        let asa = DiameterMessage::from_bytes(&asa.to_bytes().unwrap()).unwrap();
+        assert_eq!(result_code(&asa), Some(5002));
+    }
+}
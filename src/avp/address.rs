@@ -3,6 +3,7 @@ use crate::error::Result;
 use std::fmt;
 use std::io::Read;
 use std::io::Write;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 
@@ -12,7 +13,7 @@ use super::octetstring::OctetString;
 pub enum Value {
     IPv4(Ipv4Addr),
     IPv6(Ipv6Addr),
-    E164(OctetString), // TODO
+    E164(OctetString),
 }
 
 #[derive(Debug, Clone)]
@@ -31,10 +32,53 @@ impl Address {
         Address(Value::IPv6(ip))
     }
 
+    pub fn from_ip(ip: IpAddr) -> Address {
+        match ip {
+            IpAddr::V4(ip) => Address::from_ipv4(ip),
+            IpAddr::V6(ip) => Address::from_ipv6(ip),
+        }
+    }
+
     pub fn from_e164(octet: OctetString) -> Address {
         Address(Value::E164(octet))
     }
 
+    /// Creates an IPv4 address from its four octets.
+    pub fn ipv4(a: u8, b: u8, c: u8, d: u8) -> Address {
+        Address(Value::IPv4(Ipv4Addr::new(a, b, c, d)))
+    }
+
+    /// Creates an IPv6 address from its eight 16-bit segments.
+    #[allow(clippy::too_many_arguments)]
+    pub fn ipv6(a: u16, b: u16, c: u16, d: u16, e: u16, f: u16, g: u16, h: u16) -> Address {
+        Address(Value::IPv6(Ipv6Addr::new(a, b, c, d, e, f, g, h)))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+
+    /// Returns the IP address, or `None` for E.164 addresses.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match &self.0 {
+            Value::IPv4(ip) => Some(IpAddr::V4(*ip)),
+            Value::IPv6(ip) => Some(IpAddr::V6(*ip)),
+            Value::E164(_) => None,
+        }
+    }
+
+    /// Returns the address with an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`)
+    /// converted to its IPv4 form. Other addresses are returned unchanged.
+    pub fn normalized(&self) -> Address {
+        match &self.0 {
+            Value::IPv6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => Address::from_ipv4(ip),
+                None => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+
     pub fn decode_from<R: Read>(reader: &mut R, len: usize) -> Result<Address> {
         let mut b = [0; 2];
         reader.read_exact(&mut b)?;
@@ -67,7 +111,11 @@ impl Address {
                 Address(Value::IPv6(ip))
             }
             [0, 8] => {
-                todo!("E164 not implemented")
+                if len < 2 {
+                    return Err(Error::DecodeError("Invalid address length".into()));
+                }
+                let octet = OctetString::decode_from(reader, len - 2)?;
+                Address(Value::E164(octet))
             }
             _ => return Err(Error::DecodeError("Unsupported address type".into())),
         };
@@ -84,7 +132,10 @@ impl Address {
                 writer.write_all(&[0, 2])?;
                 writer.write_all(&ip.octets())?;
             }
-            Value::E164(_) => todo!(),
+            Value::E164(octet) => {
+                writer.write_all(&[0, 8])?;
+                octet.encode_to(writer)?;
+            }
         };
         Ok(())
     }
@@ -93,7 +144,7 @@ impl Address {
         match &self.0 {
             Value::IPv4(_) => 6,
             Value::IPv6(_) => 18,
-            Value::E164(_) => todo!(),
+            Value::E164(octet) => 2 + octet.length(),
         }
     }
 }
@@ -140,4 +191,94 @@ mod tests {
         let avp = Address::decode_from(&mut cursor, 18).unwrap();
         assert_eq!(avp.0.to_string(), "::1");
     }
+
+    #[test]
+    fn test_encode_ipv4() {
+        let avp = Address::ipv4(192, 168, 1, 10);
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded, vec![0x00, 0x01, 192, 168, 1, 10]);
+        assert_eq!(avp.length(), encoded.len() as u32);
+    }
+
+    #[test]
+    fn test_encode_ipv6() {
+        let avp = Address::ipv6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(&encoded[..4], &[0x00, 0x02, 0x20, 0x01]);
+        assert_eq!(avp.length(), encoded.len() as u32);
+        assert_eq!(avp.to_string(), "2001:db8::1");
+    }
+
+    #[test]
+    fn test_encode_decode_e164() {
+        let avp = Address::from_e164(OctetString::new(b"6512345678".to_vec()));
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(&encoded[..2], &[0x00, 0x08]);
+        assert_eq!(avp.length(), encoded.len() as u32);
+
+        let mut cursor = Cursor::new(&encoded);
+        let avp = Address::decode_from(&mut cursor, encoded.len()).unwrap();
+        match avp.value() {
+            Value::E164(octet) => assert_eq!(octet.value(), b"6512345678"),
+            _ => panic!("Expected an E164 address"),
+        }
+    }
+
+    #[test]
+    fn test_e164_between_avps() {
+        use crate::avp;
+        use crate::avp::flags::M;
+        use crate::avp::{Avp, UTF8String, Unsigned32};
+        use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+
+        let mut message = DiameterMessage::new(
+            CommandCode::CapabilitiesExchange,
+            ApplicationId::Common,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        // 11 digits, so the AVP needs padding
+        message.add_avp(avp!(
+            257,
+            None,
+            M,
+            Address::from_e164(OctetString::new(b"16505551234".to_vec()))
+        ));
+        message.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes.len() as u32, message.wire_length());
+
+        let message = DiameterMessage::from_bytes(&bytes).unwrap();
+        let address = message.get_avp(257).unwrap().get_address().unwrap();
+        assert_eq!(address.length(), 13);
+        match address.value() {
+            Value::E164(octet) => assert_eq!(octet.value(), b"16505551234"),
+            _ => panic!("Expected an E164 address"),
+        }
+        assert_eq!(
+            message.get_avp(268).unwrap().get_unsigned32().unwrap(),
+            2001
+        );
+    }
+
+    #[test]
+    fn test_normalized() {
+        let mapped = Address::from_ipv6("::ffff:192.168.1.10".parse().unwrap());
+        assert_eq!(
+            mapped.normalized().ip(),
+            Some("192.168.1.10".parse().unwrap())
+        );
+
+        let ipv6 = Address::from_ipv6("2001:db8::1".parse().unwrap());
+        assert_eq!(ipv6.normalized().ip(), Some("2001:db8::1".parse().unwrap()));
+
+        let ipv4 = Address::ipv4(10, 0, 0, 1);
+        assert_eq!(ipv4.normalized().ip(), Some("10.0.0.1".parse().unwrap()));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
 use std::fmt;
 use std::io::Read;
 use std::io::Write;
@@ -11,6 +11,17 @@ impl Enumerated {
         Enumerated(value)
     }
 
+    /// Creates a new `Enumerated`, failing if `value` is not one of `allowed`.
+    pub fn new_checked(value: i32, allowed: &[i32]) -> Result<Enumerated> {
+        if !allowed.contains(&value) {
+            return Err(Error::ValidationError(format!(
+                "invalid enumerated value: {}",
+                value
+            )));
+        }
+        Ok(Enumerated(value))
+    }
+
     pub fn value(&self) -> i32 {
         self.0
     }
@@ -52,4 +63,15 @@ mod tests {
         let avp = Enumerated::decode_from(&mut cursor).unwrap();
         assert_eq!(avp.0, -1234567890);
     }
+
+    #[test]
+    fn test_new_checked() {
+        let avp = Enumerated::new_checked(2, &[0, 1, 2]).unwrap();
+        assert_eq!(avp.value(), 2);
+
+        match Enumerated::new_checked(3, &[0, 1, 2]) {
+            Err(Error::ValidationError(msg)) => assert_eq!(msg, "invalid enumerated value: 3"),
+            _ => panic!("Expected a ValidationError"),
+        }
+    }
 }
@@ -44,6 +44,7 @@ mod tests {
     use std::io::Cursor;
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_encode_decode() {
         let avp = Float32::new(-3.142);
         let mut encoded = Vec::new();
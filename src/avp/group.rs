@@ -1,5 +1,6 @@
-use crate::avp::Avp;
+use crate::avp::{Avp, DecodeOptions};
 use crate::error::{Error, Result};
+use bytes::Bytes;
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
@@ -17,13 +18,31 @@ impl Grouped {
     }
 
     pub fn decode_from<R: Read + Seek>(reader: &mut R, len: usize) -> Result<Grouped> {
+        Grouped::decode_from_with(reader, len, &DecodeOptions::default())
+    }
+
+    pub fn decode_from_with<R: Read + Seek>(
+        reader: &mut R,
+        len: usize,
+        options: &DecodeOptions,
+    ) -> Result<Grouped> {
+        Grouped::decode_inner(reader, len, options, None)
+    }
+
+    pub(crate) fn decode_inner<R: Read + Seek>(
+        reader: &mut R,
+        len: usize,
+        options: &DecodeOptions,
+        shared: Option<&Bytes>,
+    ) -> Result<Grouped> {
         let mut avps = Vec::new();
 
         let mut offset = 0;
         while offset < len {
-            let avp = Avp::decode_from(reader)?;
-            offset += avp.get_length() as usize;
-            offset += avp.get_padding() as usize;
+            let remaining = (len - offset) as u32;
+            let avp = Avp::decode_inner(reader, options, shared, Some(remaining))?;
+            // The declared length ends the group, even inside the last AVP's padding
+            offset += (avp.get_length() + avp.get_padding() as u32).min(remaining) as usize;
             avps.push(avp);
         }
 
@@ -53,7 +72,7 @@ impl Grouped {
 
     pub fn fmt(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
         for avp in &self.0 {
-            write!(f, "\n")?;
+            writeln!(f)?;
             avp.fmt(f, depth + 1)?;
         }
         Ok(())
@@ -71,6 +90,8 @@ mod tests {
     use super::*;
     use crate::avp;
     use crate::avp::enumerated::Enumerated;
+    use crate::avp::flags::M;
+    use crate::avp::integer32::Integer32;
     use crate::avp::unsigned32::Unsigned32;
     use crate::avp::AvpValue;
 
@@ -98,4 +119,42 @@ mod tests {
             _ => panic!("invalid value, expected Unsigned32"),
         }
     }
+
+    #[test]
+    fn test_encode_decode_vendor_children() {
+        // Service-Information (3GPP) with a base and a 3GPP child
+        let avp = avp!(
+            873,
+            Some(10415),
+            M,
+            Grouped::new(vec![
+                avp!(415, None, M, Unsigned32::new(1000)),
+                avp!(571, Some(10415), 0, Integer32::new(-8)),
+            ])
+        );
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+
+        let mut cursor = std::io::Cursor::new(&encoded);
+        let decoded = Avp::decode_from(&mut cursor).unwrap();
+        assert_eq!(decoded.get_vendor_id(), Some(10415));
+
+        let children = decoded.get_grouped().unwrap().avps();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].get_code(), 415);
+        assert_eq!(children[0].get_vendor_id(), None);
+        assert!(children[0].get_flags().mandatory);
+        assert!(!children[0].get_flags().vendor);
+        assert_eq!(children[0].get_unsigned32(), Some(1000));
+        assert_eq!(children[1].get_code(), 571);
+        assert_eq!(children[1].get_vendor_id(), Some(10415));
+        assert!(children[1].get_flags().vendor);
+        assert!(!children[1].get_flags().mandatory);
+        assert_eq!(children[1].get_length(), 16);
+        assert_eq!(children[1].get_integer32(), Some(-8));
+
+        let mut reencoded = Vec::new();
+        decoded.encode_to(&mut reencoded).unwrap();
+        assert_eq!(reencoded, encoded);
+    }
 }
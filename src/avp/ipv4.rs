@@ -12,6 +12,10 @@ impl IPv4 {
         IPv4(value)
     }
 
+    pub fn value(&self) -> Ipv4Addr {
+        self.0
+    }
+
     pub fn decode_from<R: Read>(reader: &mut R) -> Result<IPv4> {
         let mut b = [0; 4];
         reader.read_exact(&mut b)?;
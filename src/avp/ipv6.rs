@@ -12,6 +12,10 @@ impl IPv6 {
         IPv6(value)
     }
 
+    pub fn value(&self) -> Ipv6Addr {
+        self.0
+    }
+
     pub fn decode_from<R: Read>(reader: &mut R) -> Result<IPv6> {
         let mut b = [0; 16];
         reader.read_exact(&mut b)?;
@@ -43,14 +43,19 @@ pub mod unsigned32;
 pub mod unsigned64;
 pub mod uri;
 pub mod utf8string;
+pub mod visitor;
 
 use crate::dictionary;
 use crate::error::{Error, Result};
+use bytes::Bytes;
 use core::fmt;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
+use std::sync::OnceLock;
 
 pub use crate::avp::address::Address;
 pub use crate::avp::enumerated::Enumerated;
@@ -68,6 +73,7 @@ pub use crate::avp::unsigned32::Unsigned32;
 pub use crate::avp::unsigned64::Unsigned64;
 pub use crate::avp::uri::DiameterURI;
 pub use crate::avp::utf8string::UTF8String;
+pub use crate::avp::visitor::Visitor;
 
 pub mod flags {
     pub const V: u8 = 0x80;
@@ -75,11 +81,35 @@ pub mod flags {
     pub const P: u8 = 0x20;
 }
 
+/// Codes of the base protocol AVPs shared by the applications, RFC 6733,
+/// section 4.5.
+pub mod codes {
+    pub const HOST_IP_ADDRESS: u32 = 257;
+    pub const AUTH_APPLICATION_ID: u32 = 258;
+    pub const ACCT_APPLICATION_ID: u32 = 259;
+    pub const VENDOR_SPECIFIC_APPLICATION_ID: u32 = 260;
+    pub const SESSION_ID: u32 = 263;
+    pub const ORIGIN_HOST: u32 = 264;
+    pub const VENDOR_ID: u32 = 266;
+    pub const RESULT_CODE: u32 = 268;
+    pub const PRODUCT_NAME: u32 = 269;
+    pub const ORIGIN_STATE_ID: u32 = 278;
+    pub const FAILED_AVP: u32 = 279;
+    pub const DESTINATION_REALM: u32 = 283;
+    pub const DESTINATION_HOST: u32 = 293;
+    pub const ORIGIN_REALM: u32 = 296;
+    pub const EXPERIMENTAL_RESULT: u32 = 297;
+    pub const EXPERIMENTAL_RESULT_CODE: u32 = 298;
+}
+
 #[derive(Debug, Clone)]
 pub struct Avp {
     header: AvpHeader,
     value: AvpValue,
     padding: u8,
+    // The payload bytes, kept when decoded from a shared buffer and
+    // otherwise encoded on first use by `raw_value`
+    raw: OnceLock<Bytes>,
 }
 
 #[derive(Debug, Clone)]
@@ -90,13 +120,65 @@ pub struct AvpHeader {
     vendor_id: Option<u32>,
 }
 
-#[derive(Debug, Clone)]
+/// The V, M and P flags of an AVP.
+///
+/// Can be passed to `avp!` in place of the flag bits, e.g.
+/// `avp!(1, Some(10415), AvpFlags::new().vendor().mandatory(), value)`.
+/// `Avp::new` always sets the V flag from the vendor id, so `vendor()` only
+/// documents intent.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AvpFlags {
     pub vendor: bool,
     pub mandatory: bool,
     pub private: bool,
 }
 
+impl AvpFlags {
+    /// Returns flags with none of V, M and P set.
+    pub fn new() -> AvpFlags {
+        AvpFlags::default()
+    }
+
+    /// Sets the V (vendor-specific) flag.
+    pub fn vendor(mut self) -> AvpFlags {
+        self.vendor = true;
+        self
+    }
+
+    /// Sets the M (mandatory) flag.
+    pub fn mandatory(mut self) -> AvpFlags {
+        self.mandatory = true;
+        self
+    }
+
+    /// Sets the P (private, end-to-end security) flag.
+    pub fn private(mut self) -> AvpFlags {
+        self.private = true;
+        self
+    }
+
+    /// Returns the flags as the bits of the AVP header flags byte.
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+        if self.vendor {
+            bits |= flags::V;
+        }
+        if self.mandatory {
+            bits |= flags::M;
+        }
+        if self.private {
+            bits |= flags::P;
+        }
+        bits
+    }
+}
+
+impl From<AvpFlags> for u8 {
+    fn from(flags: AvpFlags) -> u8 {
+        flags.bits()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AvpType {
     Unknown,
@@ -136,6 +218,40 @@ pub enum AvpValue {
     Unsigned32(Unsigned32),
     Unsigned64(Unsigned64),
     UTF8String(UTF8String),
+    Raw(OctetString),
+}
+
+/// Options controlling how AVPs are decoded.
+///
+/// In strict mode (the default) AVPs that are not in the dictionary fail to
+/// decode. In lenient mode they are kept as `AvpValue::Raw` and re-encoded
+/// byte-for-byte, so relays and proxies can pass them through untouched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeOptions {
+    pub strict: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions { strict: true }
+    }
+}
+
+/// Enumerates the Subscription-Id-Type (450) values defined in RFC 4006.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+pub enum SubscriptionIdType {
+    EndUserE164 = 0,
+    EndUserImsi = 1,
+    EndUserSipUri = 2,
+    EndUserNai = 3,
+    EndUserPrivate = 4,
+}
+
+impl SubscriptionIdType {
+    pub fn from_i32(value: i32) -> Option<SubscriptionIdType> {
+        FromPrimitive::from_i32(value)
+    }
 }
 
 impl fmt::Display for AvpValue {
@@ -163,6 +279,7 @@ impl AvpValue {
             AvpValue::DiameterURI(avp) => avp.length(),
             AvpValue::Time(avp) => avp.length(),
             AvpValue::Grouped(avp) => avp.length(),
+            AvpValue::Raw(avp) => avp.length(),
         }
     }
 
@@ -184,6 +301,7 @@ impl AvpValue {
             AvpValue::DiameterURI(_) => "DiameterURI",
             AvpValue::Time(_) => "Time",
             AvpValue::Grouped(_) => "Grouped",
+            AvpValue::Raw(_) => "Raw",
         }
     }
 
@@ -205,6 +323,7 @@ impl AvpValue {
             AvpValue::DiameterURI(avp) => write!(f, "{}", avp),
             AvpValue::Time(avp) => write!(f, "{}", avp),
             AvpValue::Grouped(avp) => avp.fmt(f, depth),
+            AvpValue::Raw(avp) => write!(f, "{}", avp),
         }
     }
 }
@@ -306,6 +425,18 @@ impl From<Grouped> for AvpValue {
 }
 
 impl AvpHeader {
+    pub fn get_code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn get_vendor_id(&self) -> Option<u32> {
+        self.vendor_id
+    }
+
+    pub fn get_length(&self) -> u32 {
+        self.length
+    }
+
     pub fn decode_from<R: Read>(reader: &mut R) -> Result<AvpHeader> {
         let mut b = [0; 8];
         reader.read_exact(&mut b)?;
@@ -320,6 +451,14 @@ impl AvpHeader {
 
         let length = u32::from_be_bytes([0, b[5], b[6], b[7]]);
 
+        let header_length = if flags.vendor { 12 } else { 8 };
+        if length < header_length {
+            return Err(Error::DecodeError(format!(
+                "invalid length {} for AVP {}, shorter than its {} byte header",
+                length, code, header_length
+            )));
+        }
+
         let vendor_id = if flags.vendor {
             let mut b = [0; 4];
             reader.read_exact(&mut b)?;
@@ -373,18 +512,19 @@ impl Avp {
         let header = AvpHeader {
             code,
             flags: AvpFlags {
-                vendor: if vendor_id.is_some() { true } else { false },
+                vendor: vendor_id.is_some(),
                 mandatory: (flags & flags::M) != 0,
                 private: (flags & flags::P) != 0,
             },
             length: header_length + value.length(),
             vendor_id,
         };
-        return Avp {
+        Avp {
             header,
             value,
             padding,
-        };
+            raw: OnceLock::new(),
+        }
     }
 
     pub fn from_name(avp_name: &str, value: AvpValue) -> Result<Avp> {
@@ -397,6 +537,132 @@ impl Avp {
         Ok(Avp::new(avp_def.code, avp_def.vendor_id, flags, value))
     }
 
+    /// Builds an Experimental-Result (297) grouped AVP containing
+    /// Vendor-Id (266) and Experimental-Result-Code (298).
+    pub fn experimental_result(vendor_id: u32, code: u32) -> Avp {
+        Avp::new(
+            codes::EXPERIMENTAL_RESULT,
+            None,
+            flags::M,
+            Grouped::new(vec![
+                Avp::new(
+                    codes::VENDOR_ID,
+                    None,
+                    flags::M,
+                    Unsigned32::new(vendor_id).into(),
+                ),
+                Avp::new(
+                    codes::EXPERIMENTAL_RESULT_CODE,
+                    None,
+                    flags::M,
+                    Unsigned32::new(code).into(),
+                ),
+            ])
+            .into(),
+        )
+    }
+
+    /// Returns the Vendor-Id and Experimental-Result-Code of an
+    /// Experimental-Result grouped AVP.
+    pub fn get_experimental_result(&self) -> Option<(u32, u32)> {
+        if self.get_code() != codes::EXPERIMENTAL_RESULT {
+            return None;
+        }
+        let avps = self.get_grouped()?.avps();
+        let child = |code| {
+            avps.iter()
+                .find(|avp| avp.get_code() == code)
+                .and_then(|avp| avp.get_unsigned32())
+        };
+        Some((
+            child(codes::VENDOR_ID)?,
+            child(codes::EXPERIMENTAL_RESULT_CODE)?,
+        ))
+    }
+
+    /// Builds a Vendor-Specific-Application-Id (260) grouped AVP containing
+    /// Vendor-Id (266) and Auth-Application-Id (258).
+    pub fn vendor_specific_application_id(vendor_id: u32, auth_application_id: u32) -> Avp {
+        Avp::new(
+            codes::VENDOR_SPECIFIC_APPLICATION_ID,
+            None,
+            flags::M,
+            Grouped::new(vec![
+                Avp::new(
+                    codes::VENDOR_ID,
+                    None,
+                    flags::M,
+                    Unsigned32::new(vendor_id).into(),
+                ),
+                Avp::new(
+                    codes::AUTH_APPLICATION_ID,
+                    None,
+                    flags::M,
+                    Unsigned32::new(auth_application_id).into(),
+                ),
+            ])
+            .into(),
+        )
+    }
+
+    /// Returns the Vendor-Id and application id of a Vendor-Specific-Application-Id
+    /// grouped AVP. The application id is the Auth-Application-Id (258), or the
+    /// Acct-Application-Id (259) if no Auth-Application-Id is present.
+    pub fn get_vendor_specific_application_id(&self) -> Option<(u32, u32)> {
+        if self.get_code() != codes::VENDOR_SPECIFIC_APPLICATION_ID {
+            return None;
+        }
+        let avps = self.get_grouped()?.avps();
+        let child = |code| {
+            avps.iter()
+                .find(|avp| avp.get_code() == code)
+                .and_then(|avp| avp.get_unsigned32())
+        };
+        Some((
+            child(codes::VENDOR_ID)?,
+            child(codes::AUTH_APPLICATION_ID).or_else(|| child(codes::ACCT_APPLICATION_ID))?,
+        ))
+    }
+
+    /// Builds a Subscription-Id (443) grouped AVP containing
+    /// Subscription-Id-Type (450) and Subscription-Id-Data (444).
+    pub fn subscription_id(id_type: SubscriptionIdType, data: &str) -> Avp {
+        Avp::new(
+            443,
+            None,
+            flags::M,
+            Grouped::new(vec![
+                Avp::new(450, None, flags::M, Enumerated::new(id_type as i32).into()),
+                Avp::new(444, None, flags::M, UTF8String::new(data).into()),
+            ])
+            .into(),
+        )
+    }
+
+    /// Returns the Subscription-Id-Type and Subscription-Id-Data of a
+    /// Subscription-Id grouped AVP.
+    pub fn get_subscription_id(&self) -> Option<(SubscriptionIdType, &str)> {
+        if self.get_code() != 443 {
+            return None;
+        }
+        let avps = self.get_grouped()?.avps();
+        let child = |code| avps.iter().find(|avp| avp.get_code() == code);
+        let id_type = SubscriptionIdType::from_i32(child(450)?.get_enumerated()?.value())?;
+        let data = child(444)?.get_utf8string()?.value();
+        Some((id_type, data))
+    }
+
+    fn decode_octets<R: Read + Seek>(
+        reader: &mut R,
+        len: u32,
+        shared: Option<&Bytes>,
+    ) -> Result<OctetString> {
+        match shared {
+            Some(shared) => OctetString::decode_shared(reader, len as usize, shared),
+            None => OctetString::decode_from(reader, len as usize),
+        }
+    }
+
     pub fn get_code(&self) -> u32 {
         self.header.code
     }
@@ -413,6 +679,23 @@ impl Avp {
         self.header.length
     }
 
+    /// Returns the payload bytes of the AVP, without header and padding.
+    ///
+    /// For an AVP decoded from a shared buffer, e.g. by the codec or
+    /// `DiameterMessage::decode_from_shared`, these are the bytes received.
+    /// Otherwise they are the encoded value, which only differs from what
+    /// was received in the padding between the AVPs of a group. Empty if
+    /// the value cannot be encoded.
+    pub fn raw_value(&self) -> &[u8] {
+        self.raw.get_or_init(|| {
+            let mut raw = Vec::with_capacity(self.value.length() as usize);
+            match self.encode_value(&mut raw) {
+                Ok(()) => Bytes::from(raw),
+                Err(_) => Bytes::new(),
+            }
+        })
+    }
+
     pub fn get_padding(&self) -> u8 {
         self.padding
     }
@@ -421,17 +704,133 @@ impl Avp {
         &self.value
     }
 
+    /// Validates the AVP value against its definition in the given dictionary.
+    ///
+    /// Enumerated values must be one of the items listed in the dictionary;
+    /// grouped AVPs are validated recursively.
+    pub fn validate(&self, dict: &dictionary::Dictionary) -> Result<()> {
+        match &self.value {
+            AvpValue::Enumerated(avp) => {
+                if let Some(def) = dict.get_avp(self.get_code(), self.get_vendor_id()) {
+                    if !def.allowed_values.is_empty() && !def.allowed_values.contains(&avp.value())
+                    {
+                        return Err(Error::ValidationError(format!(
+                            "invalid value {} for enumerated AVP {}",
+                            avp.value(),
+                            def.name
+                        )));
+                    }
+                }
+                Ok(())
+            }
+            AvpValue::Grouped(avp) => avp.avps().iter().try_for_each(|avp| avp.validate(dict)),
+            _ => Ok(()),
+        }
+    }
+
     pub fn decode_from<R: Read + Seek>(reader: &mut R) -> Result<Avp> {
+        Avp::decode_from_with(reader, &DecodeOptions::default())
+    }
+
+    /// Decodes an AVP using the given `DecodeOptions`.
+    pub fn decode_from_with<R: Read + Seek>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<Avp> {
+        Avp::decode_inner(reader, options, None, None)
+    }
+
+    // Decodes an AVP. When `shared` is the buffer `reader` reads from,
+    // OctetString and raw payloads are sliced from it instead of copied.
+    // `remaining` is the number of bytes left in the enclosing message or
+    // grouped AVP, which the AVP must fit in.
+    pub(crate) fn decode_inner<R: Read + Seek>(
+        reader: &mut R,
+        options: &DecodeOptions,
+        shared: Option<&Bytes>,
+        remaining: Option<u32>,
+    ) -> Result<Avp> {
+        let offset = reader.stream_position()?;
         let header = AvpHeader::decode_from(reader)?;
 
+        if let Some(remaining) = remaining {
+            if header.length > remaining {
+                return Err(Error::DecodeError(format!(
+                    "invalid length {} for AVP {}, exceeds the remaining {} bytes",
+                    header.length, header.code, remaining
+                )));
+            }
+        }
+
         let header_length = if header.flags.vendor { 12 } else { 8 };
         let value_length = header.length - header_length;
 
         let dict = dictionary::DEFAULT_DICT.read().unwrap();
-        let avp_type = dict
-            .get_avp_type(header.code, header.vendor_id)
-            .unwrap_or(&AvpType::Unknown);
+        let avp_type = match dict.get_avp_type(header.code, header.vendor_id) {
+            Some(AvpType::Unknown) if options.strict => {
+                return Err(Error::UnsupportedAvpType(header.code))
+            }
+            Some(avp_type) => avp_type,
+            None if options.strict => return Err(Error::UnknownAvpCode(header.code)),
+            None => &AvpType::Unknown,
+        };
 
+        if let Some(expected) = Avp::fixed_length(avp_type) {
+            if value_length != expected {
+                return Err(Error::InvalidAvpLength {
+                    code: header.code,
+                    expected,
+                    got: value_length,
+                });
+            }
+        }
+
+        let value =
+            Avp::decode_value(reader, avp_type, value_length, options, shared).map_err(|e| {
+                Error::AvpDecode {
+                    code: header.code,
+                    vendor_id: header.vendor_id,
+                    offset,
+                    source: Box::new(e),
+                }
+            })?;
+
+        // Skip padding. A final AVP sent without its padding has only the
+        // bytes up to the end of the enclosing length to skip.
+        let padding = Avp::pad_to_32_bits(value_length);
+        let skip = match remaining {
+            Some(remaining) => (padding as u32).min(remaining - header.length),
+            None => padding as u32,
+        };
+        if skip > 0 {
+            reader.seek(SeekFrom::Current(skip as i64))?;
+        }
+
+        // The payload is a slice of the shared buffer, so keeping it is free
+        let raw = OnceLock::new();
+        if let Some(shared) = shared {
+            let start = (offset + header_length as u64) as usize;
+            let end = start + value_length as usize;
+            if end <= shared.len() {
+                let _ = raw.set(shared.slice(start..end));
+            }
+        }
+
+        Ok(Avp {
+            header,
+            value,
+            padding,
+            raw,
+        })
+    }
+
+    fn decode_value<R: Read + Seek>(
+        reader: &mut R,
+        avp_type: &AvpType,
+        value_length: u32,
+        options: &DecodeOptions,
+        shared: Option<&Bytes>,
+    ) -> Result<AvpValue> {
         let value = match avp_type {
             AvpType::Address => {
                 AvpValue::Address(Address::decode_from(reader, value_length as usize)?)
@@ -449,7 +848,7 @@ impl Avp {
                 AvpValue::UTF8String(UTF8String::decode_from(reader, value_length as usize)?)
             }
             AvpType::OctetString => {
-                AvpValue::OctetString(OctetString::decode_from(reader, value_length as usize)?)
+                AvpValue::OctetString(Avp::decode_octets(reader, value_length, shared)?)
             }
             AvpType::Identity => {
                 AvpValue::Identity(Identity::decode_from(reader, value_length as usize)?)
@@ -458,29 +857,46 @@ impl Avp {
                 AvpValue::DiameterURI(DiameterURI::decode_from(reader, value_length as usize)?)
             }
             AvpType::Time => AvpValue::Time(Time::decode_from(reader)?),
-            AvpType::Grouped => {
-                AvpValue::Grouped(Grouped::decode_from(reader, value_length as usize)?)
-            }
-            AvpType::Unknown => return Err(Error::UnknownAvpCode(header.code)),
+            AvpType::Grouped => AvpValue::Grouped(Grouped::decode_inner(
+                reader,
+                value_length as usize,
+                options,
+                shared,
+            )?),
+            AvpType::Unknown => AvpValue::Raw(Avp::decode_octets(reader, value_length, shared)?),
         };
+        Ok(value)
+    }
 
-        // Skip padding
-        let padding = Avp::pad_to_32_bits(value_length);
-        if padding > 0 {
-            reader.seek(SeekFrom::Current(padding as i64))?;
+    // Value length of AVP types with a fixed size.
+    fn fixed_length(avp_type: &AvpType) -> Option<u32> {
+        match avp_type {
+            AvpType::AddressIPv4
+            | AvpType::Enumerated
+            | AvpType::Float32
+            | AvpType::Integer32
+            | AvpType::Unsigned32
+            | AvpType::Time => Some(4),
+            AvpType::Float64 | AvpType::Integer64 | AvpType::Unsigned64 => Some(8),
+            AvpType::AddressIPv6 => Some(16),
+            _ => None,
         }
-
-        return Ok(Avp {
-            header,
-            value,
-            padding,
-        });
     }
 
     pub fn encode_to<W: Write>(&self, writer: &mut W) -> Result<()> {
         self.header.encode_to(writer)?;
+        self.encode_value(writer)?;
+
+        // Padding
+        for _ in 0..self.padding {
+            writer.write_all(&[0])?;
+        }
+
+        Ok(())
+    }
 
-        let _ = match &self.value {
+    fn encode_value<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match &self.value {
             AvpValue::Address(avp) => avp.encode_to(writer),
             AvpValue::AddressIPv4(avp) => avp.encode_to(writer),
             AvpValue::AddressIPv6(avp) => avp.encode_to(writer),
@@ -497,14 +913,21 @@ impl Avp {
             AvpValue::DiameterURI(avp) => avp.encode_to(writer),
             AvpValue::Time(avp) => avp.encode_to(writer),
             AvpValue::Grouped(avp) => avp.encode_to(writer),
-        };
-
-        // Padding
-        for _ in 0..self.padding {
-            writer.write_all(&[0])?;
+            AvpValue::Raw(avp) => avp.encode_to(writer),
         }
+    }
 
-        Ok(())
+    /// Returns true if both AVPs encode to the same bytes, flag bits included.
+    ///
+    /// Unlike `==`, which compares code, vendor and value only, this treats
+    /// AVPs that differ in e.g. the M or P flag as different.
+    pub fn value_eq(&self, other: &Avp) -> bool {
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        match (self.encode_to(&mut a), other.encode_to(&mut b)) {
+            (Ok(()), Ok(())) => a == b,
+            _ => false,
+        }
     }
 
     fn pad_to_32_bits(length: u32) -> u8 {
@@ -623,13 +1046,53 @@ impl Avp {
         }
     }
 
+    pub fn get_raw(&self) -> Option<&OctetString> {
+        match &self.value {
+            AvpValue::Raw(avp) => Some(avp),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an Unsigned32 AVP, or `None` on type mismatch.
+    pub fn as_u32(&self) -> Option<u32> {
+        self.get_unsigned32()
+    }
+
+    /// Returns the value of an Integer32 or Enumerated AVP, or `None` on type mismatch.
+    pub fn as_i32(&self) -> Option<i32> {
+        match &self.value {
+            AvpValue::Integer32(avp) => Some(avp.value()),
+            AvpValue::Enumerated(avp) => Some(avp.value()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a UTF8String or DiameterIdentity AVP, or `None` on type mismatch.
+    pub fn as_string(&self) -> Option<&str> {
+        match &self.value {
+            AvpValue::UTF8String(avp) => Some(avp.value()),
+            AvpValue::Identity(avp) => Some(avp.value()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an Address AVP, or `None` on type mismatch.
+    pub fn as_address(&self) -> Option<&Address> {
+        self.get_address()
+    }
+
+    /// Returns the value of a Grouped AVP, or `None` on type mismatch.
+    pub fn as_grouped(&self) -> Option<&Grouped> {
+        self.get_grouped()
+    }
+
     pub fn fmt(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
-        let indent = "  ".repeat(depth.max(0));
+        let indent = "  ".repeat(depth);
 
         let dict = dictionary::DEFAULT_DICT.read().unwrap();
 
         let avp_name = dict
-            .get_avp_name(self.get_code() as u32, self.get_vendor_id())
+            .get_avp_name(self.get_code(), self.get_vendor_id())
             .unwrap_or("Unknown");
 
         let avp_name = format!("{}{}", indent, avp_name);
@@ -663,16 +1126,40 @@ fn get_bool_unicode(v: bool) -> &'static str {
     }
 }
 
+/// AVPs are equal if they have the same code, vendor and value. Flag bits are
+/// ignored, including those of the AVPs within a grouped value; use
+/// `Avp::value_eq` for a bit-exact comparison.
+impl PartialEq for Avp {
+    fn eq(&self, other: &Avp) -> bool {
+        if self.get_code() != other.get_code() || self.get_vendor_id() != other.get_vendor_id() {
+            return false;
+        }
+        match (&self.value, &other.value) {
+            (AvpValue::Grouped(a), AvpValue::Grouped(b)) => a.avps() == b.avps(),
+            (a, b) if std::mem::discriminant(a) == std::mem::discriminant(b) => {
+                let mut a = Vec::new();
+                let mut b = Vec::new();
+                self.encode_value(&mut a).is_ok() && other.encode_value(&mut b).is_ok() && a == b
+            }
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Avp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.fmt(f, 0)
     }
 }
 
+/// Creates an `Avp` from its code, vendor id, flags and value, or from its
+/// dictionary name and value.
+///
+/// The flags are either the header bits, e.g. `M`, or an `AvpFlags`.
 #[macro_export]
 macro_rules! avp {
     ($code:expr, $vendor_id:expr, $flags:expr, $value:expr $(,)?) => {
-        Avp::new($code, $vendor_id, $flags, $value.into())
+        Avp::new($code, $vendor_id, $flags.into(), $value.into())
     };
     ($name:expr, $value:expr $(,)?) => {
         Avp::from_name($name, $value.into())
@@ -682,6 +1169,7 @@ macro_rules! avp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::avp::flags::M;
     use std::io::Cursor;
 
     #[test]
@@ -696,9 +1184,9 @@ mod tests {
 
         assert_eq!(header.code, 100);
         assert_eq!(header.length, 12);
-        assert_eq!(header.flags.vendor, false);
-        assert_eq!(header.flags.mandatory, true);
-        assert_eq!(header.flags.private, false);
+        assert!(!header.flags.vendor);
+        assert!(header.flags.mandatory);
+        assert!(!header.flags.private);
         assert_eq!(header.vendor_id, None);
 
         let mut encoded = Vec::new();
@@ -719,13 +1207,340 @@ mod tests {
 
         assert_eq!(header.code, 100);
         assert_eq!(header.length, 12);
-        assert_eq!(header.flags.vendor, true);
-        assert_eq!(header.flags.mandatory, false);
-        assert_eq!(header.flags.private, false);
+        assert!(header.flags.vendor);
+        assert!(!header.flags.mandatory);
+        assert!(!header.flags.private);
         assert_eq!(header.vendor_id, Some(200));
 
         let mut encoded = Vec::new();
         header.encode_to(&mut encoded).unwrap();
         assert_eq!(encoded, data);
     }
+
+    #[test]
+    fn test_avp_flags() {
+        let avp = avp!(
+            1,
+            Some(10415),
+            AvpFlags::new().vendor().mandatory(),
+            UTF8String::new("value")
+        );
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded[4], 0xc0);
+        assert_eq!(avp.get_flags().bits(), flags::V | flags::M);
+
+        let avp = avp!(
+            263,
+            None,
+            AvpFlags::new().mandatory().private(),
+            Unsigned32::new(1)
+        );
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded[4], 0x60);
+
+        // The bit form still works
+        let avp = avp!(263, None, M, Unsigned32::new(1));
+        assert_eq!(avp.get_flags(), &AvpFlags::new().mandatory());
+    }
+
+    #[test]
+    fn test_raw_value() {
+        use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            crate::diameter::flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(
+            456,
+            None,
+            M,
+            Grouped::new(vec![
+                avp!(263, None, M, UTF8String::new("abc")),
+                avp!(432, None, M, Unsigned32::new(1000)),
+            ])
+        ));
+        let mut bytes = message.to_bytes().unwrap();
+        // A non-zero pad byte after the Session-Id inside the group
+        bytes[39] = 0xff;
+        let group_payload = bytes[28..].to_vec();
+
+        let shared = Bytes::from(bytes.clone());
+        let decoded =
+            DiameterMessage::decode_from_shared(&shared, &DecodeOptions::default()).unwrap();
+        let group = decoded.get_avp(456).unwrap();
+        assert_eq!(group.raw_value(), &group_payload[..]);
+        let children = group.get_grouped().unwrap().avps();
+        assert_eq!(children[0].raw_value(), b"abc");
+        assert_eq!(children[1].raw_value(), &1000u32.to_be_bytes());
+
+        // Without a shared buffer the payload is encoded from the value
+        let decoded = DiameterMessage::from_bytes(&bytes).unwrap();
+        let group = decoded.get_avp(456).unwrap();
+        assert_eq!(group.raw_value().len(), group_payload.len());
+        assert_eq!(group.raw_value()[11], 0);
+        assert_eq!(group.get_grouped().unwrap().avps()[0].raw_value(), b"abc");
+    }
+
+    #[test]
+    fn test_decode_unsigned32_invalid_length() {
+        let data = [
+            0x00, 0x00, 0x01, 0x9F, // code 415 (CC-Request-Number)
+            0x40, 0x00, 0x00, 0x0A, // flags, length 10
+            0x00, 0x00, 0x04, 0xB0, // value
+            0x00, 0x00, 0x00, 0x00, // padding
+        ];
+
+        let mut cursor = Cursor::new(&data);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::InvalidAvpLength {
+                code,
+                expected,
+                got,
+            }) => {
+                assert_eq!(code, 415);
+                assert_eq!(expected, 4);
+                assert_eq!(got, 2);
+            }
+            _ => panic!("Expected an InvalidAvpLength error"),
+        }
+    }
+
+    #[test]
+    fn test_decode_enumerated_invalid_length() {
+        let data = [
+            0x00, 0x00, 0x01, 0xA0, // code 416 (CC-Request-Type)
+            0x40, 0x00, 0x00, 0x0E, // flags, length 14
+            0x00, 0x00, 0x00, 0x00, // value
+            0x00, 0x01, 0x00, 0x00, // value, padding
+        ];
+
+        let mut cursor = Cursor::new(&data);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::InvalidAvpLength {
+                code,
+                expected,
+                got,
+            }) => {
+                assert_eq!(code, 416);
+                assert_eq!(expected, 4);
+                assert_eq!(got, 6);
+            }
+            _ => panic!("Expected an InvalidAvpLength error"),
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_avp_code() {
+        let data = [
+            0x00, 0x0F, 0x42, 0x3F, // code 999999
+            0x00, 0x00, 0x00, 0x0C, // flags, length 12
+            0x00, 0x00, 0x00, 0x01, // value
+        ];
+
+        let mut cursor = Cursor::new(&data);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::UnknownAvpCode(code)) => assert_eq!(code, 999999),
+            _ => panic!("Expected an UnknownAvpCode error"),
+        }
+    }
+
+    #[test]
+    fn test_decode_unsupported_avp_type() {
+        // The IPFilterRule type of Restriction-Filter-Rule is not supported
+        let data = [
+            0x00, 0x00, 0x01, 0xB6, // code 438 (Restriction-Filter-Rule)
+            0x40, 0x00, 0x00, 0x0C, // flags, length 12
+            0x00, 0x00, 0x00, 0x01, // value
+        ];
+
+        let mut cursor = Cursor::new(&data);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::UnsupportedAvpType(code)) => assert_eq!(code, 438),
+            _ => panic!("Expected an UnsupportedAvpType error"),
+        }
+    }
+
+    #[test]
+    fn test_typed_accessors() {
+        let avp = Avp::new(415, None, M, Unsigned32::new(1000).into());
+        assert_eq!(avp.as_u32(), Some(1000));
+        assert_eq!(avp.as_i32(), None);
+        assert_eq!(avp.as_string(), None);
+
+        let avp = Avp::new(416, None, M, Enumerated::new(1).into());
+        assert_eq!(avp.as_i32(), Some(1));
+        assert_eq!(avp.as_u32(), None);
+
+        let avp = Avp::new(264, None, M, Identity::new("host.example.com").into());
+        assert_eq!(avp.as_string(), Some("host.example.com"));
+        assert!(avp.as_address().is_none());
+
+        let avp = Avp::new(257, None, M, Address::ipv4(127, 0, 0, 1).into());
+        assert_eq!(avp.as_address().unwrap().to_string(), "127.0.0.1");
+        assert!(avp.as_grouped().is_none());
+
+        let avp = Avp::new(
+            456,
+            None,
+            M,
+            Grouped::new(vec![Avp::new(432, None, M, Unsigned32::new(1).into())]).into(),
+        );
+        assert_eq!(avp.as_grouped().unwrap().avps().len(), 1);
+        assert_eq!(avp.as_u32(), None);
+    }
+
+    #[test]
+    fn test_decode_lenient_unknown_avp() {
+        let data = [
+            0x00, 0x0F, 0x42, 0x3D, // code 999997
+            0xC0, 0x00, 0x00, 0x0F, // flags V M, length 15
+            0x00, 0x00, 0x28, 0xAF, // vendor_id 10415
+            0x01, 0x02, 0x03, 0x00, // value, padding
+        ];
+
+        let mut cursor = Cursor::new(&data);
+        assert!(Avp::decode_from(&mut cursor).is_err());
+
+        let mut cursor = Cursor::new(&data);
+        let avp = Avp::decode_from_with(&mut cursor, &DecodeOptions { strict: false }).unwrap();
+        assert_eq!(avp.get_code(), 999997);
+        assert_eq!(avp.get_vendor_id(), Some(10415));
+        assert!(avp.get_flags().mandatory);
+        assert_eq!(avp.get_raw().unwrap().value(), &[0x01, 0x02, 0x03]);
+
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_subscription_id_imsi() {
+        let avp = Avp::subscription_id(SubscriptionIdType::EndUserImsi, "001010123456789");
+
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        let mut cursor = Cursor::new(&encoded);
+        let avp = Avp::decode_from(&mut cursor).unwrap();
+
+        assert_eq!(avp.get_code(), 443);
+        assert_eq!(
+            avp.get_subscription_id(),
+            Some((SubscriptionIdType::EndUserImsi, "001010123456789"))
+        );
+        assert_eq!(SubscriptionIdType::from_i32(5), None);
+    }
+
+    #[test]
+    fn test_decode_length_shorter_than_header() {
+        let zero_length = [
+            0x00, 0x00, 0x01, 0x9F, // code 415
+            0x40, 0x00, 0x00, 0x00, // flags M, length 0
+        ];
+        let mut cursor = Cursor::new(&zero_length);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::DecodeError(msg)) => assert_eq!(
+                msg,
+                "invalid length 0 for AVP 415, shorter than its 8 byte header"
+            ),
+            _ => panic!("Expected a DecodeError"),
+        }
+
+        let vendor_without_room = [
+            0x00, 0x00, 0x01, 0x9F, // code 415
+            0xC0, 0x00, 0x00, 0x08, // flags V M, length 8
+            0x00, 0x00, 0x28, 0xAF, // vendor_id 10415
+        ];
+        let mut cursor = Cursor::new(&vendor_without_room);
+        assert!(matches!(
+            Avp::decode_from(&mut cursor),
+            Err(Error::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_grouped_child_exceeds_group() {
+        let data = [
+            0x00, 0x00, 0x01, 0xBB, // code 443 (Subscription-Id)
+            0x40, 0x00, 0x00, 0x14, // flags M, length 20
+            0x00, 0x00, 0x01, 0xC2, // code 450 (Subscription-Id-Type)
+            0x40, 0x00, 0x00, 0x40, // flags M, length 64, past the group
+            0x00, 0x00, 0x00, 0x01, // value
+        ];
+        let mut cursor = Cursor::new(&data);
+        match Avp::decode_from(&mut cursor) {
+            Err(Error::AvpDecode { code, source, .. }) => {
+                assert_eq!(code, 443);
+                assert!(matches!(*source, Error::DecodeError(_)));
+            }
+            _ => panic!("Expected an AvpDecode error"),
+        }
+    }
+
+    #[test]
+    fn test_vendor_specific_application_id() {
+        let avp = Avp::vendor_specific_application_id(10415, 16777238);
+
+        let mut encoded = Vec::new();
+        avp.encode_to(&mut encoded).unwrap();
+        let mut cursor = Cursor::new(&encoded);
+        let avp = Avp::decode_from(&mut cursor).unwrap();
+
+        assert_eq!(avp.get_code(), 260);
+        assert_eq!(
+            avp.get_vendor_specific_application_id(),
+            Some((10415, 16777238))
+        );
+
+        let acct = Avp::new(
+            260,
+            None,
+            M,
+            Grouped::new(vec![
+                Avp::new(266, None, M, Unsigned32::new(10415).into()),
+                Avp::new(259, None, M, Unsigned32::new(3).into()),
+            ])
+            .into(),
+        );
+        assert_eq!(acct.get_vendor_specific_application_id(), Some((10415, 3)));
+    }
+
+    #[test]
+    fn test_eq_ignores_flags() {
+        let mandatory = Avp::new(415, None, M, Unsigned32::new(1000).into());
+        let optional = Avp::new(415, None, 0, Unsigned32::new(1000).into());
+
+        assert_eq!(mandatory, optional);
+        assert!(!mandatory.value_eq(&optional));
+        assert!(mandatory.value_eq(&mandatory.clone()));
+
+        assert_ne!(
+            mandatory,
+            Avp::new(415, None, M, Unsigned32::new(1001).into())
+        );
+        assert_ne!(
+            mandatory,
+            Avp::new(416, None, M, Unsigned32::new(1000).into())
+        );
+        assert_ne!(
+            mandatory,
+            Avp::new(415, None, M, Enumerated::new(1000).into())
+        );
+
+        let grouped = |flags| {
+            Avp::new(
+                456,
+                None,
+                M,
+                Grouped::new(vec![Avp::new(432, None, flags, Unsigned32::new(1).into())]).into(),
+            )
+        };
+        assert_eq!(grouped(M), grouped(0));
+        assert!(!grouped(M).value_eq(&grouped(0)));
+    }
 }
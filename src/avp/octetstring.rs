@@ -1,24 +1,79 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use bytes::Bytes;
 use std::fmt;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
 
+/// OctetString AVP data.
+///
+/// The value is stored as `Bytes`, so AVPs decoded with
+/// `DiameterMessage::decode_from_shared` reference the message buffer
+/// instead of copying their payload.
 #[derive(Debug, Clone)]
-pub struct OctetString(Vec<u8>);
+pub struct OctetString(Bytes);
 
 impl OctetString {
     pub fn new(value: Vec<u8>) -> OctetString {
+        OctetString(Bytes::from(value))
+    }
+
+    /// Creates an `OctetString` from a shared buffer without copying it.
+    pub fn from_bytes(value: Bytes) -> OctetString {
         OctetString(value)
     }
 
+    /// Creates an `OctetString`, returning a `DecodeError` if `value` is
+    /// longer than `max_len` bytes.
+    pub fn with_max_len(value: Vec<u8>, max_len: usize) -> Result<OctetString> {
+        if value.len() > max_len {
+            return Err(Error::DecodeError(format!(
+                "OctetString length {} exceeds maximum of {}",
+                value.len(),
+                max_len
+            )));
+        }
+        Ok(OctetString(Bytes::from(value)))
+    }
+
     pub fn value(&self) -> &[u8] {
         &self.0
     }
 
+    /// Returns the shared buffer holding the value.
+    pub fn bytes(&self) -> &Bytes {
+        &self.0
+    }
+
+    /// Returns the value as a string slice, or a `DecodeError` if it is not valid UTF-8.
+    pub fn as_utf8(&self) -> Result<&str> {
+        std::str::from_utf8(&self.0)
+            .map_err(|e| Error::DecodeError(format!("OctetString is not valid UTF-8: {}", e)))
+    }
+
     pub fn decode_from<R: Read>(reader: &mut R, len: usize) -> Result<OctetString> {
         let mut b = vec![0u8; len];
         reader.read_exact(&mut b)?;
-        Ok(OctetString(b))
+        Ok(OctetString(Bytes::from(b)))
+    }
+
+    /// Decodes the value as a slice of `shared`, the buffer `reader` reads
+    /// from, instead of copying it. The reader is advanced past the value.
+    pub fn decode_shared<R: Read + Seek>(
+        reader: &mut R,
+        len: usize,
+        shared: &Bytes,
+    ) -> Result<OctetString> {
+        let start = reader.stream_position()? as usize;
+        let end = start + len;
+        if end > shared.len() {
+            return Err(Error::DecodeError(
+                "invalid octetstring avp, length exceeds buffer".into(),
+            ));
+        }
+        reader.seek(SeekFrom::Current(len as i64))?;
+        Ok(OctetString(shared.slice(start..end)))
     }
 
     pub fn encode_to<W: Write>(&self, writer: &mut W) -> Result<()> {
@@ -33,10 +88,8 @@ impl OctetString {
 
 impl fmt::Display for OctetString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (index, &byte) in self.0.iter().enumerate() {
-            if index > 0 {
-                write!(f, " ")?;
-            }
+        write!(f, "0x")?;
+        for byte in self.0.iter() {
             write!(f, "{:02x}", byte)?;
         }
         Ok(())
@@ -81,4 +134,42 @@ mod tests {
         let avp = OctetString::decode_from(&mut cursor, bytes.len()).unwrap();
         assert_eq!(avp.value(), bytes);
     }
+
+    #[test]
+    fn test_with_max_len() {
+        let avp = OctetString::with_max_len(vec![0x01, 0x02, 0x03], 3).unwrap();
+        assert_eq!(avp.value(), &[0x01, 0x02, 0x03]);
+
+        match OctetString::with_max_len(vec![0x01, 0x02, 0x03, 0x04], 3) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "OctetString length 4 exceeds maximum of 3")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+
+    #[test]
+    fn test_display_hex() {
+        let avp = OctetString::new(vec![0x0a, 0x1b, 0x00, 0xff]);
+        assert_eq!(avp.to_string(), "0x0a1b00ff");
+        assert!(avp.as_utf8().is_err());
+
+        let avp = OctetString::new(b"abc".to_vec());
+        assert_eq!(avp.to_string(), "0x616263");
+        assert_eq!(avp.as_utf8().unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_decode_shared() {
+        let shared = Bytes::from_static(b"\x00\x01Hello World");
+        let mut cursor = Cursor::new(&shared[..]);
+        cursor.set_position(2);
+        let avp = OctetString::decode_shared(&mut cursor, 11, &shared).unwrap();
+        assert_eq!(avp.value(), b"Hello World");
+        assert_eq!(avp.bytes().as_ptr(), shared[2..].as_ptr());
+        assert_eq!(cursor.position(), 13);
+
+        cursor.set_position(2);
+        assert!(OctetString::decode_shared(&mut cursor, 12, &shared).is_err());
+    }
 }
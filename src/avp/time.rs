@@ -67,7 +67,7 @@ mod tests {
     #[test]
     fn test_encode_decode() {
         let now: DateTime<Utc> = Utc.with_ymd_and_hms(2024, 1, 10, 10, 35, 58).unwrap();
-        let avp = Time::new(now.into());
+        let avp = Time::new(now);
         let mut encoded = Vec::new();
         avp.encode_to(&mut encoded).unwrap();
         let mut cursor = Cursor::new(&encoded);
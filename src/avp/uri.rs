@@ -1,45 +1,423 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
 use std::fmt;
 use std::io::Read;
 use std::io::Write;
 
 use crate::avp::OctetString;
 
+/// DiameterURI AVP, as defined in RFC 6733 section 4.3.1:
+/// ```text
+/// "aaa://" FQDN [ port ] [ transport ] [ protocol ]
+/// "aaas://" FQDN [ port ] [ transport ] [ protocol ]
+/// ```
+///
+/// The encoded bytes are kept as received, and are also parsed into their
+/// parts. Decoding fails on bytes that do not form a valid URI, while `new`
+/// keeps them unparsed. Parameters other than `transport` and
+/// `protocol`, and values of those this crate doesn't know, are kept as
+/// they are in `params`.
 #[derive(Debug, Clone)]
-pub struct DiameterURI(OctetString);
+pub struct DiameterURI {
+    value: OctetString,
+    parts: Option<Parts>,
+}
+
+#[derive(Debug, Clone)]
+struct Parts {
+    scheme: Scheme,
+    host: String,
+    port: Option<u16>,
+    transport: Option<Transport>,
+    protocol: Option<Protocol>,
+    params: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Aaa,
+    Aaas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Sctp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Diameter,
+    Radius,
+    TacacsPlus,
+}
 
 impl DiameterURI {
+    /// Creates a DiameterURI from its encoded bytes, which are parsed if
+    /// they form a valid URI and kept as they are otherwise.
     pub fn new(value: Vec<u8>) -> DiameterURI {
-        DiameterURI(OctetString::new(value))
+        let parts = std::str::from_utf8(&value)
+            .ok()
+            .and_then(|uri| Parts::parse(uri).ok());
+        DiameterURI {
+            value: OctetString::new(value),
+            parts,
+        }
     }
 
+    /// Creates a DiameterURI from a scheme and host, to which the `with_`
+    /// methods add a port and parameters.
+    pub fn from_parts(scheme: Scheme, host: &str) -> DiameterURI {
+        DiameterURI::from_parsed(Parts {
+            scheme,
+            host: host.into(),
+            port: None,
+            transport: None,
+            protocol: None,
+            params: Vec::new(),
+        })
+    }
+
+    /// Sets the port. Leaves a URI that could not be parsed unchanged, as
+    /// do the other `with_` methods.
+    pub fn with_port(self, port: u16) -> DiameterURI {
+        self.with_parts(|parts| parts.port = Some(port))
+    }
+
+    pub fn with_transport(self, transport: Transport) -> DiameterURI {
+        self.with_parts(|parts| parts.transport = Some(transport))
+    }
+
+    pub fn with_protocol(self, protocol: Protocol) -> DiameterURI {
+        self.with_parts(|parts| parts.protocol = Some(protocol))
+    }
+
+    fn from_parsed(parts: Parts) -> DiameterURI {
+        DiameterURI {
+            value: OctetString::new(parts.to_string().into_bytes()),
+            parts: Some(parts),
+        }
+    }
+
+    fn with_parts(self, f: impl FnOnce(&mut Parts)) -> DiameterURI {
+        match self.parts {
+            Some(mut parts) => {
+                f(&mut parts);
+                DiameterURI::from_parsed(parts)
+            }
+            None => self,
+        }
+    }
+
+    /// Parses a DiameterURI from its textual form, e.g. `aaa://host.example.com:3868;transport=tcp`.
+    ///
+    /// Unlike `new`, fails if the scheme, host or port is invalid.
+    pub fn parse(uri: &str) -> Result<DiameterURI> {
+        let parts = Parts::parse(uri)?;
+        Ok(DiameterURI {
+            value: OctetString::new(uri.as_bytes().to_vec()),
+            parts: Some(parts),
+        })
+    }
+
+    /// Returns the encoded bytes.
     pub fn value(&self) -> &[u8] {
-        self.0.value()
+        self.value.value()
+    }
+
+    /// Returns the scheme, or `None` if the URI could not be parsed, as do
+    /// the other accessors.
+    pub fn scheme(&self) -> Option<Scheme> {
+        self.parts.as_ref().map(|parts| parts.scheme)
+    }
+
+    /// Returns the host, without the brackets of an IPv6 address.
+    pub fn host(&self) -> Option<&str> {
+        self.parts.as_ref().map(|parts| parts.host.as_str())
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.parts.as_ref().and_then(|parts| parts.port)
     }
 
+    pub fn transport(&self) -> Option<Transport> {
+        self.parts.as_ref().and_then(|parts| parts.transport)
+    }
+
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.parts.as_ref().and_then(|parts| parts.protocol)
+    }
+
+    /// Returns the parameters that are not parsed, e.g. `["foo=bar"]`.
+    pub fn params(&self) -> &[String] {
+        self.parts
+            .as_ref()
+            .map(|parts| parts.params.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Decodes a DiameterURI, failing with `Error::DecodeError` if the bytes
+    /// are not a valid URI, e.g. one with a scheme other than `aaa` or `aaas`.
     pub fn decode_from<R: Read>(reader: &mut R, len: usize) -> Result<DiameterURI> {
-        let avp = OctetString::decode_from(reader, len)?;
-        Ok(DiameterURI(avp))
+        let mut b = vec![0u8; len];
+        reader.read_exact(&mut b)?;
+        let uri = std::str::from_utf8(&b)
+            .map_err(|e| Error::DecodeError(format!("Invalid DiameterURI: {}", e)))?;
+        let parts = Parts::parse(uri)?;
+        Ok(DiameterURI {
+            value: OctetString::new(b),
+            parts: Some(parts),
+        })
     }
 
     pub fn encode_to<W: Write>(&self, writer: &mut W) -> Result<()> {
-        self.0.encode_to(writer)?;
+        self.value.encode_to(writer)?;
         Ok(())
     }
 
     pub fn length(&self) -> u32 {
-        self.0.length()
+        self.value.length()
+    }
+}
+
+// The parts follow from the encoded bytes
+impl PartialEq for DiameterURI {
+    fn eq(&self, other: &DiameterURI) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl Parts {
+    fn parse(uri: &str) -> Result<Parts> {
+        let (scheme, rest) = match uri.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("aaa") => (Scheme::Aaa, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("aaas") => (Scheme::Aaas, rest),
+            _ => {
+                return Err(Error::DecodeError(format!(
+                    "Invalid DiameterURI scheme: {}",
+                    uri
+                )))
+            }
+        };
+
+        let mut params = rest.split(';');
+        let authority = params.next().unwrap_or_default();
+        let (host, port) = match authority.strip_prefix('[') {
+            // An IPv6 address, e.g. [::1]:3868
+            Some(rest) => match rest.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, port.strip_prefix(':').or(Some(port))),
+                None => ("", None),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = port
+            .map(|port| {
+                port.parse::<u16>()
+                    .map_err(|_| Error::DecodeError(format!("Invalid DiameterURI port: {}", uri)))
+            })
+            .transpose()?;
+        if host.is_empty() || (host.contains(':') && !authority.starts_with('[')) {
+            return Err(Error::DecodeError(format!(
+                "Invalid DiameterURI host: {}",
+                uri
+            )));
+        }
+
+        let mut parts = Parts {
+            scheme,
+            host: host.into(),
+            port,
+            transport: None,
+            protocol: None,
+            params: Vec::new(),
+        };
+
+        for param in params {
+            match param.split_once('=') {
+                Some(("transport", "tcp")) => parts.transport = Some(Transport::Tcp),
+                Some(("transport", "sctp")) => parts.transport = Some(Transport::Sctp),
+                Some(("transport", "udp")) => parts.transport = Some(Transport::Udp),
+                Some(("protocol", "diameter")) => parts.protocol = Some(Protocol::Diameter),
+                Some(("protocol", "radius")) => parts.protocol = Some(Protocol::Radius),
+                Some(("protocol", "tacacs+")) => parts.protocol = Some(Protocol::TacacsPlus),
+                _ => parts.params.push(param.into()),
+            }
+        }
+
+        Ok(parts)
+    }
+}
+
+impl fmt::Display for Parts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.scheme {
+            Scheme::Aaa => "aaa",
+            Scheme::Aaas => "aaas",
+        };
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]", scheme, self.host)?;
+        } else {
+            write!(f, "{}://{}", scheme, self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        if let Some(transport) = self.transport {
+            let transport = match transport {
+                Transport::Tcp => "tcp",
+                Transport::Sctp => "sctp",
+                Transport::Udp => "udp",
+            };
+            write!(f, ";transport={}", transport)?;
+        }
+        if let Some(protocol) = self.protocol {
+            let protocol = match protocol {
+                Protocol::Diameter => "diameter",
+                Protocol::Radius => "radius",
+                Protocol::TacacsPlus => "tacacs+",
+            };
+            write!(f, ";protocol={}", protocol)?;
+        }
+        for param in &self.params {
+            write!(f, ";{}", param)?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Display for DiameterURI {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (index, &byte) in self.0.value().iter().enumerate() {
-            if index > 0 {
-                write!(f, " ")?;
+        match std::str::from_utf8(self.value()) {
+            Ok(uri) => write!(f, "{}", uri),
+            Err(_) => {
+                for (index, &byte) in self.value().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
             }
-            write!(f, "{:02x}", byte)?;
         }
-        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_encode_decode_minimal() {
+        let uri = DiameterURI::parse("aaa://host.example.com").unwrap();
+        assert_eq!(uri.scheme(), Some(Scheme::Aaa));
+        assert_eq!(uri.host(), Some("host.example.com"));
+        assert_eq!(uri.port(), None);
+        assert_eq!(uri.transport(), None);
+        assert_eq!(uri.protocol(), None);
+
+        let mut encoded = Vec::new();
+        uri.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded, b"aaa://host.example.com");
+        assert_eq!(uri.length(), encoded.len() as u32);
+    }
+
+    #[test]
+    fn test_encode_decode_full() {
+        let data = b"aaas://host.example.com:5658;transport=sctp;protocol=diameter";
+        let mut cursor = Cursor::new(&data);
+        let uri = DiameterURI::decode_from(&mut cursor, data.len()).unwrap();
+        assert_eq!(uri.scheme(), Some(Scheme::Aaas));
+        assert_eq!(uri.host(), Some("host.example.com"));
+        assert_eq!(uri.port(), Some(5658));
+        assert_eq!(uri.transport(), Some(Transport::Sctp));
+        assert_eq!(uri.protocol(), Some(Protocol::Diameter));
+
+        let mut encoded = Vec::new();
+        uri.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+
+        let built = DiameterURI::from_parts(Scheme::Aaas, "host.example.com")
+            .with_port(5658)
+            .with_transport(Transport::Sctp)
+            .with_protocol(Protocol::Diameter);
+        assert_eq!(built, uri);
+        assert_eq!(built.length(), data.len() as u32);
+    }
+
+    #[test]
+    fn test_decode_ipv6() {
+        let uri = DiameterURI::parse("aaa://[::1]:3868;transport=tcp").unwrap();
+        assert_eq!(uri.host(), Some("::1"));
+        assert_eq!(uri.port(), Some(3868));
+        assert_eq!(uri.transport(), Some(Transport::Tcp));
+
+        let built = DiameterURI::from_parts(Scheme::Aaa, "::1")
+            .with_port(3868)
+            .with_transport(Transport::Tcp);
+        assert_eq!(built.value(), b"aaa://[::1]:3868;transport=tcp");
+
+        let uri = DiameterURI::parse("aaa://[2001:db8::1]").unwrap();
+        assert_eq!(uri.host(), Some("2001:db8::1"));
+        assert_eq!(uri.port(), None);
+        assert!(DiameterURI::parse("aaa://::1").is_err());
+    }
+
+    #[test]
+    fn test_decode_unknown_params() {
+        let data = b"aaa://host.example.com;transport=quic;foo=bar;protocol=radius";
+        let mut cursor = Cursor::new(&data);
+        let uri = DiameterURI::decode_from(&mut cursor, data.len()).unwrap();
+        assert_eq!(uri.transport(), None);
+        assert_eq!(uri.protocol(), Some(Protocol::Radius));
+        assert_eq!(uri.params(), ["transport=quic", "foo=bar"]);
+
+        let mut encoded = Vec::new();
+        uri.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+
+        // Rebuilt from the parts, the unknown parameters are kept
+        let uri = uri.with_port(3868);
+        assert_eq!(
+            uri.value(),
+            b"aaa://host.example.com:3868;protocol=radius;transport=quic;foo=bar"
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        match DiameterURI::parse("http://host.example.com") {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "Invalid DiameterURI scheme: http://host.example.com")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+
+        let data = b"http://host.example.com";
+        let mut cursor = Cursor::new(&data);
+        match DiameterURI::decode_from(&mut cursor, data.len()) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "Invalid DiameterURI scheme: http://host.example.com")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+        let data = [0xff, 0x01];
+        let mut cursor = Cursor::new(&data);
+        assert!(matches!(
+            DiameterURI::decode_from(&mut cursor, data.len()),
+            Err(Error::DecodeError(_))
+        ));
+
+        // `new` keeps an invalid URI as it is rather than failing
+        let uri = DiameterURI::new(b"http://host.example.com".to_vec());
+        assert_eq!(uri.scheme(), None);
+        assert_eq!(uri.value(), b"http://host.example.com");
+        assert_eq!(uri.to_string(), "http://host.example.com");
+        assert_eq!(uri.clone().with_port(3868), uri);
+        assert_eq!(DiameterURI::new(vec![0xff, 0x01]).to_string(), "ff 01");
     }
 }
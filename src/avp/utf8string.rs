@@ -15,12 +15,21 @@ impl UTF8String {
         &self.0
     }
 
+    /// Decodes exactly `len` bytes, which must be valid UTF-8.
+    ///
+    /// Invalid UTF-8 is rejected rather than converted lossily; binary data
+    /// belongs in an `OctetString`.
     pub fn decode_from<R: Read>(reader: &mut R, len: usize) -> Result<UTF8String> {
         let mut b = vec![0u8; len];
-        reader.read_exact(&mut b)?;
+        reader.read_exact(&mut b).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                Error::DecodeError(format!("truncated UTF8String, expected {} bytes", len))
+            }
+            _ => Error::IoError(e),
+        })?;
 
-        let s = String::from_utf8(b.to_vec())
-            .map_err(|e| Error::DecodeError(format!("invalid UTF8String: {}", e)))?;
+        let s =
+            String::from_utf8(b).map_err(|_| Error::DecodeError("invalid UTF-8 in AVP".into()))?;
         Ok(UTF8String(s))
     }
 
@@ -74,10 +83,20 @@ mod tests {
         let mut cursor = Cursor::new(&bytes);
         match UTF8String::decode_from(&mut cursor, 5) {
             Err(Error::DecodeError(msg)) => {
-                assert_eq!(
-                    msg,
-                    "invalid UTF8String: invalid utf-8 sequence of 1 bytes from index 4"
-                );
+                assert_eq!(msg, "invalid UTF-8 in AVP");
+            }
+            Err(_) => panic!("Expected a DecodeError, but got a different error"),
+            Ok(_) => panic!("Expected an error, but got Ok"),
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let bytes = "Hello".as_bytes();
+        let mut cursor = Cursor::new(&bytes);
+        match UTF8String::decode_from(&mut cursor, 8) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "truncated UTF8String, expected 8 bytes");
             }
             Err(_) => panic!("Expected a DecodeError, but got a different error"),
             Ok(_) => panic!("Expected an error, but got Ok"),
@@ -0,0 +1,166 @@
+//! Walks AVPs by their decoded type.
+//!
+//! Implement `Visitor` to export a message, e.g. to JSON logs, without
+//! matching on `AvpValue`. Every method has a default, so a visitor only
+//! overrides the types it handles; grouped AVPs are descended into.
+
+use crate::avp::{Address, Avp, AvpValue, DiameterURI};
+use chrono::{DateTime, Utc};
+
+/// Receives each AVP of a message with its typed value, see
+/// `DiameterMessage::accept` and `Avp::accept`.
+///
+/// Identity and UTF8String values go to `visit_string`, and IPv4 and IPv6
+/// values to `visit_address`. Enumerated, DiameterURI and undecoded (raw)
+/// values fall back to `visit_i32`, `visit_string` and `visit_octets`
+/// unless overridden.
+#[allow(unused_variables)]
+pub trait Visitor {
+    fn visit_u32(&mut self, avp: &Avp, value: u32) {}
+
+    fn visit_u64(&mut self, avp: &Avp, value: u64) {}
+
+    fn visit_i32(&mut self, avp: &Avp, value: i32) {}
+
+    fn visit_i64(&mut self, avp: &Avp, value: i64) {}
+
+    fn visit_f32(&mut self, avp: &Avp, value: f32) {}
+
+    fn visit_f64(&mut self, avp: &Avp, value: f64) {}
+
+    fn visit_enumerated(&mut self, avp: &Avp, value: i32) {
+        self.visit_i32(avp, value)
+    }
+
+    fn visit_string(&mut self, avp: &Avp, value: &str) {}
+
+    fn visit_uri(&mut self, avp: &Avp, value: &DiameterURI) {
+        self.visit_string(avp, &value.to_string())
+    }
+
+    fn visit_octets(&mut self, avp: &Avp, value: &[u8]) {}
+
+    fn visit_raw(&mut self, avp: &Avp, value: &[u8]) {
+        self.visit_octets(avp, value)
+    }
+
+    fn visit_address(&mut self, avp: &Avp, value: &Address) {}
+
+    fn visit_time(&mut self, avp: &Avp, value: &DateTime<Utc>) {}
+
+    /// Called for a grouped AVP. The default visits each child in order.
+    fn visit_grouped(&mut self, avp: &Avp, avps: &[Avp]) {
+        for child in avps {
+            child.accept(self);
+        }
+    }
+}
+
+impl Avp {
+    /// Dispatches the AVP to the `visitor` method for its type.
+    pub fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        match self.get_value() {
+            AvpValue::Address(value) => visitor.visit_address(self, value),
+            AvpValue::AddressIPv4(value) => {
+                visitor.visit_address(self, &Address::from_ipv4(value.value()))
+            }
+            AvpValue::AddressIPv6(value) => {
+                visitor.visit_address(self, &Address::from_ipv6(value.value()))
+            }
+            AvpValue::Identity(value) => visitor.visit_string(self, value.value()),
+            AvpValue::DiameterURI(value) => visitor.visit_uri(self, value),
+            AvpValue::Enumerated(value) => visitor.visit_enumerated(self, value.value()),
+            AvpValue::Float32(value) => visitor.visit_f32(self, value.value()),
+            AvpValue::Float64(value) => visitor.visit_f64(self, value.value()),
+            AvpValue::Grouped(value) => visitor.visit_grouped(self, value.avps()),
+            AvpValue::Integer32(value) => visitor.visit_i32(self, value.value()),
+            AvpValue::Integer64(value) => visitor.visit_i64(self, value.value()),
+            AvpValue::OctetString(value) => visitor.visit_octets(self, value.value()),
+            AvpValue::Time(value) => visitor.visit_time(self, value.value()),
+            AvpValue::Unsigned32(value) => visitor.visit_u32(self, value.value()),
+            AvpValue::Unsigned64(value) => visitor.visit_u64(self, value.value()),
+            AvpValue::UTF8String(value) => visitor.visit_string(self, value.value()),
+            AvpValue::Raw(value) => visitor.visit_raw(self, value.value()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp;
+    use crate::avp::flags::M;
+    use crate::avp::{Enumerated, Grouped, Identity, UTF8String, Unsigned32};
+    use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+    use std::collections::BTreeMap;
+
+    // Collects AVP codes and their values as strings, grouped children
+    // included.
+    #[derive(Default)]
+    struct Collector {
+        values: BTreeMap<u32, String>,
+    }
+
+    impl Visitor for Collector {
+        fn visit_u32(&mut self, avp: &Avp, value: u32) {
+            self.values.insert(avp.get_code(), value.to_string());
+        }
+
+        fn visit_i32(&mut self, avp: &Avp, value: i32) {
+            self.values.insert(avp.get_code(), value.to_string());
+        }
+
+        fn visit_string(&mut self, avp: &Avp, value: &str) {
+            self.values.insert(avp.get_code(), value.to_string());
+        }
+
+        fn visit_address(&mut self, avp: &Avp, value: &Address) {
+            self.values.insert(avp.get_code(), value.to_string());
+        }
+
+        fn visit_grouped(&mut self, avp: &Avp, avps: &[Avp]) {
+            self.values
+                .insert(avp.get_code(), format!("{} avps", avps.len()));
+            for child in avps {
+                child.accept(self);
+            }
+        }
+    }
+
+    #[test]
+    fn test_collect_values() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+        message.add_avp(avp!(257, None, M, Address::ipv4(10, 0, 0, 1)));
+        message.add_avp(avp!(
+            873,
+            Some(10415),
+            M,
+            Grouped::new(vec![avp!(415, None, M, Unsigned32::new(1000))])
+        ));
+
+        let mut collector = Collector::default();
+        message.accept(&mut collector);
+
+        let expected: BTreeMap<u32, String> = [
+            (257, "10.0.0.1"),
+            (263, "ses;12345888"),
+            (264, "host.example.com"),
+            (415, "1000"),
+            (416, "1"),
+            (873, "1 avps"),
+        ]
+        .into_iter()
+        .map(|(code, value)| (code, value.to_string()))
+        .collect();
+        assert_eq!(collector.values, expected);
+    }
+}
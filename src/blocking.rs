@@ -0,0 +1,119 @@
+//! Diameter Blocking Client
+//!
+//! A synchronous wrapper around [`DiameterClient`] for tools and scripts that
+//! do not run a tokio runtime of their own.
+//!
+//! The client owns a current-thread runtime and blocks on it for every call.
+//! It must not be used from within an existing async context: blocking calls
+//! made from inside a runtime panic.
+
+use crate::diameter::DiameterMessage;
+use crate::error::Result;
+use crate::transport::{DiameterClient, DiameterClientConfig};
+use tokio::runtime::{Builder, Runtime};
+
+/// A blocking Diameter protocol client.
+///
+/// Fields:
+///     runtime: The current-thread runtime driving the connection.
+///     client: The underlying async `DiameterClient`.
+pub struct BlockingDiameterClient {
+    runtime: Runtime,
+    client: DiameterClient,
+}
+
+impl BlockingDiameterClient {
+    /// Connects to a Diameter server, blocking until the connection is established.
+    ///
+    /// The reader loop is spawned on the client's runtime and runs whenever
+    /// a blocking call is in progress.
+    ///
+    /// Args:
+    ///     addr: The address of the Diameter server to connect to.
+    ///     config: The client configuration.
+    ///
+    /// Returns:
+    ///     A `Result` containing the connected `BlockingDiameterClient`, or an error
+    ///     if the runtime cannot be created or the connection cannot be established.
+    pub fn connect(addr: &str, config: DiameterClientConfig) -> Result<BlockingDiameterClient> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let mut client = DiameterClient::new(addr, config);
+        let mut handler = runtime.block_on(client.connect())?;
+        runtime.spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        Ok(BlockingDiameterClient { runtime, client })
+    }
+
+    /// Sends a Diameter message and blocks until the response is received.
+    ///
+    /// Args:
+    ///     req: The Diameter message to send.
+    ///
+    /// Returns:
+    ///     A `Result` containing the response `DiameterMessage`.
+    pub fn send_message(&mut self, req: DiameterMessage) -> Result<DiameterMessage> {
+        let client = &mut self.client;
+        self.runtime
+            .block_on(async move { client.send_message(req).await?.await })
+    }
+
+    /// Returns the next sequence number, for use as a Hop-by-Hop Identifier.
+    pub fn get_next_seq_num(&mut self) -> u32 {
+        self.client.get_next_seq_num()
+    }
+
+    /// Shuts down the connection, blocking until in-flight requests complete.
+    pub fn shutdown(self) -> Result<()> {
+        self.runtime.block_on(self.client.shutdown())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp;
+    use crate::avp::flags::M;
+    use crate::avp::Avp;
+    use crate::avp::Unsigned32;
+    use crate::diameter::flags;
+    use crate::diameter::{ApplicationId, CommandCode};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_blocking_send_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut b = vec![0; 4];
+            stream.read_exact(&mut b).unwrap();
+            let length = u32::from_be_bytes([0, b[1], b[2], b[3]]) as usize;
+            b.resize(length, 0);
+            stream.read_exact(&mut b[4..]).unwrap();
+
+            let req = DiameterMessage::from_bytes(&b).unwrap();
+            let mut answer = req.to_answer();
+            answer.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            stream.write_all(&answer.to_bytes().unwrap()).unwrap();
+        });
+
+        let mut client =
+            BlockingDiameterClient::connect(&addr.to_string(), DiameterClientConfig::default())
+                .unwrap();
+        let seq_num = client.get_next_seq_num();
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            seq_num,
+            seq_num,
+        );
+        let cca = client.send_message(ccr).unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), seq_num);
+        assert_eq!(cca.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+
+        server.join().unwrap();
+    }
+}
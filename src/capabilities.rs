@@ -0,0 +1,280 @@
+//! Diameter Capabilities Exchange
+//!
+//! Builds Capabilities-Exchange-Request (CER) messages from a [`PeerConfig`]
+//! and parses the peer capabilities advertised in a CER or CEA, as described
+//! in RFC 6733, section 5.3. Also builds the Device-Watchdog-Request (DWR)
+//! and Answer (DWA) of section 5.5, and the Disconnect-Peer-Answer (DPA) of
+//! section 5.4.
+
+use crate::avp::codes::{
+    AUTH_APPLICATION_ID, HOST_IP_ADDRESS, ORIGIN_HOST, ORIGIN_REALM, ORIGIN_STATE_ID, PRODUCT_NAME,
+    RESULT_CODE, VENDOR_ID,
+};
+use crate::avp::flags::M;
+use crate::avp::{Address, Avp, Identity, UTF8String, Unsigned32};
+use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+use crate::error::{Error, Result};
+use crate::result_code::ResultCode;
+use std::net::IpAddr;
+
+/// Local peer identity advertised during the capabilities exchange.
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    pub origin_host: String,
+    pub origin_realm: String,
+    pub host_ip_addresses: Vec<IpAddr>,
+    pub vendor_id: u32,
+    pub product_name: String,
+    pub origin_state_id: Option<u32>,
+    pub auth_application_ids: Vec<u32>,
+}
+
+/// Capabilities advertised by a remote peer in a CER or CEA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerCapabilities {
+    pub origin_host: String,
+    pub origin_realm: String,
+    pub host_ip_addresses: Vec<IpAddr>,
+    pub vendor_id: u32,
+    pub product_name: String,
+}
+
+impl PeerConfig {
+    /// Builds a Capabilities-Exchange-Request, emitting one Host-IP-Address
+    /// AVP per configured address.
+    pub fn cer(&self, hop_by_hop_id: u32, end_to_end_id: u32) -> DiameterMessage {
+        let mut builder =
+            DiameterMessage::builder(CommandCode::CapabilitiesExchange, ApplicationId::Common)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(hop_by_hop_id)
+                .end_to_end_id(end_to_end_id)
+                .origin(&self.origin_host, &self.origin_realm);
+
+        for ip in &self.host_ip_addresses {
+            builder = builder.avp(Avp::new(
+                HOST_IP_ADDRESS,
+                None,
+                M,
+                Address::from_ip(*ip).into(),
+            ));
+        }
+        builder = builder
+            .avp(Avp::new(
+                VENDOR_ID,
+                None,
+                M,
+                Unsigned32::new(self.vendor_id).into(),
+            ))
+            .avp(Avp::new(
+                PRODUCT_NAME,
+                None,
+                0,
+                UTF8String::new(&self.product_name).into(),
+            ));
+        if let Some(origin_state_id) = self.origin_state_id {
+            builder = builder.origin_state_id(origin_state_id);
+        }
+        for app_id in &self.auth_application_ids {
+            builder = builder.avp(Avp::new(
+                AUTH_APPLICATION_ID,
+                None,
+                M,
+                Unsigned32::new(*app_id).into(),
+            ));
+        }
+        builder.build()
+    }
+
+    /// Builds a Device-Watchdog-Request.
+    pub fn dwr(&self, hop_by_hop_id: u32, end_to_end_id: u32) -> DiameterMessage {
+        let mut builder =
+            DiameterMessage::builder(CommandCode::DeviceWatchdog, ApplicationId::Common)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(hop_by_hop_id)
+                .end_to_end_id(end_to_end_id)
+                .origin(&self.origin_host, &self.origin_realm);
+        if let Some(origin_state_id) = self.origin_state_id {
+            builder = builder.origin_state_id(origin_state_id);
+        }
+        builder.build()
+    }
+
+    /// Builds the Device-Watchdog-Answer to `dwr`, with a DIAMETER_SUCCESS
+    /// Result-Code.
+    pub fn dwa(&self, dwr: &DiameterMessage) -> DiameterMessage {
+        let mut dwa = self.success_answer(dwr);
+        if let Some(origin_state_id) = self.origin_state_id {
+            dwa.add_avp(Avp::new(
+                ORIGIN_STATE_ID,
+                None,
+                M,
+                Unsigned32::new(origin_state_id).into(),
+            ));
+        }
+        dwa
+    }
+
+    /// Builds the Disconnect-Peer-Answer to `dpr`, with a DIAMETER_SUCCESS
+    /// Result-Code.
+    pub fn dpa(&self, dpr: &DiameterMessage) -> DiameterMessage {
+        self.success_answer(dpr)
+    }
+
+    // The answer to `req` with Result-Code, Origin-Host and Origin-Realm.
+    fn success_answer(&self, req: &DiameterMessage) -> DiameterMessage {
+        let mut answer = req.to_answer();
+        answer.add_avp(Avp::new(
+            RESULT_CODE,
+            None,
+            M,
+            Unsigned32::new(ResultCode::Success.as_u32()).into(),
+        ));
+        answer.add_avp(Avp::new(
+            ORIGIN_HOST,
+            None,
+            M,
+            Identity::new(&self.origin_host).into(),
+        ));
+        answer.add_avp(Avp::new(
+            ORIGIN_REALM,
+            None,
+            M,
+            Identity::new(&self.origin_realm).into(),
+        ));
+        answer
+    }
+}
+
+impl PeerCapabilities {
+    /// Reads the peer capabilities from a CER or CEA, collecting every
+    /// Host-IP-Address AVP.
+    pub fn from_message(message: &DiameterMessage) -> Result<PeerCapabilities> {
+        let identity = |code, name: &str| {
+            message
+                .get_avp(code)
+                .and_then(|avp| avp.as_string())
+                .map(|s| s.to_string())
+                .ok_or_else(|| Error::DecodeError(format!("missing {} AVP", name)))
+        };
+
+        let host_ip_addresses = message
+            .get_avps_by_code(HOST_IP_ADDRESS)
+            .iter()
+            .filter_map(|avp| avp.as_address().and_then(|address| address.ip()))
+            .collect();
+
+        Ok(PeerCapabilities {
+            origin_host: identity(ORIGIN_HOST, "Origin-Host")?,
+            origin_realm: identity(ORIGIN_REALM, "Origin-Realm")?,
+            host_ip_addresses,
+            vendor_id: message
+                .get_avp(VENDOR_ID)
+                .and_then(|avp| avp.as_u32())
+                .ok_or_else(|| Error::DecodeError("missing Vendor-Id AVP".into()))?,
+            product_name: identity(PRODUCT_NAME, "Product-Name")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_config() -> PeerConfig {
+        PeerConfig {
+            origin_host: "client.example.com".into(),
+            origin_realm: "example.com".into(),
+            host_ip_addresses: vec![
+                "192.168.1.10".parse().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+            ],
+            vendor_id: 10415,
+            product_name: "diameter-rs".into(),
+            origin_state_id: Some(1),
+            auth_application_ids: vec![4],
+        }
+    }
+
+    #[test]
+    fn test_dwr() {
+        let dwr = peer_config().dwr(7, 8);
+        assert_eq!(dwr.get_command_code(), CommandCode::DeviceWatchdog);
+        assert_eq!(dwr.get_flags(), flags::REQUEST);
+        assert_eq!(dwr.get_hop_by_hop_id(), 7);
+        assert_eq!(
+            dwr.get_avp(264).unwrap().get_identity().unwrap().value(),
+            "client.example.com"
+        );
+        assert_eq!(dwr.get_avp(278).unwrap().get_unsigned32(), Some(1));
+    }
+
+    #[test]
+    fn test_dwa_and_dpa() {
+        let dwr = DiameterMessage::new(
+            CommandCode::DeviceWatchdog,
+            ApplicationId::Common,
+            flags::REQUEST,
+            7,
+            8,
+        );
+        let dwa = peer_config().dwa(&dwr);
+        assert_eq!(dwa.get_command_code(), CommandCode::DeviceWatchdog);
+        assert_eq!(dwa.get_flags(), 0);
+        assert_eq!(dwa.get_hop_by_hop_id(), 7);
+        assert_eq!(dwa.get_end_to_end_id(), 8);
+        assert_eq!(dwa.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+        assert_eq!(
+            dwa.get_avp(296).unwrap().get_identity().unwrap().value(),
+            "example.com"
+        );
+        assert_eq!(dwa.get_avp(278).unwrap().get_unsigned32(), Some(1));
+
+        let dpr = DiameterMessage::new(
+            CommandCode::DisconnectPeer,
+            ApplicationId::Common,
+            flags::REQUEST,
+            9,
+            9,
+        );
+        let dpa = peer_config().dpa(&dpr);
+        assert_eq!(dpa.get_command_code(), CommandCode::DisconnectPeer);
+        assert_eq!(dpa.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+        assert!(dpa.get_avp(278).is_none());
+    }
+
+    #[test]
+    fn test_cer_host_ip_addresses() {
+        let cer = peer_config().cer(1, 1);
+        assert_eq!(cer.get_command_code(), CommandCode::CapabilitiesExchange);
+
+        let addresses = cer.get_avps_by_code(257);
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].get_length(), 8 + 6);
+        assert_eq!(addresses[1].get_length(), 8 + 18);
+
+        let bytes = cer.to_bytes().unwrap();
+        let cer = DiameterMessage::from_bytes(&bytes).unwrap();
+        let capabilities = PeerCapabilities::from_message(&cer).unwrap();
+        assert_eq!(capabilities.origin_host, "client.example.com");
+        assert_eq!(capabilities.vendor_id, 10415);
+        assert_eq!(
+            capabilities.host_ip_addresses,
+            peer_config().host_ip_addresses
+        );
+    }
+
+    #[test]
+    fn test_from_message_missing_origin_host() {
+        let cea = DiameterMessage::new(
+            CommandCode::CapabilitiesExchange,
+            ApplicationId::Common,
+            0,
+            1,
+            1,
+        );
+        match PeerCapabilities::from_message(&cea) {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "missing Origin-Host AVP"),
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Diameter Protocol Codec
+//!
+//! Provides a [`DiameterCodec`] implementing `tokio_util`'s `Decoder` and `Encoder`
+//! traits so Diameter messages can be framed over any byte stream, e.g. with `Framed`.
+
+use crate::diameter::DiameterMessage;
+use crate::error::{Error, Result};
+use crate::stream::decode_frame;
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+pub use crate::stream::MAX_MESSAGE_LENGTH;
+
+/// Frames Diameter messages using the 24-bit message length in the header.
+///
+/// The decoder buffers until a complete message is available and returns
+/// `Ok(None)` on partial reads.
+#[derive(Debug, Default, Clone)]
+pub struct DiameterCodec {}
+
+impl DiameterCodec {
+    /// Creates a new `DiameterCodec`.
+    pub fn new() -> DiameterCodec {
+        DiameterCodec {}
+    }
+}
+
+impl Decoder for DiameterCodec {
+    type Item = DiameterMessage;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<DiameterMessage>> {
+        decode_frame(src, MAX_MESSAGE_LENGTH)
+    }
+
+    /// Decodes the last message when the stream ends. Buffered bytes that do
+    /// not form a whole message mean the peer closed mid-frame, which is
+    /// reported as a `DecodeError` rather than as a clean close.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<DiameterMessage>> {
+        match self.decode(src)? {
+            Some(msg) => Ok(Some(msg)),
+            None if src.is_empty() => Ok(None),
+            None => Err(Error::DecodeError(format!(
+                "connection closed mid-frame, {} bytes of a partial message",
+                src.len()
+            ))),
+        }
+    }
+}
+
+impl Encoder<DiameterMessage> for DiameterCodec {
+    type Error = Error;
+
+    fn encode(&mut self, msg: DiameterMessage, dst: &mut BytesMut) -> Result<()> {
+        dst.reserve(msg.get_length() as usize);
+        msg.encode_into(dst)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::diameter::tests::ccr;
+    use bytes::BufMut;
+
+    #[test]
+    fn test_decode_one_byte_at_a_time() {
+        let mut codec = DiameterCodec::new();
+        let mut encoded = BytesMut::new();
+        codec.encode(ccr(1), &mut encoded).unwrap();
+        codec.encode(ccr(2), &mut encoded).unwrap();
+
+        let mut src = BytesMut::new();
+        let mut decoded = vec![];
+        for byte in encoded.iter() {
+            src.put_u8(*byte);
+            if let Some(msg) = codec.decode(&mut src).unwrap() {
+                decoded.push(msg);
+            }
+        }
+
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].get_hop_by_hop_id(), 1);
+        assert_eq!(decoded[1].get_hop_by_hop_id(), 2);
+        assert_eq!(
+            decoded[1].get_avp(415).unwrap().get_unsigned32(),
+            Some(1000)
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_eof() {
+        let mut codec = DiameterCodec::new();
+        let mut src = BytesMut::new();
+        codec.encode(ccr(1), &mut src).unwrap();
+        let msg = codec.decode_eof(&mut src).unwrap().unwrap();
+        assert_eq!(msg.get_hop_by_hop_id(), 1);
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+
+        // A partial message left when the stream ends is an error
+        codec.encode(ccr(2), &mut src).unwrap();
+        src.truncate(30);
+        match codec.decode_eof(&mut src) {
+            Err(Error::DecodeError(msg)) => assert_eq!(
+                msg,
+                "connection closed mid-frame, 30 bytes of a partial message"
+            ),
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+
+    #[test]
+    fn test_decode_too_large() {
+        let mut codec = DiameterCodec::new();
+        let mut src = BytesMut::from(&[0x01, 0xFF, 0xFF, 0xFF][..]);
+        match codec.decode(&mut src) {
+            Err(Error::MessageTooLarge { size, limit }) => {
+                assert_eq!(size, 0xFFFFFF);
+                assert_eq!(limit, MAX_MESSAGE_LENGTH);
+            }
+            _ => panic!("Expected an error for oversized message"),
+        }
+    }
+
+    // Captures log lines so tests can assert on them. Shared by every test
+    // in the crate, as a process has a single logger.
+    struct CapturingLogger;
+
+    static LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+    static LOGGER: CapturingLogger = CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGS.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    pub(crate) fn captured_logs() -> Vec<String> {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        LOGS.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_decode_failure_logs_ids() {
+        captured_logs();
+        let mut codec = DiameterCodec::new();
+
+        let mut encoded = BytesMut::new();
+        codec.encode(ccr(7), &mut encoded).unwrap();
+        // Cut the last AVP short, keeping the message length consistent
+        let len = encoded.len() as u32 - 2;
+        encoded.truncate(len as usize);
+        encoded[1..4].copy_from_slice(&len.to_be_bytes()[1..4]);
+        assert!(codec.decode(&mut encoded).is_err());
+
+        let mut bad_version = BytesMut::new();
+        codec.encode(ccr(8), &mut bad_version).unwrap();
+        bad_version[0] = 0x02;
+        assert!(codec.decode(&mut bad_version).is_err());
+
+        let logs = captured_logs();
+        assert!(logs.iter().any(|line| {
+            line.starts_with(
+            "Failed to decode message; hop_by_hop_id 7, end_to_end_id 7, command code CreditControl"
+        )
+        }));
+        assert!(logs
+            .iter()
+            .any(|line| line.starts_with("Failed to decode message; first bytes 02 00 00 ")));
+    }
+}
@@ -30,11 +30,26 @@
 //!   +-+-+-+-+-+-+-+-+
 //! ```
 
+use crate::avp::codes::{
+    DESTINATION_HOST, DESTINATION_REALM, EXPERIMENTAL_RESULT, FAILED_AVP, ORIGIN_HOST,
+    ORIGIN_REALM, ORIGIN_STATE_ID, RESULT_CODE, SESSION_ID,
+};
+use crate::avp::flags::M;
 use crate::avp::Avp;
+use crate::avp::AvpValue;
+use crate::avp::DecodeOptions;
+use crate::avp::Grouped;
+use crate::avp::Identity;
+use crate::avp::Unsigned32;
+use crate::avp::Visitor;
+use crate::dictionary;
 use crate::error::{Error, Result};
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
+use crate::hex;
+use crate::proxy::{self, ProxyInfo};
+use crate::result_code::ResultCode;
+use bytes::{BufMut, Bytes, BytesMut};
 use std::fmt;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
@@ -51,7 +66,7 @@ pub mod flags {
 /// Represents a Diameter message as defined in RFC 6733.
 ///
 /// It consists of a standard header and a list of Attribute-Value Pairs (AVPs).
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DiameterMessage {
     header: DiameterHeader,
     avps: Vec<Avp>,
@@ -61,7 +76,7 @@ pub struct DiameterMessage {
 ///
 /// It includes version, message length, command flags, command code, application ID,
 /// and unique identifiers for routing and matching requests and replies.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DiameterHeader {
     version: u8,
     length: u32,
@@ -72,33 +87,64 @@ pub struct DiameterHeader {
     end_to_end_id: u32,
 }
 
+/// A builder for `DiameterMessage` with convenience methods for the
+/// standard routing AVPs.
+///
+/// Example:
+///    ```
+///    use diameter::{ApplicationId, CommandCode, DiameterMessage};
+///    use diameter::flags;
+///
+///    let ccr = DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+///        .flags(flags::REQUEST)
+///        .hop_by_hop_id(1)
+///        .end_to_end_id(1)
+///        .origin("host.example.com", "realm.example.com")
+///        .destination_realm("realm.example.com")
+///        .build();
+///    assert_eq!(ccr.get_avps().len(), 3);
+///    ```
+#[derive(Debug)]
+pub struct DiameterMessageBuilder {
+    message: DiameterMessage,
+}
+
 /// Enumerates various command codes used in Diameter messages.
-#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+///
+/// Codes without a named variant decode as `Unknown`. Create command codes
+/// from raw values with `from_u32`, which never returns `Unknown` for a code
+/// that has a named variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CommandCode {
-    Error = 0,
-    CapabilitiesExchange = 257,
-    DeviceWatchdog = 280,
-    DisconnectPeer = 282,
-    ReAuth = 258,
-    SessionTerminate = 275,
-    AbortSession = 274,
-    CreditControl = 272,
-    SpendingLimit = 8388635,
-    SpendingStatusNotification = 8388636,
-    Accounting = 271,
-    AA = 265,
+    Error,
+    CapabilitiesExchange,
+    DeviceWatchdog,
+    DisconnectPeer,
+    ReAuth,
+    SessionTerminate,
+    AbortSession,
+    CreditControl,
+    SpendingLimit,
+    SpendingStatusNotification,
+    Accounting,
+    AA,
+    Unknown(u32),
 }
 
 /// Enumerates the different application IDs that can be used in Diameter messages
-#[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+///
+/// Application ids without a named variant decode as `Unknown`. Create
+/// application ids from raw values with `from_u32`, which never returns
+/// `Unknown` for an id that has a named variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ApplicationId {
-    Common = 0,
-    Accounting = 3,
-    CreditControl = 4,
-    Gx = 16777238,
-    Rx = 16777236,
-    Sy = 16777302,
+    Common,
+    Accounting,
+    CreditControl,
+    Gx,
+    Rx,
+    Sy,
+    Unknown(u32),
 }
 
 impl DiameterMessage {
@@ -124,28 +170,217 @@ impl DiameterMessage {
         DiameterMessage { header, avps }
     }
 
+    /// Returns a `DiameterMessageBuilder` for a message with the given
+    /// command code and application ID.
+    pub fn builder(code: CommandCode, application_id: ApplicationId) -> DiameterMessageBuilder {
+        DiameterMessageBuilder {
+            message: DiameterMessage::new(code, application_id, 0, 0, 0),
+        }
+    }
+
+    /// Constructs an answer for this request.
+    ///
+    /// The answer mirrors the command code, application ID, Hop-by-Hop and
+    /// End-to-End Identifiers of the request, keeps the P flag and clears the
+    /// R flag. The Session-Id (263) and any Proxy-Info (284) AVPs are copied
+    /// in their original order. Origin-Host and Origin-Realm are not copied
+    /// since they identify the answering node and must be added by the caller.
+    pub fn to_answer(&self) -> DiameterMessage {
+        let mut answer = DiameterMessage::new(
+            self.header.code,
+            self.header.application_id,
+            self.header.flags & flags::PROXYABLE,
+            self.header.hop_by_hop_id,
+            self.header.end_to_end_id,
+        );
+        self.avps
+            .iter()
+            .filter(|avp| avp.get_code() == SESSION_ID || avp.get_code() == proxy::PROXY_INFO)
+            .for_each(|avp| answer.add_avp(avp.clone()));
+        answer
+    }
+
     /// Returns a reference to the AVP with the specified code,
     /// if it exists within the message.
     pub fn get_avp(&self, code: u32) -> Option<&Avp> {
         self.avps.iter().find(|avp| avp.get_code() == code)
     }
 
+    /// Returns a reference to the vendor-specific AVP with the specified
+    /// vendor ID and code, if it exists within the message.
+    pub fn get_avp_by_vendor(&self, vendor_id: u32, code: u32) -> Option<&Avp> {
+        self.avps
+            .iter()
+            .find(|avp| avp.get_code() == code && avp.get_vendor_id() == Some(vendor_id))
+    }
+
+    /// Returns references to all AVPs with the specified code, in the order
+    /// they appear within the message.
+    pub fn get_avps_by_code(&self, code: u32) -> Vec<&Avp> {
+        self.avps
+            .iter()
+            .filter(|avp| avp.get_code() == code)
+            .collect()
+    }
+
     /// Provides a reference to the vector containing all AVPs in the message.
     pub fn get_avps(&self) -> &Vec<Avp> {
         &self.avps
     }
 
+    /// Returns an iterator over the AVPs in the message.
+    pub fn avps(&self) -> impl Iterator<Item = &Avp> {
+        self.avps.iter()
+    }
+
+    /// Walks the AVPs of the message in order, dispatching each to the
+    /// `visitor` method for its decoded type.
+    pub fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        for avp in &self.avps {
+            avp.accept(visitor);
+        }
+    }
+
     /// Adds an AVP to the message.
     pub fn add_avp(&mut self, avp: Avp) {
         self.header.length += avp.get_length() + avp.get_padding() as u32;
         self.avps.push(avp);
     }
 
+    /// Adds AVPs to the message, in iteration order.
+    pub fn add_avps<I: IntoIterator<Item = Avp>>(&mut self, avps: I) {
+        for avp in avps {
+            self.add_avp(avp);
+        }
+    }
+
+    /// Sets an AVP, replacing any AVPs with the same code and vendor.
+    ///
+    /// The AVP takes the position of the first AVP it replaces, or is
+    /// appended if the message has none.
+    pub fn set_avp(&mut self, avp: Avp) {
+        let key = (avp.get_code(), avp.get_vendor_id());
+        let mut avp = Some(avp);
+        let mut avps = Vec::with_capacity(self.avps.len());
+        for existing in std::mem::take(&mut self.avps) {
+            if (existing.get_code(), existing.get_vendor_id()) != key {
+                avps.push(existing);
+            } else if let Some(avp) = avp.take() {
+                avps.push(avp);
+            }
+        }
+        avps.extend(avp);
+        self.avps = avps;
+        self.header.length = self.wire_length();
+    }
+
+    /// Removes every AVP with the given code, e.g. to strip Proxy-Info.
+    ///
+    /// Returns the number of AVPs removed.
+    pub fn remove_avp(&mut self, code: u32) -> usize {
+        let before = self.avps.len();
+        self.avps.retain(|avp| avp.get_code() != code);
+        let removed = before - self.avps.len();
+        if removed > 0 {
+            self.header.length = self.wire_length();
+        }
+        removed
+    }
+
+    /// Replaces the first AVP with the given code and the vendor of `avp`,
+    /// or appends `avp` if the message has none. Unlike `set_avp`, later
+    /// AVPs with the same code and vendor are kept.
+    pub fn replace_avp(&mut self, code: u32, avp: Avp) {
+        let key = (code, avp.get_vendor_id());
+        match self
+            .avps
+            .iter()
+            .position(|existing| (existing.get_code(), existing.get_vendor_id()) == key)
+        {
+            Some(index) => {
+                self.avps[index] = avp;
+                self.header.length = self.wire_length();
+            }
+            None => self.add_avp(avp),
+        }
+    }
+
+    /// Adds one AVP per value, all with the same code, vendor and flags,
+    /// e.g. for repeated Class or counter AVPs.
+    pub fn add_values<V: Into<AvpValue>>(
+        &mut self,
+        code: u32,
+        vendor_id: Option<u32>,
+        flags: u8,
+        values: Vec<V>,
+    ) {
+        for value in values {
+            self.add_avp(Avp::new(code, vendor_id, flags, value.into()));
+        }
+    }
+
+    /// Returns the values of all Unsigned32 AVPs with the specified code,
+    /// in the order they appear within the message.
+    pub fn get_all_u32(&self, code: u32) -> Vec<u32> {
+        self.avps
+            .iter()
+            .filter(|avp| avp.get_code() == code)
+            .filter_map(|avp| avp.get_unsigned32())
+            .collect()
+    }
+
+    /// Reorders the AVPs of the message into a canonical order.
+    ///
+    /// Session-Id is moved to the front, as RFC 6733 requires for every
+    /// message that carries one. The remaining AVPs keep the order in which
+    /// each (code, vendor) first appears, with repeated AVPs grouped right
+    /// after their first occurrence. The relative order of repeated AVPs is
+    /// preserved, so the message keeps its meaning.
+    ///
+    /// Messages are encoded in insertion order unless this is called.
+    pub fn canonicalize(&mut self) {
+        let mut first_seen: Vec<(u32, Option<u32>)> = Vec::new();
+        for avp in &self.avps {
+            let key = (avp.get_code(), avp.get_vendor_id());
+            if !first_seen.contains(&key) {
+                first_seen.push(key);
+            }
+        }
+        self.avps.sort_by_key(|avp| {
+            let key = (avp.get_code(), avp.get_vendor_id());
+            let position = first_seen.iter().position(|k| *k == key).unwrap();
+            (
+                avp.get_code() != SESSION_ID || avp.get_vendor_id().is_some(),
+                position,
+            )
+        });
+    }
+
     /// Returns the total length of the Diameter message, including the header and AVPs.
     pub fn get_length(&self) -> u32 {
         self.header.length
     }
 
+    /// Returns the number of bytes the message occupies on the wire, computed
+    /// from the header and the padded lengths of its AVPs.
+    ///
+    /// For a consistent message this equals `get_length`.
+    pub fn wire_length(&self) -> u32 {
+        self.avps.iter().fold(HEADER_LENGTH, |length, avp| {
+            length + avp.get_length() + avp.get_padding() as u32
+        })
+    }
+
+    /// Provides a reference to the message header.
+    pub fn get_header(&self) -> &DiameterHeader {
+        &self.header
+    }
+
+    /// Retrieves the protocol version from the message header.
+    pub fn get_version(&self) -> u8 {
+        self.header.version
+    }
+
     /// Retrieves the command code from the message header.
     pub fn get_command_code(&self) -> CommandCode {
         self.header.code
@@ -161,27 +396,159 @@ impl DiameterMessage {
         self.header.flags
     }
 
+    /// Sets or clears the P(roxiable) flag in the message header.
+    pub fn set_proxiable(&mut self, proxiable: bool) {
+        self.set_flag(flags::PROXYABLE, proxiable);
+    }
+
+    /// Returns true if the E(rror) flag is set, i.e. the message is an
+    /// error answer carrying only base/error AVPs.
+    pub fn is_error(&self) -> bool {
+        self.header.flags & flags::ERROR != 0
+    }
+
+    /// Sets or clears the E(rror) flag in the message header.
+    pub fn set_error(&mut self, error: bool) {
+        self.set_flag(flags::ERROR, error);
+    }
+
+    /// Sets or clears the T (potentially re-transmitted) flag in the message header.
+    ///
+    /// RFC 6733 requires the T flag to be set on requests re-sent after a link failover.
+    pub fn set_retransmit(&mut self, retransmit: bool) {
+        self.set_flag(flags::RETRANSMIT, retransmit);
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.header.flags |= flag;
+        } else {
+            self.header.flags &= !flag;
+        }
+    }
+
     /// Retrieves the Hop-by-Hop Identifier from the message header.
     pub fn get_hop_by_hop_id(&self) -> u32 {
         self.header.hop_by_hop_id
     }
 
+    /// Sets the Hop-by-Hop Identifier in the message header.
+    pub fn set_hop_by_hop_id(&mut self, hop_by_hop_id: u32) {
+        self.header.hop_by_hop_id = hop_by_hop_id;
+    }
+
+    /// Returns a copy of the message with a new Hop-by-Hop Identifier, keeping
+    /// the End-to-End Identifier, flags and every AVP, e.g. to fan a request
+    /// out to several upstream peers.
+    pub fn clone_with_new_hop_by_hop(&self, hop_by_hop_id: u32) -> DiameterMessage {
+        let mut message = self.clone();
+        message.set_hop_by_hop_id(hop_by_hop_id);
+        message
+    }
+
     /// Retrieves the End-to-End Identifier from the message header.
     pub fn get_end_to_end_id(&self) -> u32 {
         self.header.end_to_end_id
     }
 
+    /// Returns the Vendor-Id and Experimental-Result-Code of the
+    /// Experimental-Result AVP, if present.
+    pub fn experimental_result(&self) -> Option<(u32, u32)> {
+        self.get_avp(EXPERIMENTAL_RESULT)?.get_experimental_result()
+    }
+
+    /// Returns the Result-Code (268) AVP as a `ResultCode`, or `None` if it is
+    /// absent or not one of the values defined in RFC 6733.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        ResultCode::from_u32(self.get_avp(RESULT_CODE)?.get_unsigned32()?)
+    }
+
+    /// Wraps the given AVP in a Failed-AVP (279) grouped AVP and appends it
+    /// to the message, e.g. to report the offending AVP in an error answer.
+    pub fn add_failed_avp(&mut self, avp: Avp) {
+        self.add_avp(Avp::new(
+            FAILED_AVP,
+            None,
+            M,
+            Grouped::new(vec![avp]).into(),
+        ));
+    }
+
+    /// Returns the AVPs embedded in all Failed-AVP AVPs of the message.
+    pub fn failed_avps(&self) -> Vec<&Avp> {
+        self.avps
+            .iter()
+            .filter(|avp| avp.get_code() == FAILED_AVP)
+            .filter_map(|avp| avp.get_grouped())
+            .flat_map(|group| group.avps())
+            .collect()
+    }
+
+    /// Appends a Proxy-Info AVP with the given Proxy-Host and Proxy-State.
+    pub fn push_proxy_info(&mut self, host: &str, state: &[u8]) {
+        self.add_avp(ProxyInfo::new(host, state).to_avp());
+    }
+
+    /// Returns all Proxy-Info AVPs of the message, in the order they appear.
+    pub fn proxy_info(&self) -> Result<Vec<ProxyInfo>> {
+        self.avps
+            .iter()
+            .filter(|avp| avp.get_code() == proxy::PROXY_INFO)
+            .map(ProxyInfo::from_avp)
+            .collect()
+    }
+
+    /// Validates the AVPs of the message against the default dictionary.
+    ///
+    /// Returns a `ValidationError` if an Enumerated AVP carries a value
+    /// not allowed by its dictionary definition.
+    pub fn validate(&self) -> Result<()> {
+        let dict = dictionary::DEFAULT_DICT.read().unwrap();
+        self.avps.iter().try_for_each(|avp| avp.validate(&dict))
+    }
+
+    /// Decodes only the 20-byte header of a Diameter message, leaving the
+    /// reader positioned at the first AVP.
+    ///
+    /// Lets a router inspect the command code, application id and length of a
+    /// message before, or instead of, decoding its AVPs.
+    pub fn decode_header<R: Read>(reader: &mut R) -> Result<DiameterHeader> {
+        DiameterHeader::decode_from(reader)
+    }
+
     /// Decodes a Diameter message from the given byte slice.
     pub fn decode_from<R: Read + Seek>(reader: &mut R) -> Result<DiameterMessage> {
-        let header = DiameterHeader::decode_from(reader)?;
+        DiameterMessage::decode_from_with(reader, &DecodeOptions::default())
+    }
+
+    /// Decodes a Diameter message using the given `DecodeOptions`.
+    ///
+    /// With `strict: false`, AVPs unknown to the dictionary are preserved as raw
+    /// bytes so the message can be relayed unchanged.
+    pub fn decode_from_with<R: Read + Seek>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<DiameterMessage> {
+        DiameterMessage::decode_inner(reader, options, None)
+    }
+
+    fn decode_inner<R: Read + Seek>(
+        reader: &mut R,
+        options: &DecodeOptions,
+        shared: Option<&Bytes>,
+    ) -> Result<DiameterMessage> {
+        let mut header = DiameterHeader::decode_from(reader)?;
         let mut avps = Vec::new();
 
         let total_length = header.length;
         let mut offset = HEADER_LENGTH;
+        let mut padded_length = HEADER_LENGTH;
         while offset < total_length {
-            let avp = Avp::decode_from(reader)?;
-            offset += avp.get_length();
-            offset += avp.get_padding() as u32;
+            let remaining = total_length - offset;
+            let avp = Avp::decode_inner(reader, options, shared, Some(remaining))?;
+            // The header length ends the message, even inside the last AVP's padding
+            offset += (avp.get_length() + avp.get_padding() as u32).min(remaining);
+            padded_length += avp.get_length() + avp.get_padding() as u32;
             avps.push(avp);
         }
 
@@ -192,41 +559,272 @@ impl DiameterMessage {
             ));
         }
 
+        // Counts a missing final padding, which re-encoding writes
+        header.length = padded_length;
         Ok(DiameterMessage { header, avps })
     }
 
     /// Encodes the Diameter message to the given writer.
+    ///
+    /// The message is encoded into a buffer first, and nothing is written if
+    /// it fails with an `EncodeError`, e.g. because the encoded bytes differ
+    /// from the length in the header, which would desync the peer's framing.
     pub fn encode_to<W: Write>(&self, writer: &mut W) -> Result<()> {
-        self.header.encode_to(writer)?;
+        let mut buf = BytesMut::with_capacity(self.header.length as usize);
+        self.encode_into(&mut buf)?;
+        writer.write_all(&buf)?;
+        Ok(())
+    }
 
-        for avp in &self.avps {
-            avp.encode_to(writer)?;
+    // Appends the encoded message to `buf`. If the encoded bytes differ from
+    // the length in the header, or the length does not fit its 24 bits, fails
+    // with an `EncodeError` and leaves `buf` as it was.
+    pub(crate) fn encode_into(&self, buf: &mut BytesMut) -> Result<()> {
+        let length = self.header.length;
+        if length > 0xFFFFFF {
+            return Err(Error::EncodeError(format!(
+                "message length {} exceeds the 24-bit length field",
+                length
+            )));
+        }
+
+        let start = buf.len();
+        if let Err(e) = self.encode_avps_into(buf) {
+            buf.truncate(start);
+            return Err(e);
+        }
+        let written = buf.len() - start;
+        if written != length as usize {
+            buf.truncate(start);
+            return Err(Error::EncodeError(format!(
+                "encoded {} bytes but the message length is {}",
+                written, length
+            )));
         }
+        Ok(())
+    }
 
+    fn encode_avps_into(&self, buf: &mut BytesMut) -> Result<()> {
+        let mut writer = buf.writer();
+        self.header.encode_to(&mut writer)?;
+        for avp in &self.avps {
+            avp.encode_to(&mut writer)?;
+        }
         Ok(())
     }
 
+    /// Decodes a Diameter message from a byte slice.
+    ///
+    /// Returns an error if the slice contains bytes beyond the declared message length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DiameterMessage> {
+        DiameterMessage::from_slice(bytes, &DecodeOptions::default(), None)
+    }
+
+    /// Decodes a Diameter message from its hex representation, e.g. as copied
+    /// from Wireshark.
+    ///
+    /// Whitespace is ignored, and each whitespace-separated group of digits
+    /// may start with `0x`, so both `0100002c...` and `0x01 0x00 0x00 0x2c ...`
+    /// are accepted.
+    pub fn from_hex(hex: &str) -> Result<DiameterMessage> {
+        let digits: String = hex
+            .split_whitespace()
+            .map(|group| {
+                group
+                    .strip_prefix("0x")
+                    .or_else(|| group.strip_prefix("0X"))
+                    .unwrap_or(group)
+            })
+            .collect();
+        let bytes = hex::decode(&digits)?;
+        DiameterMessage::from_bytes(&bytes)
+    }
+
+    /// Decodes a Diameter message from a shared buffer without copying
+    /// OctetString and raw AVP payloads, which are kept as slices of `bytes`.
+    ///
+    /// Intended for relays that mostly forward messages: the decoded message
+    /// holds a reference to `bytes` for as long as those AVPs are alive.
+    pub fn decode_from_shared(bytes: &Bytes, options: &DecodeOptions) -> Result<DiameterMessage> {
+        DiameterMessage::from_slice(bytes, options, Some(bytes))
+    }
+
+    fn from_slice(
+        bytes: &[u8],
+        options: &DecodeOptions,
+        shared: Option<&Bytes>,
+    ) -> Result<DiameterMessage> {
+        if bytes.len() >= 4 {
+            let length = u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]) as usize;
+            if bytes.len() < length {
+                return Err(Error::DecodeError(
+                    "invalid diameter message, length mismatch".into(),
+                ));
+            }
+        }
+
+        let mut cursor = Cursor::new(bytes);
+        let message = DiameterMessage::decode_inner(&mut cursor, options, shared)?;
+        if cursor.position() != bytes.len() as u64 {
+            return Err(Error::DecodeError(
+                "invalid diameter message, trailing bytes".into(),
+            ));
+        }
+        Ok(message)
+    }
+
+    /// Encodes the Diameter message into a new byte vector.
+    ///
+    /// Fails like `encode_to`, e.g. if the encoded bytes differ from the
+    /// length in the header.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = BytesMut::with_capacity(self.header.length as usize);
+        self.encode_into(&mut buf)?;
+        Ok(buf.into())
+    }
+
+    /// Encodes the message as a string of lowercase hex digits.
+    pub fn to_hex(&self) -> Result<String> {
+        Ok(hex::encode(&self.to_bytes()?))
+    }
+
     fn fmt(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
-        let indent = "  ".repeat(depth.max(0));
+        let indent = "  ".repeat(depth);
         self.header.fmt(f, depth)?;
-        write!(f, "\n")?;
-        write!(
+        writeln!(f)?;
+        writeln!(
             f,
-            "  {}{:<40} {:>8} {:>5}  {} {} {}  {:<16}  {}\n",
-            indent, "AVP", "Vendor", "Code", "V", "M", "P", "Type", "Value"
+            "  {}AVP                                        Vendor  Code  V M P  Type              Value",
+            indent
         )?;
 
         for avp in &self.avps {
             avp.fmt(f, depth)?;
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
     }
 }
 
+impl DiameterMessageBuilder {
+    /// Sets the command flags.
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.message.header.flags = flags;
+        self
+    }
+
+    /// Sets the Hop-by-Hop Identifier.
+    pub fn hop_by_hop_id(mut self, hop_by_hop_id: u32) -> Self {
+        self.message.header.hop_by_hop_id = hop_by_hop_id;
+        self
+    }
+
+    /// Sets the End-to-End Identifier.
+    pub fn end_to_end_id(mut self, end_to_end_id: u32) -> Self {
+        self.message.header.end_to_end_id = end_to_end_id;
+        self
+    }
+
+    /// Adds an AVP to the message.
+    pub fn avp(mut self, avp: Avp) -> Self {
+        self.message.add_avp(avp);
+        self
+    }
+
+    /// Adds AVPs to the message, in iteration order.
+    pub fn avps<I: IntoIterator<Item = Avp>>(mut self, avps: I) -> Self {
+        self.message.add_avps(avps);
+        self
+    }
+
+    /// Adds the Origin-Host (264) and Origin-Realm (296) AVPs.
+    pub fn origin(self, host: &str, realm: &str) -> Self {
+        self.avp(Avp::new(ORIGIN_HOST, None, M, Identity::new(host).into()))
+            .avp(Avp::new(ORIGIN_REALM, None, M, Identity::new(realm).into()))
+    }
+
+    /// Adds the Destination-Host (293) AVP.
+    pub fn destination_host(self, host: &str) -> Self {
+        self.avp(Avp::new(
+            DESTINATION_HOST,
+            None,
+            M,
+            Identity::new(host).into(),
+        ))
+    }
+
+    /// Adds the Destination-Realm (283) AVP.
+    pub fn destination_realm(self, realm: &str) -> Self {
+        self.avp(Avp::new(
+            DESTINATION_REALM,
+            None,
+            M,
+            Identity::new(realm).into(),
+        ))
+    }
+
+    /// Adds the Origin-State-Id (278) AVP.
+    pub fn origin_state_id(self, origin_state_id: u32) -> Self {
+        self.avp(Avp::new(
+            ORIGIN_STATE_ID,
+            None,
+            M,
+            Unsigned32::new(origin_state_id).into(),
+        ))
+    }
+
+    /// Returns the built `DiameterMessage`.
+    pub fn build(self) -> DiameterMessage {
+        self.message
+    }
+}
+
 impl DiameterHeader {
+    /// Retrieves the protocol version.
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    /// Retrieves the message length, including the header and AVPs.
+    pub fn get_length(&self) -> u32 {
+        self.length
+    }
+
+    /// Retrieves the command flags.
+    pub fn get_flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Retrieves the command code.
+    pub fn get_command_code(&self) -> CommandCode {
+        self.code
+    }
+
+    /// Retrieves the application ID.
+    pub fn get_application_id(&self) -> ApplicationId {
+        self.application_id
+    }
+
+    /// Retrieves the Hop-by-Hop Identifier.
+    pub fn get_hop_by_hop_id(&self) -> u32 {
+        self.hop_by_hop_id
+    }
+
+    /// Retrieves the End-to-End Identifier.
+    pub fn get_end_to_end_id(&self) -> u32 {
+        self.end_to_end_id
+    }
+
+    pub(crate) fn set_length(&mut self, length: u32) {
+        self.length = length;
+    }
+
     /// Decodes a Diameter header from the given byte slice.
+    ///
+    /// The command code is the 24-bit field in bytes 5-7, after the flags,
+    /// and the application id the 32-bit field in bytes 8-11, both in
+    /// network byte order.
     pub fn decode_from<R: Read>(reader: &mut R) -> Result<DiameterHeader> {
         let mut b = [0; HEADER_LENGTH as usize];
         reader.read_exact(&mut b)?;
@@ -238,6 +836,9 @@ impl DiameterHeader {
         }
 
         let version = b[0];
+        if version != 1 {
+            return Err(Error::DecodeError("unsupported Diameter version".into()));
+        }
         let length = u32::from_be_bytes([0, b[1], b[2], b[3]]);
         let flags = b[4];
 
@@ -246,12 +847,9 @@ impl DiameterHeader {
         let hop_by_hop_id = u32::from_be_bytes([b[12], b[13], b[14], b[15]]);
         let end_to_end_id = u32::from_be_bytes([b[16], b[17], b[18], b[19]]);
 
-        let code = CommandCode::from_u32(code)
-            .ok_or_else(|| Error::DecodeError(format!("unknown command code: {}", code).into()))?;
+        let code = CommandCode::from_u32(code);
 
-        let application_id = ApplicationId::from_u32(application_id).ok_or_else(|| {
-            Error::DecodeError(format!("unknown application id: {}", application_id).into())
-        })?;
+        let application_id = ApplicationId::from_u32(application_id);
 
         Ok(DiameterHeader {
             version,
@@ -265,7 +863,7 @@ impl DiameterHeader {
     }
 
     fn fmt(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
-        let indent = "  ".repeat(depth.max(0));
+        let indent = "  ".repeat(depth);
         let request_flag = if self.flags & flags::REQUEST != 0 {
             "Request"
         } else {
@@ -293,9 +891,9 @@ impl DiameterHeader {
             indent,
             self.version,
             self.code,
-            self.code as u32,
+            self.code.as_u32(),
             self.application_id,
-            self.application_id as u32,
+            self.application_id.as_u32(),
             request_flag,
             error_flag,
             proxyable_flag,
@@ -318,12 +916,12 @@ impl DiameterHeader {
         writer.write_all(&[self.flags])?;
 
         // Code
-        let code = self.code as u32;
+        let code = self.code.as_u32();
         let code_bytes = &code.to_be_bytes()[1..4];
         writer.write_all(code_bytes)?;
 
         // Application-ID
-        let application_id = self.application_id as u32;
+        let application_id = self.application_id.as_u32();
         writer.write_all(&application_id.to_be_bytes())?;
 
         // Hop-by-Hop Identifier and End-to-End Identifier
@@ -335,16 +933,90 @@ impl DiameterHeader {
 }
 
 impl CommandCode {
+    /// Returns the command code for a raw value, or `Unknown` if it has no
+    /// named variant.
+    pub fn from_u32(code: u32) -> CommandCode {
+        match code {
+            0 => CommandCode::Error,
+            257 => CommandCode::CapabilitiesExchange,
+            280 => CommandCode::DeviceWatchdog,
+            282 => CommandCode::DisconnectPeer,
+            258 => CommandCode::ReAuth,
+            275 => CommandCode::SessionTerminate,
+            274 => CommandCode::AbortSession,
+            272 => CommandCode::CreditControl,
+            8388635 => CommandCode::SpendingLimit,
+            8388636 => CommandCode::SpendingStatusNotification,
+            271 => CommandCode::Accounting,
+            265 => CommandCode::AA,
+            code => CommandCode::Unknown(code),
+        }
+    }
+
     /// Returns the command code as a u32.
-    pub fn from_u32(code: u32) -> Option<CommandCode> {
-        FromPrimitive::from_u32(code)
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            CommandCode::Error => 0,
+            CommandCode::CapabilitiesExchange => 257,
+            CommandCode::DeviceWatchdog => 280,
+            CommandCode::DisconnectPeer => 282,
+            CommandCode::ReAuth => 258,
+            CommandCode::SessionTerminate => 275,
+            CommandCode::AbortSession => 274,
+            CommandCode::CreditControl => 272,
+            CommandCode::SpendingLimit => 8388635,
+            CommandCode::SpendingStatusNotification => 8388636,
+            CommandCode::Accounting => 271,
+            CommandCode::AA => 265,
+            CommandCode::Unknown(code) => *code,
+        }
     }
 }
 
 impl ApplicationId {
+    /// Returns the application ID for a raw value, or `Unknown` if it has no
+    /// named variant.
+    pub fn from_u32(application_id: u32) -> ApplicationId {
+        match application_id {
+            0 => ApplicationId::Common,
+            3 => ApplicationId::Accounting,
+            4 => ApplicationId::CreditControl,
+            16777238 => ApplicationId::Gx,
+            16777236 => ApplicationId::Rx,
+            16777302 => ApplicationId::Sy,
+            application_id => ApplicationId::Unknown(application_id),
+        }
+    }
+
     /// Returns the application ID as a u32.
-    pub fn from_u32(application_id: u32) -> Option<ApplicationId> {
-        FromPrimitive::from_u32(application_id)
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            ApplicationId::Common => 0,
+            ApplicationId::Accounting => 3,
+            ApplicationId::CreditControl => 4,
+            ApplicationId::Gx => 16777238,
+            ApplicationId::Rx => 16777236,
+            ApplicationId::Sy => 16777302,
+            ApplicationId::Unknown(application_id) => *application_id,
+        }
+    }
+}
+
+impl IntoIterator for DiameterMessage {
+    type Item = Avp;
+    type IntoIter = std::vec::IntoIter<Avp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.avps.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DiameterMessage {
+    type Item = &'a Avp;
+    type IntoIter = std::slice::Iter<'a, Avp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.avps.iter()
     }
 }
 
@@ -373,12 +1045,13 @@ impl fmt::Display for ApplicationId {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::avp;
     use crate::avp::enumerated::Enumerated;
     use crate::avp::flags::M;
     use crate::avp::group::Grouped;
     use crate::avp::identity::Identity;
+    use crate::avp::octetstring::OctetString;
     use crate::avp::unsigned32::Unsigned32;
     use crate::avp::utf8string::UTF8String;
     use crate::avp::AvpValue;
@@ -386,6 +1059,22 @@ mod tests {
     use super::*;
     use std::io::Cursor;
 
+    // A CCR with Origin-Host, Session-Id and CC-Request-Number, both ids set
+    // to `seq_num`. Shared by the codec, stream and transport tests.
+    pub(crate) fn ccr(seq_num: u32) -> DiameterMessage {
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            seq_num,
+            seq_num,
+        );
+        ccr.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
+        ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+        ccr
+    }
+
     #[test]
     fn test_decode_encode_header() {
         let data = [
@@ -413,6 +1102,41 @@ mod tests {
         assert_eq!(encoded, data);
     }
 
+    #[test]
+    fn test_decode_ccr_header() {
+        // A Gy CCR header, with the R and P flags set, and a Session-Id
+        let data = [
+            0x01, 0x00, 0x00, 0x28, // version, length
+            0xc0, 0x00, 0x01, 0x10, // flags, code
+            0x00, 0x00, 0x00, 0x04, // application_id
+            0x42, 0xf1, 0x8a, 0x53, // hop_by_hop_id
+            0xb8, 0xe9, 0x5c, 0x1b, // end_to_end_id
+            0x00, 0x00, 0x01, 0x07, // avp code
+            0x40, 0x00, 0x00, 0x14, // flags, length
+            0x73, 0x65, 0x73, 0x3b, // value
+            0x31, 0x32, 0x33, 0x34, //
+            0x35, 0x38, 0x38, 0x38, //
+        ];
+
+        let message = DiameterMessage::decode_from(&mut Cursor::new(&data)).unwrap();
+        let header = message.get_header();
+        assert_eq!(header.get_version(), 1);
+        assert_eq!(header.get_length(), 40);
+        assert_eq!(header.get_flags(), flags::REQUEST | flags::PROXYABLE);
+        assert_eq!(header.get_command_code(), CommandCode::CreditControl);
+        assert_eq!(header.get_command_code().as_u32(), 272);
+        assert_eq!(header.get_application_id(), ApplicationId::CreditControl);
+        assert_eq!(header.get_application_id().as_u32(), 4);
+        assert_eq!(header.get_hop_by_hop_id(), 1123125843);
+        assert_eq!(header.get_end_to_end_id(), 3102301211);
+
+        // A command code above 16 bits must not lose its high byte
+        let mut data = data;
+        data[5] = 0x01;
+        let header = DiameterMessage::decode_header(&mut &data[..]).unwrap();
+        assert_eq!(header.get_command_code().as_u32(), 0x010110);
+    }
+
     #[test]
     fn test_decode_encode_diameter_message() {
         let data = [
@@ -440,9 +1164,9 @@ mod tests {
         let avp0 = &avps[0];
         assert_eq!(avp0.get_code(), 415);
         assert_eq!(avp0.get_length(), 12);
-        assert_eq!(avp0.get_flags().vendor, false);
-        assert_eq!(avp0.get_flags().mandatory, true);
-        assert_eq!(avp0.get_flags().private, false);
+        assert!(!avp0.get_flags().vendor);
+        assert!(avp0.get_flags().mandatory);
+        assert!(!avp0.get_flags().private);
         assert_eq!(avp0.get_vendor_id(), None);
         match avp0.get_value() {
             AvpValue::Unsigned32(ref v) => assert_eq!(v.value(), 1200),
@@ -451,9 +1175,9 @@ mod tests {
         let avp1 = &avps[1];
         assert_eq!(avp1.get_code(), 30);
         assert_eq!(avp1.get_length(), 18);
-        assert_eq!(avp1.get_flags().vendor, false);
-        assert_eq!(avp1.get_flags().mandatory, false);
-        assert_eq!(avp1.get_flags().private, false);
+        assert!(!avp1.get_flags().vendor);
+        assert!(!avp1.get_flags().mandatory);
+        assert!(!avp1.get_flags().private);
         assert_eq!(avp1.get_vendor_id(), None);
         match avp1.get_value() {
             AvpValue::UTF8String(ref v) => assert_eq!(v.value(), "foobar1234"),
@@ -520,4 +1244,958 @@ mod tests {
         let message = DiameterMessage::decode_from(&mut cursor).unwrap();
         println!("diameter message: {}", message);
     }
+
+    #[test]
+    fn test_octetstring_padding() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![1, 2, 3, 4, 5])));
+        message.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+
+        let class = message.get_avp(25).unwrap();
+        assert_eq!(class.get_length(), 13);
+        assert_eq!(class.get_padding(), 3);
+
+        let mut encoded = Vec::new();
+        message.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded.len() as u32, message.get_length());
+        assert_eq!(encoded.len(), 20 + 16 + 12);
+
+        // 8 bytes of AVP header, 5 bytes of data, then 3 pad bytes
+        assert_eq!(&encoded[28..33], &[1, 2, 3, 4, 5]);
+        assert_eq!(&encoded[33..36], &[0, 0, 0]);
+        assert_eq!(&encoded[36..40], &[0x00, 0x00, 0x01, 0x9F]);
+
+        let mut cursor = Cursor::new(&encoded);
+        let message = DiameterMessage::decode_from(&mut cursor).unwrap();
+        let avps = message.get_avps();
+        assert_eq!(avps.len(), 2);
+        assert_eq!(avps[0].get_octetstring().unwrap().value(), &[1, 2, 3, 4, 5]);
+        assert_eq!(avps[1].get_code(), 415);
+        assert_eq!(avps[1].get_unsigned32(), Some(1000));
+    }
+
+    #[test]
+    fn test_decode_ignores_padding_value() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![1, 2, 3, 4, 5])));
+        message.push_proxy_info("proxy", b"abc");
+        message.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+
+        let mut encoded = message.to_bytes().unwrap();
+        assert_eq!(encoded.len(), 84);
+
+        // Pad bytes of Class, and of Proxy-Host and Proxy-State inside Proxy-Info
+        for range in [33..36, 57..60, 71..72] {
+            assert!(encoded[range.clone()].iter().all(|b| *b == 0));
+            encoded[range].fill(0xFF);
+        }
+
+        let decoded = DiameterMessage::from_bytes(&encoded).unwrap();
+        let avps = decoded.get_avps();
+        assert_eq!(avps.len(), 3);
+        assert_eq!(avps[0].get_octetstring().unwrap().value(), &[1, 2, 3, 4, 5]);
+        let proxy_info = decoded.proxy_info().unwrap();
+        assert_eq!(proxy_info[0].host, "proxy");
+        assert_eq!(proxy_info[0].state, b"abc");
+        assert_eq!(avps[2].get_unsigned32(), Some(1000));
+
+        let shared = Bytes::from(encoded);
+        let decoded =
+            DiameterMessage::decode_from_shared(&shared, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.get_avps()[2].get_unsigned32(), Some(1000));
+
+        // Re-encoding writes zero padding
+        assert_eq!(decoded.to_bytes().unwrap(), message.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_decode_final_avp_padding() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![1, 2, 3, 4, 5])));
+        let padded = message.to_bytes().unwrap();
+        assert_eq!(padded.len(), 20 + 12 + 16);
+
+        // The final AVP with its padding, and without it as some peers send it
+        let mut unpadded = padded[..padded.len() - 3].to_vec();
+        let len = unpadded.len() as u32;
+        unpadded[1..4].copy_from_slice(&len.to_be_bytes()[1..4]);
+
+        for bytes in [&padded, &unpadded] {
+            // Followed by another message, which must not be read into
+            let mut stream = bytes.clone();
+            stream.extend_from_slice(&padded);
+            let mut cursor = Cursor::new(&stream);
+
+            let first = DiameterMessage::decode_from(&mut cursor).unwrap();
+            assert_eq!(cursor.position(), bytes.len() as u64);
+            let avps = first.get_avps();
+            assert_eq!(avps.len(), 2);
+            assert_eq!(avps[1].get_octetstring().unwrap().value(), &[1, 2, 3, 4, 5]);
+
+            let second = DiameterMessage::decode_from(&mut cursor).unwrap();
+            assert_eq!(second.get_avps().len(), 2);
+        }
+
+        // Re-encoding restores the padding
+        let decoded = DiameterMessage::from_bytes(&unpadded).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), padded);
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1123158611,
+            3102381851,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;1")));
+        message.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+
+        let hex = message.to_hex().unwrap();
+        assert_eq!(
+            hex,
+            "01000030800001100000000442f20a53b8ea971b\
+             000001074000000d7365733b31000000\
+             0000019f4000000c000003e8"
+        );
+        let decoded = DiameterMessage::from_hex(&hex).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), message.to_bytes().unwrap());
+
+        // As copied from a packet capture
+        let capture = "0x01 0x00 0x00 0x30 0x80 0x00 0x01 0x10 0x00 0x00 0x00 0x04
+            42f20a53 b8ea971b
+            00000107 4000000d 7365733b 31000000
+            0X0000019F 4000000C 000003E8";
+        let decoded = DiameterMessage::from_hex(capture).unwrap();
+        assert_eq!(decoded.get_hop_by_hop_id(), 1123158611);
+        assert_eq!(decoded.get_avp(415).unwrap().get_unsigned32(), Some(1000));
+
+        match DiameterMessage::from_hex("0100002") {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "invalid hex, odd number of digits 7")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+        match DiameterMessage::from_hex("01zz") {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "invalid hex digits at offset 2"),
+            _ => panic!("Expected a DecodeError"),
+        }
+        assert!(DiameterMessage::from_hex("01é0").is_err());
+    }
+
+    #[test]
+    fn test_validate_enumerated() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+        message.add_avp(avp!(
+            456,
+            None,
+            M,
+            Grouped::new(vec![avp!(416, None, M, Enumerated::new(2))])
+        ));
+        assert!(message.validate().is_ok());
+
+        message.add_avp(avp!(
+            456,
+            None,
+            M,
+            Grouped::new(vec![avp!(416, None, M, Enumerated::new(9))])
+        ));
+        match message.validate() {
+            Err(Error::ValidationError(msg)) => {
+                assert_eq!(msg, "invalid value 9 for enumerated AVP CC-Request-Type")
+            }
+            _ => panic!("Expected a ValidationError"),
+        }
+    }
+
+    #[test]
+    fn test_from_to_bytes() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1234,
+            5678,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes.len() as u32, message.get_length());
+
+        let decoded = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get_hop_by_hop_id(), 1234);
+        assert_eq!(decoded.get_end_to_end_id(), 5678);
+        assert_eq!(decoded.get_avps().len(), 2);
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        match DiameterMessage::from_bytes(&trailing) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "invalid diameter message, trailing bytes")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+
+    #[test]
+    fn test_builder_routing_avps() {
+        let message =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(10)
+                .end_to_end_id(20)
+                .origin("host.example.com", "realm.example.com")
+                .destination_host("server.example.com")
+                .destination_realm("server-realm.example.com")
+                .origin_state_id(7)
+                .build();
+
+        assert_eq!(message.get_flags(), flags::REQUEST);
+        assert_eq!(message.get_hop_by_hop_id(), 10);
+        assert_eq!(message.get_end_to_end_id(), 20);
+
+        let origin_host = message.get_avp(264).unwrap();
+        assert!(origin_host.get_flags().mandatory);
+        assert_eq!(
+            origin_host.get_identity().unwrap().value(),
+            "host.example.com"
+        );
+        let origin_realm = message.get_avp(296).unwrap();
+        assert_eq!(
+            origin_realm.get_identity().unwrap().value(),
+            "realm.example.com"
+        );
+        let destination_host = message.get_avp(293).unwrap();
+        assert_eq!(
+            destination_host.get_identity().unwrap().value(),
+            "server.example.com"
+        );
+        let destination_realm = message.get_avp(283).unwrap();
+        assert_eq!(
+            destination_realm.get_identity().unwrap().value(),
+            "server-realm.example.com"
+        );
+        assert_eq!(message.get_avp(278).unwrap().get_unsigned32(), Some(7));
+
+        let bytes = message.to_bytes().unwrap();
+        let decoded = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get_avps().len(), 5);
+    }
+
+    #[test]
+    fn test_to_answer() {
+        let mut request =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST | flags::PROXYABLE)
+                .hop_by_hop_id(1123158610)
+                .end_to_end_id(3102381851)
+                .avp(avp!(263, None, M, UTF8String::new("ses;12345888")))
+                .origin("client.example.com", "client-realm.example.com")
+                .destination_realm("server-realm.example.com")
+                .build();
+        request.push_proxy_info("proxy.example.com", b"state");
+
+        let answer = request.to_answer();
+        assert_eq!(answer.get_command_code(), CommandCode::CreditControl);
+        assert_eq!(answer.get_application_id(), ApplicationId::CreditControl);
+        assert_eq!(answer.get_hop_by_hop_id(), 1123158610);
+        assert_eq!(answer.get_end_to_end_id(), 3102381851);
+        assert_eq!(answer.get_flags() & flags::REQUEST, 0);
+        assert_eq!(answer.get_flags(), flags::PROXYABLE);
+
+        assert_eq!(answer.get_avps().len(), 2);
+        assert_eq!(
+            answer
+                .get_avp(263)
+                .unwrap()
+                .get_utf8string()
+                .unwrap()
+                .value(),
+            "ses;12345888"
+        );
+        assert!(answer.get_avp(264).is_none());
+        assert_eq!(answer.proxy_info().unwrap().len(), 1);
+        assert_eq!(answer.get_length(), answer.to_bytes().unwrap().len() as u32);
+    }
+
+    #[test]
+    fn test_get_avps_by_code() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(282, None, M, Identity::new("relay1.example.com")));
+        message.add_avp(avp!(282, None, M, Identity::new("relay2.example.com")));
+        message.add_avp(avp!(1032, Some(10415), M, Enumerated::new(1004)));
+
+        let session_id = message.get_avp(263).unwrap();
+        assert_eq!(session_id.get_utf8string().unwrap().value(), "ses;12345888");
+
+        let route_records = message.get_avps_by_code(282);
+        assert_eq!(route_records.len(), 2);
+        assert_eq!(
+            route_records[0].get_identity().unwrap().value(),
+            "relay1.example.com"
+        );
+        assert_eq!(
+            route_records[1].get_identity().unwrap().value(),
+            "relay2.example.com"
+        );
+        assert!(message.get_avps_by_code(999).is_empty());
+
+        let rat_type = message.get_avp_by_vendor(10415, 1032).unwrap();
+        assert_eq!(rat_type.get_enumerated().unwrap().value(), 1004);
+        assert!(message.get_avp_by_vendor(10415, 263).is_none());
+    }
+
+    #[test]
+    fn test_set_flags() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+
+        message.set_proxiable(true);
+        assert_eq!(message.get_flags(), flags::REQUEST | flags::PROXYABLE);
+        message.set_error(true);
+        assert_eq!(
+            message.get_flags(),
+            flags::REQUEST | flags::PROXYABLE | flags::ERROR
+        );
+        message.set_retransmit(true);
+        assert_eq!(
+            message.get_flags(),
+            flags::REQUEST | flags::PROXYABLE | flags::ERROR | flags::RETRANSMIT
+        );
+
+        message.set_proxiable(false);
+        assert_eq!(
+            message.get_flags(),
+            flags::REQUEST | flags::ERROR | flags::RETRANSMIT
+        );
+        message.set_error(false);
+        assert_eq!(message.get_flags(), flags::REQUEST | flags::RETRANSMIT);
+        message.set_retransmit(false);
+        assert_eq!(message.get_flags(), flags::REQUEST);
+
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes[4], flags::REQUEST);
+    }
+
+    // Built with `--no-default-features`, where only the synchronous API is available.
+    #[cfg(not(feature = "transport"))]
+    #[test]
+    fn test_sync_only_round_trip() {
+        let ccr =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(1)
+                .end_to_end_id(1)
+                .avp(avp!(263, None, M, UTF8String::new("ses;12345888")))
+                .origin("host.example.com", "realm.example.com")
+                .build();
+
+        let mut encoded = Vec::new();
+        ccr.encode_to(&mut encoded).unwrap();
+        let mut cursor = Cursor::new(&encoded);
+        let decoded = DiameterMessage::decode_from(&mut cursor).unwrap();
+
+        assert_eq!(decoded.get_hop_by_hop_id(), 1);
+        assert_eq!(decoded.get_avps().len(), 3);
+        assert_eq!(
+            decoded.get_avp(263).unwrap().as_string(),
+            Some("ses;12345888")
+        );
+    }
+
+    #[test]
+    fn test_experimental_result() {
+        let mut cca = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+        );
+        cca.add_avp(Avp::experimental_result(10415, 5030));
+
+        let avp = cca.get_avp(297).unwrap();
+        assert!(avp.get_flags().mandatory);
+        let children = avp.get_grouped().unwrap().avps();
+        assert!(children.iter().all(|avp| avp.get_flags().mandatory));
+
+        let bytes = cca.to_bytes().unwrap();
+        let cca = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(cca.experimental_result(), Some((10415, 5030)));
+        assert_eq!(
+            cca.get_avp(297).unwrap().as_grouped().unwrap().avps().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        let data = [
+            0x02, 0x00, 0x00, 0x14, // version 2, length
+            0x80, 0x00, 0x01, 0x10, // flags, code
+            0x00, 0x00, 0x00, 0x04, // application_id
+            0x00, 0x00, 0x00, 0x03, // hop_by_hop_id
+            0x00, 0x00, 0x00, 0x04, // end_to_end_id
+        ];
+
+        match DiameterMessage::from_bytes(&data) {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "unsupported Diameter version"),
+            _ => panic!("Expected a DecodeError"),
+        }
+
+        let mut data = data;
+        data[0] = 0x01;
+        let message = DiameterMessage::from_bytes(&data).unwrap();
+        assert_eq!(message.get_version(), 1);
+    }
+
+    #[test]
+    fn test_decode_length_mismatch() {
+        let mut data = vec![
+            0x01, 0x00, 0x00, 0x34, // version, length
+            0x80, 0x00, 0x01, 0x10, // flags, code
+            0x00, 0x00, 0x00, 0x04, // application_id
+            0x00, 0x00, 0x00, 0x03, // hop_by_hop_id
+            0x00, 0x00, 0x00, 0x04, // end_to_end_id
+            0x00, 0x00, 0x01, 0x9F, // avp code
+            0x40, 0x00, 0x00, 0x0C, // flags, length
+            0x00, 0x00, 0x04, 0xB0, // value
+            0x00, 0x00, 0x00, 0x1E, // avp code
+            0x00, 0x00, 0x00, 0x12, // flags, length
+            0x66, 0x6F, 0x6F, 0x62, // value
+            0x61, 0x72, 0x31, 0x32, // value
+            0x33, 0x34, 0x00, 0x00,
+        ];
+        let message = DiameterMessage::from_bytes(&data).unwrap();
+        assert_eq!(message.wire_length(), 0x34);
+        assert_eq!(message.wire_length(), message.get_length());
+
+        // Declared length ends in the middle of the second AVP
+        data[3] = 0x30;
+        match DiameterMessage::from_bytes(&data) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(
+                    msg,
+                    "invalid length 18 for AVP 30, exceeds the remaining 16 bytes"
+                )
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+
+        // Declared length is longer than the available bytes
+        data[3] = 0x40;
+        match DiameterMessage::from_bytes(&data) {
+            Err(Error::DecodeError(msg)) => {
+                assert_eq!(msg, "invalid diameter message, length mismatch")
+            }
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+
+    #[test]
+    fn test_iterate_avps() {
+        let message =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .avp(avp!(263, None, M, UTF8String::new("ses;12345888")))
+                .origin("host.example.com", "realm.example.com")
+                .avp(avp!(415, None, M, Unsigned32::new(1000)))
+                .build();
+
+        assert_eq!(message.avps().count(), 4);
+        assert_eq!(
+            message
+                .avps()
+                .filter(|avp| avp.as_string().is_some())
+                .count(),
+            3
+        );
+
+        let mut count = 0;
+        for avp in &message {
+            assert!(avp.get_flags().mandatory);
+            count += 1;
+        }
+        assert_eq!(count, 4);
+
+        let codes: Vec<u32> = message.into_iter().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 264, 296, 415]);
+    }
+
+    #[test]
+    fn test_failed_avp() {
+        let mut answer = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::ERROR,
+            1,
+            1,
+        );
+        answer.add_avp(avp!(268, None, M, Unsigned32::new(5004)));
+        answer.add_failed_avp(avp!(416, None, M, Enumerated::new(1)));
+
+        let bytes = answer.to_bytes().unwrap();
+        let answer = DiameterMessage::from_bytes(&bytes).unwrap();
+
+        let failed = answer.failed_avps();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].get_code(), 416);
+        assert_eq!(failed[0].as_i32(), Some(1));
+    }
+
+    #[test]
+    fn test_decode_from_shared() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1234,
+            5678,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(25, None, M, OctetString::new(b"class".to_vec())));
+
+        let bytes = Bytes::from(message.to_bytes().unwrap());
+        let decoded =
+            DiameterMessage::decode_from_shared(&bytes, &DecodeOptions::default()).unwrap();
+
+        // The Class payload points into the shared buffer rather than a copy
+        let class = decoded.get_avp(25).unwrap().get_octetstring().unwrap();
+        assert_eq!(class.value(), b"class");
+        let offset = class.bytes().as_ptr() as usize - bytes.as_ptr() as usize;
+        assert_eq!(&bytes[offset..offset + 5], b"class");
+
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_add_values_repeated_unsigned32() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_values(
+            415,
+            None,
+            M,
+            vec![Unsigned32::new(1), Unsigned32::new(2), Unsigned32::new(3)],
+        );
+        assert_eq!(message.get_avps().len(), 3);
+
+        let bytes = message.to_bytes().unwrap();
+        let message = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(message.get_all_u32(415), vec![1, 2, 3]);
+        assert!(message.get_all_u32(416).is_empty());
+    }
+
+    #[test]
+    fn test_add_avps() {
+        let avps = vec![
+            Avp::new(263, None, M, UTF8String::new("ses;1").into()),
+            Avp::new(416, None, M, Enumerated::new(1).into()),
+            Avp::new(415, None, M, Unsigned32::new(0).into()),
+        ];
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avps(avps.clone());
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 416, 415]);
+
+        let built =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(1)
+                .end_to_end_id(1)
+                .avps(avps)
+                .build();
+        assert_eq!(built.get_length(), message.get_length());
+        assert_eq!(built.to_bytes().unwrap(), message.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_clone_with_new_hop_by_hop() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            1234,
+            5678,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+
+        let copy = message.clone_with_new_hop_by_hop(4321);
+        assert_eq!(copy.get_hop_by_hop_id(), 4321);
+        assert_eq!(copy.get_end_to_end_id(), 5678);
+        assert_eq!(copy.get_flags(), message.get_flags());
+        assert_eq!(copy.get_length(), message.get_length());
+
+        // Only the Hop-by-Hop Identifier (bytes 12..16) differs on the wire
+        let original = message.to_bytes().unwrap();
+        let copied = copy.to_bytes().unwrap();
+        assert_eq!(original[..12], copied[..12]);
+        assert_eq!(copied[12..16], 4321u32.to_be_bytes());
+        assert_eq!(original[16..], copied[16..]);
+    }
+
+    #[test]
+    fn test_decode_reports_avp_code() {
+        let data = [
+            0x01, 0x00, 0x00, 0x28, // version, length
+            0x80, 0x00, 0x01, 0x10, // flags, code
+            0x00, 0x00, 0x00, 0x04, // application_id
+            0x00, 0x00, 0x00, 0x03, // hop_by_hop_id
+            0x00, 0x00, 0x00, 0x04, // end_to_end_id
+            0x00, 0x00, 0x01, 0xBB, // avp code 443 (Subscription-Id)
+            0x40, 0x00, 0x00, 0x14, // flags, length
+            0x00, 0x00, 0x01, 0xC2, // avp code 450 (Subscription-Id-Type)
+            0x40, 0x00, 0x00, 0x0A, // flags, length too short for an Enumerated
+            0x00, 0x01, 0x00, 0x00, // value, padding
+        ];
+
+        let err = DiameterMessage::from_bytes(&data).unwrap_err();
+        match &err {
+            Error::AvpDecode {
+                code,
+                vendor_id,
+                offset,
+                source,
+            } => {
+                assert_eq!(*code, 443);
+                assert_eq!(*vendor_id, None);
+                assert_eq!(*offset, 20);
+                assert!(matches!(
+                    **source,
+                    Error::InvalidAvpLength { code: 450, .. }
+                ));
+            }
+            _ => panic!("Expected an AvpDecode error, got {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("Failed decoding AVP 443 at offset 20: "));
+    }
+
+    #[test]
+    fn test_decode_avp_length_beyond_message() {
+        let data = [
+            0x01, 0x00, 0x00, 0x20, // version, length
+            0x80, 0x00, 0x01, 0x10, // flags, code
+            0x00, 0x00, 0x00, 0x04, // application_id
+            0x00, 0x00, 0x00, 0x03, // hop_by_hop_id
+            0x00, 0x00, 0x00, 0x04, // end_to_end_id
+            0x00, 0x00, 0x00, 0x19, // avp code 25 (Class)
+            0x40, 0xFF, 0xFF, 0xFF, // flags, length far beyond the message
+            0x00, 0x00, 0x00, 0x00, // value
+        ];
+        match DiameterMessage::from_bytes(&data) {
+            Err(Error::DecodeError(msg)) => assert_eq!(
+                msg,
+                "invalid length 16777215 for AVP 25, exceeds the remaining 12 bytes"
+            ),
+            _ => panic!("Expected a DecodeError"),
+        }
+
+        let mut zero_length = data;
+        zero_length[25..28].copy_from_slice(&[0x00, 0x00, 0x00]);
+        assert!(matches!(
+            DiameterMessage::from_bytes(&zero_length),
+            Err(Error::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_header_only() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1234,
+            5678,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+        let bytes = message.to_bytes().unwrap();
+
+        let mut cursor = Cursor::new(&bytes);
+        let header = DiameterMessage::decode_header(&mut cursor).unwrap();
+        assert_eq!(cursor.position(), HEADER_LENGTH as u64);
+        assert_eq!(header.get_version(), 1);
+        assert_eq!(header.get_length(), bytes.len() as u32);
+        assert_eq!(header.get_flags(), flags::REQUEST);
+        assert_eq!(header.get_command_code(), CommandCode::CreditControl);
+        assert_eq!(header.get_application_id(), ApplicationId::CreditControl);
+        assert_eq!(header.get_hop_by_hop_id(), 1234);
+        assert_eq!(header.get_end_to_end_id(), 5678);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![1])));
+        message.add_avp(avp!(296, None, M, Identity::new("realm.example.com")));
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(25, None, M, OctetString::new(vec![2])));
+        let length = message.get_length();
+
+        message.canonicalize();
+
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 264, 25, 25, 296]);
+        let classes: Vec<&[u8]> = message
+            .get_avps_by_code(25)
+            .iter()
+            .filter_map(|avp| avp.get_octetstring())
+            .map(|value| value.value())
+            .collect();
+        assert_eq!(classes, vec![&[1][..], &[2][..]]);
+        assert_eq!(message.get_length(), length);
+    }
+
+    #[test]
+    fn test_command_code_round_trip() {
+        let codes = [
+            (CommandCode::Error, 0),
+            (CommandCode::CapabilitiesExchange, 257),
+            (CommandCode::ReAuth, 258),
+            (CommandCode::AA, 265),
+            (CommandCode::Accounting, 271),
+            (CommandCode::CreditControl, 272),
+            (CommandCode::AbortSession, 274),
+            (CommandCode::SessionTerminate, 275),
+            (CommandCode::DeviceWatchdog, 280),
+            (CommandCode::DisconnectPeer, 282),
+            (CommandCode::SpendingLimit, 8388635),
+            (CommandCode::SpendingStatusNotification, 8388636),
+            (CommandCode::Unknown(316), 316),
+        ];
+        for (command_code, code) in codes {
+            assert_eq!(command_code.as_u32(), code);
+            assert_eq!(CommandCode::from_u32(code), command_code);
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_command_code() {
+        let message = DiameterMessage::new(
+            CommandCode::Unknown(316),
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(&bytes[5..8], &[0x00, 0x01, 0x3c]);
+
+        let message = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(message.get_command_code(), CommandCode::Unknown(316));
+    }
+
+    #[test]
+    fn test_decode_unknown_application_id() {
+        let message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        let mut bytes = message.to_bytes().unwrap();
+        // S6a, which has no named variant
+        bytes[8..12].copy_from_slice(&16777251u32.to_be_bytes());
+
+        let message = DiameterMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            message.get_application_id(),
+            ApplicationId::Unknown(16777251)
+        );
+        assert_eq!(message.get_application_id().as_u32(), 16777251);
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+
+        assert_eq!(ApplicationId::from_u32(4), ApplicationId::CreditControl);
+        assert_eq!(ApplicationId::Gx.as_u32(), 16777238);
+    }
+
+    #[test]
+    fn test_set_avp() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(264, None, M, Identity::new("client.example.com")));
+        message.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+        message.add_avp(avp!(264, None, M, Identity::new("other.example.com")));
+
+        message.set_avp(avp!(264, None, M, Identity::new("ocs.operator.net")));
+        message.set_avp(avp!(296, None, M, Identity::new("operator.net")));
+
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 264, 268, 296]);
+        assert_eq!(
+            message
+                .get_avp(264)
+                .unwrap()
+                .get_identity()
+                .unwrap()
+                .value(),
+            "ocs.operator.net"
+        );
+        assert_eq!(message.get_length(), message.wire_length());
+    }
+
+    #[test]
+    fn test_remove_avp() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.push_proxy_info("proxy1.example.com", b"state1");
+        message.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+        message.push_proxy_info("proxy2.example.com", b"state2");
+
+        assert_eq!(message.remove_avp(284), 2);
+        assert_eq!(message.remove_avp(284), 0);
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 268]);
+        assert_eq!(message.get_length(), message.wire_length());
+    }
+
+    #[test]
+    fn test_replace_avp() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(283, None, M, Identity::new("home.example.com")));
+        message.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+        message.add_avp(avp!(283, None, M, Identity::new("other.example.com")));
+
+        message.replace_avp(
+            283,
+            avp!(283, None, M, Identity::new("visited.operator.net")),
+        );
+        message.replace_avp(293, avp!(293, None, M, Identity::new("ocs.operator.net")));
+
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 283, 268, 283, 293]);
+        assert_eq!(
+            message
+                .get_avp(283)
+                .unwrap()
+                .get_identity()
+                .unwrap()
+                .value(),
+            "visited.operator.net"
+        );
+        assert_eq!(message.get_length(), message.wire_length());
+
+        let decoded = DiameterMessage::from_bytes(&message.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.get_avps().len(), 5);
+
+        // A vendor-specific AVP does not replace the base one with its code
+        message.replace_avp(268, avp!(268, Some(10415), M, Unsigned32::new(5030)));
+        let codes: Vec<u32> = message.avps().map(|avp| avp.get_code()).collect();
+        assert_eq!(codes, vec![263, 283, 268, 283, 293, 268]);
+        assert_eq!(message.get_length(), message.wire_length());
+    }
+
+    #[test]
+    fn test_encode_length_matches_bytes_written() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        // 13 bytes, padded to 16
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;123458890")));
+        message.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+
+        let mut encoded = Vec::new();
+        message.encode_to(&mut encoded).unwrap();
+        let length = u32::from_be_bytes([0, encoded[1], encoded[2], encoded[3]]);
+        assert_eq!(length as usize, encoded.len());
+        assert_eq!(message.to_bytes().unwrap(), encoded);
+
+        // A desynced header length is rejected before anything is written
+        message.header.length += 4;
+        let mut written = Vec::new();
+        match message.encode_to(&mut written) {
+            Err(Error::EncodeError(msg)) => {
+                assert_eq!(msg, "encoded 56 bytes but the message length is 60")
+            }
+            _ => panic!("Expected an EncodeError"),
+        }
+        assert!(written.is_empty());
+        assert!(matches!(message.to_bytes(), Err(Error::EncodeError(_))));
+    }
 }
@@ -29,6 +29,13 @@ pub struct AvpDefinition {
     pub name: String,
     pub avp_type: AvpType,
     pub m_flag: bool,
+    pub allowed_values: Vec<i32>,
+}
+
+impl Default for Dictionary {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Dictionary {
@@ -93,11 +100,11 @@ impl Dictionary {
     }
 
     pub fn get_application_id_by_name(&self, name: &str) -> Option<ApplicationId> {
-        self.applications.get(name).map(|app| *app)
+        self.applications.get(name).copied()
     }
 
     pub fn get_command_code_by_name(&self, name: &str) -> Option<CommandCode> {
-        self.commands.get(name).map(|code| *code)
+        self.commands.get(name).copied()
     }
 }
 
@@ -176,12 +183,12 @@ pub fn parse(xml: &str, dictionary: &mut Dictionary) {
 
     dict.applications.iter().for_each(|app| {
         let app_id = app.id.parse::<u32>().unwrap();
-        let app_id: ApplicationId = ApplicationId::from_u32(app_id).unwrap();
+        let app_id: ApplicationId = ApplicationId::from_u32(app_id);
         dictionary.applications.insert(app.name.clone(), app_id);
 
         app.commands.iter().for_each(|cmd| {
             let cmd_code = cmd.code.parse::<u32>().unwrap();
-            let cmd_code: CommandCode = CommandCode::from_u32(cmd_code).unwrap();
+            let cmd_code: CommandCode = CommandCode::from_u32(cmd_code);
             dictionary.commands.insert(cmd.name.clone(), cmd_code);
         });
 
@@ -210,12 +217,16 @@ pub fn parse(xml: &str, dictionary: &mut Dictionary) {
                 Some(ref s) => s.split(',').collect(),
                 None => vec![],
             };
-            let m_flag = if flags.contains(&"M") { true } else { false };
+            let m_flag = flags.contains(&"M");
 
-            let vendor_id = match avp.vendor_id {
-                Some(ref s) => Some(s.parse::<u32>().unwrap()),
-                None => None,
-            };
+            let vendor_id = avp.vendor_id.as_ref().map(|s| s.parse::<u32>().unwrap());
+
+            let allowed_values = avp
+                .data
+                .item
+                .iter()
+                .map(|item| item.code.parse::<i32>().unwrap())
+                .collect();
 
             let avp_definition = AvpDefinition {
                 code: avp.code.parse::<u32>().unwrap(),
@@ -223,6 +234,7 @@ pub fn parse(xml: &str, dictionary: &mut Dictionary) {
                 name: avp.name.clone(),
                 avp_type,
                 m_flag,
+                allowed_values,
             };
 
             dictionary.add_avp(avp_definition);
@@ -238,7 +250,7 @@ lazy_static! {
         RwLock::new(dictionary)
     };
     pub static ref DEFAULT_DICT_XML: &'static str = {
-        let xml = r#"
+        r#"
 <diameter>
 	<application id="4" type="auth" name="Charging Control">
 		<!-- Diameter Credit Control Application -->
@@ -951,6 +963,7 @@ lazy_static! {
 				<item code="1" name="INITIAL_REQUEST"/>
 				<item code="2" name="UPDATE_REQUEST"/>
 				<item code="3" name="TERMINATION_REQUEST"/>
+				<item code="4" name="EVENT_REQUEST"/>
 			</data>
 		</avp>
 
@@ -1303,8 +1316,7 @@ lazy_static! {
 		</avp>
     </application>
 </diameter>
-    "#;
-        xml
+    "#
     };
 }
 
@@ -1321,6 +1333,10 @@ mod tests {
         assert_eq!(dict.get_avp(263, None).unwrap().name, "Session-Id");
         assert_eq!(dict.get_avp(1, None).unwrap().name, "User-Name");
         assert_eq!(dict.get_avp(258, None).unwrap().name, "Auth-Application-Id");
+        assert_eq!(
+            dict.get_avp(416, None).unwrap().allowed_values,
+            vec![1, 2, 3, 4]
+        );
 
         println!("Total AVP definitions {}", dict.avps.len());
 
@@ -1349,6 +1365,7 @@ mod tests {
             avp_type: AvpType::UTF8String,
             name: "Server-Name".into(),
             m_flag: true,
+            allowed_values: vec![],
         });
 
         assert_eq!(dict.get_avp(602, Some(10415)).unwrap().name, "Server-Name");
@@ -1420,5 +1437,6 @@ mod tests {
         );
 
         assert_eq!(dict.get_avp(2904, None).unwrap().name, "SL-Request-Type");
+        assert_eq!(dict.get_avp(2904, None).unwrap().allowed_values, vec![0, 1]);
     }
 }
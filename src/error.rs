@@ -1,3 +1,5 @@
+use crate::diameter::{ApplicationId, CommandCode};
+use crate::peer::{PeerEvent, PeerState};
 use std::fmt;
 use std::result::Result as StdResult;
 use std::sync::{MutexGuard, PoisonError};
@@ -7,13 +9,56 @@ use std::sync::{MutexGuard, PoisonError};
 pub enum Error {
     DecodeError(String),
     EncodeError(String),
+    ValidationError(String),
     UnknownAvpCode(u32),
     UnknownAvpName(String),
+    UnsupportedAvpType(u32),
+    InvalidAvpLength {
+        code: u32,
+        expected: u32,
+        got: u32,
+    },
+    MessageTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    AvpDecode {
+        code: u32,
+        vendor_id: Option<u32>,
+        offset: u64,
+        source: Box<Error>,
+    },
+    Timeout,
+    Cancelled,
+    ConnectionClosed,
+    NotConnected,
+    TooManyRequests,
+    RateLimited,
+    DuplicateHopByHopId(u32),
+    AnswerMismatch {
+        hop_by_hop_id: u32,
+        application_id: ApplicationId,
+        command_code: CommandCode,
+        expected_application_id: ApplicationId,
+        expected_command_code: CommandCode,
+    },
+    DiameterError {
+        result_code: u32,
+    },
+    PeerNotOpen {
+        state: PeerState,
+        command_code: CommandCode,
+    },
+    InvalidPeerEvent {
+        state: PeerState,
+        event: PeerEvent,
+    },
     ClientError(String),
     ServerError(String),
     IoError(std::io::Error),
     TryFromSliceError(std::array::TryFromSliceError),
     LockError(String),
+    #[cfg(feature = "transport")]
     NativeTlsError(native_tls::Error),
 }
 
@@ -25,19 +70,104 @@ impl fmt::Display for Error {
         match self {
             Error::DecodeError(msg) => write!(f, "{}", msg),
             Error::EncodeError(msg) => write!(f, "{}", msg),
+            Error::ValidationError(msg) => write!(f, "{}", msg),
             Error::UnknownAvpCode(code) => write!(f, "Unknown AVP code: {}", code),
             Error::UnknownAvpName(name) => write!(f, "Unknown AVP name: {}", name),
+            Error::UnsupportedAvpType(code) => {
+                write!(f, "Unsupported AVP type for AVP code: {}", code)
+            }
+            Error::InvalidAvpLength {
+                code,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Invalid length for AVP code {}: expected {}, got {}",
+                code, expected, got
+            ),
+            Error::MessageTooLarge { size, limit } => write!(
+                f,
+                "Message too large to read: {} bytes exceeds limit of {} bytes",
+                size, limit
+            ),
+            Error::AvpDecode {
+                code,
+                vendor_id,
+                offset,
+                source,
+            } => match vendor_id {
+                Some(vendor_id) => write!(
+                    f,
+                    "Failed decoding AVP {} (vendor {}) at offset {}: {}",
+                    code, vendor_id, offset, source
+                ),
+                None => write!(
+                    f,
+                    "Failed decoding AVP {} at offset {}: {}",
+                    code, offset, source
+                ),
+            },
+            Error::Timeout => write!(f, "Operation timed out"),
+            Error::Cancelled => write!(f, "Operation cancelled"),
+            Error::ConnectionClosed => write!(f, "Connection closed"),
+            Error::NotConnected => write!(f, "Not connected"),
+            Error::TooManyRequests => write!(f, "Too many requests in flight"),
+            Error::RateLimited => write!(f, "Request rate limit exceeded"),
+            Error::DuplicateHopByHopId(id) => {
+                write!(f, "Duplicate hop-by-hop id of a pending request: {}", id)
+            }
+            Error::AnswerMismatch {
+                hop_by_hop_id,
+                application_id,
+                command_code,
+                expected_application_id,
+                expected_command_code,
+            } => write!(
+                f,
+                "Answer for hop-by-hop id {} has application id {} and command code {}, expected {} and {}",
+                hop_by_hop_id,
+                application_id,
+                command_code,
+                expected_application_id,
+                expected_command_code
+            ),
+            Error::DiameterError { result_code } => {
+                write!(f, "Diameter error answer, Result-Code: {}", result_code)
+            }
+            Error::PeerNotOpen {
+                state,
+                command_code,
+            } => write!(
+                f,
+                "Cannot send command code {} while the peer is {}",
+                command_code, state
+            ),
+            Error::InvalidPeerEvent { state, event } => {
+                write!(f, "Invalid peer event {:?} in state {}", event, state)
+            }
             Error::ClientError(msg) => write!(f, "{}", msg),
             Error::ServerError(msg) => write!(f, "{}", msg),
             Error::IoError(e) => write!(f, "{}", e),
             Error::TryFromSliceError(e) => write!(f, "{}", e),
             Error::LockError(msg) => write!(f, "{}", msg),
+            #[cfg(feature = "transport")]
             Error::NativeTlsError(e) => write!(f, "{}", e),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::AvpDecode { source, .. } => Some(source.as_ref()),
+            Error::IoError(e) => Some(e),
+            Error::TryFromSliceError(e) => Some(e),
+            #[cfg(feature = "transport")]
+            Error::NativeTlsError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 // io error
 impl From<std::io::Error> for Error {
@@ -60,8 +190,84 @@ impl<'a, T> From<PoisonError<MutexGuard<'a, T>>> for Error {
     }
 }
 
+#[cfg(feature = "transport")]
 impl From<native_tls::Error> for Error {
     fn from(err: native_tls::Error) -> Self {
         Error::NativeTlsError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::InvalidAvpLength {
+            code: 415,
+            expected: 4,
+            got: 2,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Invalid length for AVP code 415: expected 4, got 2"
+        );
+        let err = Error::MessageTooLarge {
+            size: 2048,
+            limit: 1024,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Message too large to read: 2048 bytes exceeds limit of 1024 bytes"
+        );
+        assert_eq!(Error::NotConnected.to_string(), "Not connected");
+        assert_eq!(
+            Error::TooManyRequests.to_string(),
+            "Too many requests in flight"
+        );
+        assert_eq!(
+            Error::RateLimited.to_string(),
+            "Request rate limit exceeded"
+        );
+        assert_eq!(
+            Error::DuplicateHopByHopId(1000).to_string(),
+            "Duplicate hop-by-hop id of a pending request: 1000"
+        );
+        let err = Error::AnswerMismatch {
+            hop_by_hop_id: 1,
+            application_id: ApplicationId::Gx,
+            command_code: CommandCode::CreditControl,
+            expected_application_id: ApplicationId::CreditControl,
+            expected_command_code: CommandCode::CreditControl,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Answer for hop-by-hop id 1 has application id Gx and command code CreditControl, expected CreditControl and CreditControl"
+        );
+        let err = Error::PeerNotOpen {
+            state: PeerState::WaitICea,
+            command_code: CommandCode::CreditControl,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Cannot send command code CreditControl while the peer is Wait-I-CEA"
+        );
+        let err = Error::InvalidPeerEvent {
+            state: PeerState::IOpen,
+            event: PeerEvent::RcvCeaSuccess,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Invalid peer event RcvCeaSuccess in state I-Open"
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        use std::error::Error as StdError;
+
+        let err: Error = std::io::Error::other("boom").into();
+        assert!(err.source().is_some());
+        assert!(Error::ConnectionClosed.source().is_none());
+    }
+}
@@ -0,0 +1,71 @@
+//! Hex encoding shared by `DiameterMessage::to_hex`/`from_hex` and the
+//! serde representation of OctetString values.
+
+use crate::error::{Error, Result};
+use std::fmt::Write;
+
+/// Encodes `bytes` as a string of lowercase hex digits.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// Decodes a string of hex digits, two per byte, in either case.
+pub(crate) fn decode(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(Error::DecodeError(format!(
+            "invalid hex, odd number of digits {}",
+            hex.len()
+        )));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            // from_str_radix alone would accept a sign, e.g. "+f"
+            Some(pair)
+                .filter(|pair| pair.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|pair| std::str::from_utf8(pair).ok())
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| {
+                    Error::DecodeError(format!("invalid hex digits at offset {}", i * 2))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        assert_eq!(encode(&[0x01, 0xab, 0x00]), "01ab00");
+        assert_eq!(decode("01AB00").unwrap(), vec![0x01, 0xab, 0x00]);
+        assert_eq!(encode(&[]), "");
+        assert!(decode("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        match decode("abc") {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "invalid hex, odd number of digits 3"),
+            _ => panic!("Expected a DecodeError"),
+        }
+        match decode("01zz") {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "invalid hex digits at offset 2"),
+            _ => panic!("Expected a DecodeError"),
+        }
+        match decode("+1+f") {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "invalid hex digits at offset 0"),
+            _ => panic!("Expected a DecodeError"),
+        }
+        match decode("00+1") {
+            Err(Error::DecodeError(msg)) => assert_eq!(msg, "invalid hex digits at offset 2"),
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+}
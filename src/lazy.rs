@@ -0,0 +1,299 @@
+//! Lazily Decoded Diameter Messages
+//!
+//! A [`LazyMessage`] keeps the original bytes of a message and an index of
+//! its AVPs, decoding an AVP only when it is accessed. Re-encoding copies the
+//! original bytes of every untouched AVP, so a relay that rewrites one or two
+//! AVPs forwards the rest of the message byte for byte.
+
+use crate::avp::{Avp, AvpHeader, DecodeOptions};
+use crate::diameter::{DiameterHeader, HEADER_LENGTH};
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use std::io::{Cursor, Write};
+use std::ops::Range;
+
+/// A Diameter message whose AVPs are decoded on access.
+///
+/// Example:
+///    ```
+///    use bytes::Bytes;
+///    use diameter::lazy::LazyMessage;
+///    use diameter::{ApplicationId, CommandCode, DiameterMessage};
+///
+///    let ccr = DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+///        .destination_realm("operator.net")
+///        .build();
+///    let bytes = Bytes::from(ccr.to_bytes().unwrap());
+///
+///    let lazy = LazyMessage::new(bytes).unwrap();
+///    let realm = lazy.get_avp(283).unwrap().unwrap();
+///    assert_eq!(realm.get_identity().unwrap().value(), "operator.net");
+///    ```
+#[derive(Debug, Clone)]
+pub struct LazyMessage {
+    bytes: Bytes,
+    header: DiameterHeader,
+    avps: Vec<LazyAvp>,
+}
+
+#[derive(Debug, Clone)]
+enum LazyAvp {
+    // An AVP of the original message, by its padded byte range
+    Original {
+        code: u32,
+        vendor_id: Option<u32>,
+        range: Range<usize>,
+    },
+    // An AVP set with `LazyMessage::set_avp`
+    Replaced(Avp),
+}
+
+impl LazyAvp {
+    fn key(&self) -> (u32, Option<u32>) {
+        match self {
+            LazyAvp::Original {
+                code, vendor_id, ..
+            } => (*code, *vendor_id),
+            LazyAvp::Replaced(avp) => (avp.get_code(), avp.get_vendor_id()),
+        }
+    }
+}
+
+impl LazyMessage {
+    /// Indexes the AVPs of an encoded message without decoding their values.
+    ///
+    /// Returns a `DecodeError` if the header is invalid or an AVP length does
+    /// not fit in the message.
+    pub fn new(bytes: Bytes) -> Result<LazyMessage> {
+        let header = DiameterHeader::decode_from(&mut Cursor::new(&bytes[..]))?;
+        let total_length = header.get_length() as usize;
+        if bytes.len() < total_length {
+            return Err(Error::DecodeError(
+                "invalid diameter message, length mismatch".into(),
+            ));
+        }
+
+        let mut avps = Vec::new();
+        let mut offset = HEADER_LENGTH as usize;
+        while offset < total_length {
+            let avp_header = AvpHeader::decode_from(&mut Cursor::new(&bytes[offset..]))?;
+            let length = avp_header.get_length() as usize;
+            if offset + length > total_length {
+                return Err(Error::DecodeError(format!(
+                    "invalid length {} for AVP {}, exceeds the remaining {} bytes",
+                    length,
+                    avp_header.get_code(),
+                    total_length - offset
+                )));
+            }
+            // The header length ends the message, even inside the last AVP's padding
+            let end = (offset + padded_len(length)).min(total_length);
+            avps.push(LazyAvp::Original {
+                code: avp_header.get_code(),
+                vendor_id: avp_header.get_vendor_id(),
+                range: offset..end,
+            });
+            offset = end;
+        }
+
+        Ok(LazyMessage {
+            bytes,
+            header,
+            avps,
+        })
+    }
+
+    pub fn get_header(&self) -> &DiameterHeader {
+        &self.header
+    }
+
+    /// Decodes and returns the first AVP with the specified code, if it
+    /// exists within the message.
+    pub fn get_avp(&self, code: u32) -> Result<Option<Avp>> {
+        match self.avps.iter().find(|avp| avp.key().0 == code) {
+            Some(avp) => self.decode(avp).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets an AVP, replacing any AVPs with the same code and vendor.
+    ///
+    /// The AVP takes the position of the first AVP it replaces, or is
+    /// appended if the message has none. Other AVPs are left untouched.
+    pub fn set_avp(&mut self, avp: Avp) {
+        let key = (avp.get_code(), avp.get_vendor_id());
+        let mut avp = Some(avp);
+        let avps = std::mem::take(&mut self.avps);
+        for existing in avps {
+            if existing.key() != key {
+                self.avps.push(existing);
+            } else if let Some(avp) = avp.take() {
+                self.avps.push(LazyAvp::Replaced(avp));
+            }
+        }
+        if let Some(avp) = avp {
+            self.avps.push(LazyAvp::Replaced(avp));
+        }
+    }
+
+    /// Encodes the message, copying the original bytes of untouched AVPs.
+    ///
+    /// A final AVP received without its padding is padded.
+    pub fn encode_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut header = self.header.clone();
+        header.set_length(self.avps.iter().fold(HEADER_LENGTH, |length, avp| {
+            length
+                + match avp {
+                    LazyAvp::Original { range, .. } => padded_len(range.len()) as u32,
+                    LazyAvp::Replaced(avp) => avp.get_length() + avp.get_padding() as u32,
+                }
+        }));
+        header.encode_to(writer)?;
+
+        for avp in &self.avps {
+            match avp {
+                LazyAvp::Original { range, .. } => {
+                    writer.write_all(&self.bytes[range.clone()])?;
+                    let padding = padded_len(range.len()) - range.len();
+                    writer.write_all(&[0; 3][..padding])?;
+                }
+                LazyAvp::Replaced(avp) => avp.encode_to(writer)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.encode_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // OctetString payloads are sliced from the original bytes rather than copied
+    fn decode(&self, avp: &LazyAvp) -> Result<Avp> {
+        match avp {
+            LazyAvp::Original { range, .. } => {
+                let mut cursor = Cursor::new(&self.bytes[..]);
+                cursor.set_position(range.start as u64);
+                Avp::decode_inner(
+                    &mut cursor,
+                    &DecodeOptions::default(),
+                    Some(&self.bytes),
+                    Some(range.len() as u32),
+                )
+            }
+            LazyAvp::Replaced(avp) => Ok(avp.clone()),
+        }
+    }
+}
+
+// Rounds an AVP length up to the next multiple of 4.
+fn padded_len(length: usize) -> usize {
+    length + (4 - length % 4) % 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp;
+    use crate::avp::flags::M;
+    use crate::avp::{Identity, OctetString, UTF8String, Unsigned32};
+    use crate::diameter::flags;
+    use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+
+    #[test]
+    fn test_rewrite_destination_realm() {
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            1,
+            1,
+        );
+        ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        ccr.add_avp(avp!(264, None, M, Identity::new("client.example.com")));
+        ccr.add_avp(avp!(283, None, M, Identity::new("example.com")));
+        ccr.add_avp(avp!(25, None, M, OctetString::new(vec![1, 2, 3])));
+        ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+        let original = ccr.to_bytes().unwrap();
+
+        let mut lazy = LazyMessage::new(Bytes::from(original.clone())).unwrap();
+        let realm = lazy.get_avp(283).unwrap().unwrap();
+        assert_eq!(realm.get_identity().unwrap().value(), "example.com");
+        assert!(lazy.get_avp(293).unwrap().is_none());
+
+        // Untouched, the message re-encodes byte for byte
+        assert_eq!(lazy.to_bytes().unwrap(), original);
+
+        lazy.set_avp(avp!(283, None, M, Identity::new("operator.net")));
+        let rewritten = lazy.to_bytes().unwrap();
+
+        let mut expected = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            1,
+            1,
+        );
+        for avp in ccr.avps() {
+            match avp.get_code() {
+                283 => expected.add_avp(avp!(283, None, M, Identity::new("operator.net"))),
+                _ => expected.add_avp(avp.clone()),
+            }
+        }
+        assert_eq!(rewritten, expected.to_bytes().unwrap());
+
+        // The AVPs before the rewritten one are the original bytes
+        let prefix = ccr.get_avps()[..2]
+            .iter()
+            .map(|avp| (avp.get_length() + avp.get_padding() as u32) as usize)
+            .sum::<usize>()
+            + HEADER_LENGTH as usize;
+        assert_eq!(rewritten[4..prefix], original[4..prefix]);
+    }
+
+    #[test]
+    fn test_final_avp_padding() {
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+        ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;1")));
+        let padded = ccr.to_bytes().unwrap();
+
+        // Drop the 3 padding bytes of Session-Id, and from the header length
+        let mut bytes = padded[..padded.len() - 3].to_vec();
+        bytes[3] -= 3;
+
+        let lazy = LazyMessage::new(Bytes::from(bytes)).unwrap();
+        let session_id = lazy.get_avp(263).unwrap().unwrap();
+        assert_eq!(session_id.get_utf8string().unwrap().value(), "ses;1");
+        assert_eq!(lazy.to_bytes().unwrap(), padded);
+    }
+
+    #[test]
+    fn test_avp_exceeds_message() {
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
+        let mut bytes = ccr.to_bytes().unwrap();
+        bytes[27] = 0x20;
+
+        match LazyMessage::new(Bytes::from(bytes)) {
+            Err(Error::DecodeError(msg)) => assert_eq!(
+                msg,
+                "invalid length 32 for AVP 415, exceeds the remaining 12 bytes"
+            ),
+            _ => panic!("Expected a DecodeError"),
+        }
+    }
+}
@@ -10,15 +10,49 @@
 //!
 //! * [`server`] - A simple diameter server that listens for requests and sends a response.
 //!
+//! ## Features
+//! * `transport` (default) - The async client and server built on tokio, and the
+//!   tokio-util `DiameterCodec`. Disable default features to use only the
+//!   synchronous `decode_from`/`encode_to` APIs of messages, AVPs and the dictionary:
+//!   ```toml
+//!   diameter = { version = "0.6", default-features = false }
+//!   ```
+//! * `serde` - `Serialize` and `Deserialize` for `DiameterMessage` and `Avp`,
+//!   e.g. to log messages as JSON.
+//! * `tls` - Diameter over TLS (RFC 6733, section 2.2) with rustls, using the
+//!   `ring` crypto provider. The rustls crate is re-exported as
+//!   `diameter::transport::rustls`.
+//! * `sctp` - Diameter over SCTP on Linux and FreeBSD, with
+//!   `transport::sctp::SctpConnector`.
+//!
 //! [`server`]: https://github.com/lwlee2608/diameter-rs/blob/v0.6.0/examples/server.rs
 //! [`client`]: https://github.com/lwlee2608/diameter-rs/blob/v0.6.0/examples/client.rs
 
+pub mod applications;
 pub mod avp;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod capabilities;
+#[cfg(feature = "transport")]
+pub mod codec;
 pub mod diameter;
 pub mod dictionary;
 pub mod error;
+mod hex;
+pub mod lazy;
+pub mod peer;
+pub mod proxy;
+pub mod result_code;
+pub mod routing;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod session;
+pub mod stream;
+#[cfg(feature = "transport")]
 pub mod transport;
 
 pub use crate::diameter::flags;
-pub use crate::diameter::{ApplicationId, CommandCode, DiameterHeader, DiameterMessage};
+pub use crate::diameter::{
+    ApplicationId, CommandCode, DiameterHeader, DiameterMessage, DiameterMessageBuilder,
+};
 pub use crate::error::{Error, Result};
@@ -0,0 +1,548 @@
+//! Diameter Peer State Machine
+//!
+//! The peer state machine of RFC 6733, section 5.6. A peer may be reached
+//! over the connection we initiate (the "I" side) and the one it initiates
+//! (the "R" side); when both come up together, an election decides which
+//! one is kept. Every transition yields the actions the driver performs,
+//! such as sending the CER or disconnecting the losing connection.
+//!
+//! `DiameterClient` drives the initiator side when `DiameterClientConfig::peer`
+//! is set. Application messages may only be sent while the peer is open; see
+//! [`PeerStateMachine::check_send`].
+
+use crate::avp::codes::RESULT_CODE;
+use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+use crate::error::{Error, Result};
+use crate::result_code::ResultCode;
+use std::fmt;
+
+/// The state of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// No connection.
+    Closed,
+    /// Waiting for our transport connection to be established.
+    WaitConnAck,
+    /// Our CER is sent and its CEA awaited.
+    WaitICea,
+    /// Waiting for our transport connection, while the peer has connected
+    /// to us with a CER of its own.
+    WaitConnAckElect,
+    /// Both connections sent a CER; waiting for the election or our CEA.
+    WaitReturns,
+    /// Open over the connection the peer initiated.
+    ROpen,
+    /// Open over the connection we initiated.
+    IOpen,
+    /// A DPR was sent or answered, and the connection is going down.
+    Closing,
+}
+
+impl PeerState {
+    /// Returns whether the capabilities exchange succeeded on either connection.
+    pub fn is_open(&self) -> bool {
+        matches!(self, PeerState::ROpen | PeerState::IOpen)
+    }
+}
+
+impl fmt::Display for PeerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PeerState::Closed => "Closed",
+            PeerState::WaitConnAck => "Wait-Conn-Ack",
+            PeerState::WaitICea => "Wait-I-CEA",
+            PeerState::WaitConnAckElect => "Wait-Conn-Ack/Elect",
+            PeerState::WaitReturns => "Wait-Returns",
+            PeerState::ROpen => "R-Open",
+            PeerState::IOpen => "I-Open",
+            PeerState::Closing => "Closing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An event driving the peer state machine.
+///
+/// Events without an `R` prefix concern the connection we initiate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// A connection to the peer is initiated.
+    Start,
+    /// Our transport connection is established (I-Rcv-Conn-Ack).
+    ConnAck,
+    /// Our transport connection could not be established (I-Rcv-Conn-Nack).
+    ConnFailed,
+    /// A CEA with a successful Result-Code was received.
+    RcvCeaSuccess,
+    /// A CEA with any other Result-Code was received.
+    RcvCeaFailure,
+    /// A message other than a CEA was received while awaiting the CEA.
+    RcvNonCea,
+    /// A DWR was received.
+    RcvDwr,
+    /// A DWA was received.
+    RcvDwa,
+    /// The local side asks to disconnect; the DPR is sent next.
+    Stop,
+    /// The peer asked to disconnect with a DPR.
+    RcvDpr,
+    /// The answer to our DPR was received.
+    RcvDpa,
+    /// A CEA, DPA or connection was not received in time.
+    Timeout,
+    /// Our transport connection was lost (I-Peer-Disc).
+    PeerDisconnected,
+    /// The peer connected to us and sent a CER (R-Conn-CER).
+    RConnCer,
+    /// A DWR was received on the peer's connection.
+    RRcvDwr,
+    /// A DWA was received on the peer's connection.
+    RRcvDwa,
+    /// The peer asked to disconnect with a DPR on its connection.
+    RRcvDpr,
+    /// The answer to our DPR was received on the peer's connection.
+    RRcvDpa,
+    /// The peer's connection was lost (R-Peer-Disc).
+    RPeerDisconnected,
+    /// The election was won, see `PeerStateMachine::wins_election`.
+    WinElection,
+}
+
+impl PeerEvent {
+    /// Returns the event a message received on our connection drives, if any.
+    ///
+    /// CEAs are not mapped, since only the answer to our own CER counts.
+    pub fn for_received(msg: &DiameterMessage) -> Option<PeerEvent> {
+        if msg.get_application_id() != ApplicationId::Common {
+            return None;
+        }
+        let request = msg.get_flags() & flags::REQUEST != 0;
+        match (msg.get_command_code(), request) {
+            (CommandCode::DeviceWatchdog, true) => Some(PeerEvent::RcvDwr),
+            (CommandCode::DeviceWatchdog, false) => Some(PeerEvent::RcvDwa),
+            (CommandCode::DisconnectPeer, true) => Some(PeerEvent::RcvDpr),
+            (CommandCode::DisconnectPeer, false) => Some(PeerEvent::RcvDpa),
+            _ => None,
+        }
+    }
+
+    /// Returns `RcvCeaSuccess` or `RcvCeaFailure` for a CEA, depending on
+    /// whether its Result-Code (268) is DIAMETER_SUCCESS.
+    pub fn for_cea(cea: &DiameterMessage) -> PeerEvent {
+        let result_code = cea
+            .get_avp(RESULT_CODE)
+            .and_then(|avp| avp.get_unsigned32());
+        if result_code == Some(ResultCode::Success.as_u32()) {
+            PeerEvent::RcvCeaSuccess
+        } else {
+            PeerEvent::RcvCeaFailure
+        }
+    }
+}
+
+/// An action the driver of the state machine performs on a transition.
+///
+/// Actions prefixed `I` apply to the connection we initiate, those prefixed
+/// `R` to the one the peer initiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerAction {
+    /// Start connecting to the peer.
+    ISndConnReq,
+    /// Send the CER on our connection.
+    ISndCer,
+    /// Accept the peer's connection.
+    RAccept,
+    /// Check the peer's CER, e.g. for common applications.
+    ProcessCer,
+    /// Answer the peer's CER with a successful CEA.
+    RSndCea,
+    /// Reject the peer's connection, e.g. because one is already open.
+    RReject,
+    /// Run the election with the Origin-Host of the peer's CER, issuing
+    /// `WinElection` if it is won.
+    Elect,
+    /// Check the CEA received on our connection.
+    ProcessCea,
+    /// Process a received DWR.
+    ProcessDwr,
+    /// Answer a DWR with a DWA on our connection.
+    ISndDwa,
+    /// Answer a DWR with a DWA on the peer's connection.
+    RSndDwa,
+    /// Process a received DWA.
+    ProcessDwa,
+    /// Send a DPR on our connection.
+    ISndDpr,
+    /// Send a DPR on the peer's connection.
+    RSndDpr,
+    /// Answer a DPR on our connection.
+    ISndDpa,
+    /// Answer a DPR on the peer's connection.
+    RSndDpa,
+    /// Disconnect our connection.
+    IDisc,
+    /// Disconnect the peer's connection.
+    RDisc,
+    /// Release the resources of a failed connection attempt.
+    Cleanup,
+    /// Report the failure and disconnect.
+    ReportError,
+}
+
+/// The state machine of one peer.
+///
+/// Example:
+///    ```
+///    use diameter::peer::{PeerAction, PeerEvent, PeerState, PeerStateMachine};
+///
+///    let mut peer = PeerStateMachine::new();
+///    peer.handle(PeerEvent::Start).unwrap();
+///    let (state, actions) = peer.transition(PeerEvent::ConnAck).unwrap();
+///    assert_eq!(state, PeerState::WaitICea);
+///    assert_eq!(actions, &[PeerAction::ISndCer]);
+///    assert_eq!(peer.handle(PeerEvent::RcvCeaSuccess).unwrap(), PeerState::IOpen);
+///    ```
+#[derive(Debug, Clone)]
+pub struct PeerStateMachine {
+    state: PeerState,
+}
+
+impl PeerStateMachine {
+    /// Returns a state machine in the `Closed` state.
+    pub fn new() -> PeerStateMachine {
+        PeerStateMachine {
+            state: PeerState::Closed,
+        }
+    }
+
+    pub fn get_state(&self) -> PeerState {
+        self.state
+    }
+
+    /// Applies an event, returning the new state.
+    ///
+    /// An event that is not valid in the current state, e.g. a CEA while
+    /// open, fails with `Error::InvalidPeerEvent` and leaves the state unchanged.
+    pub fn handle(&mut self, event: PeerEvent) -> Result<PeerState> {
+        self.transition(event).map(|(state, _)| state)
+    }
+
+    /// Applies an event, returning the new state and the actions to perform,
+    /// in order, as listed in RFC 6733, section 5.6.
+    ///
+    /// Fails like `handle` for an event that is not valid in the current state.
+    pub fn transition(&mut self, event: PeerEvent) -> Result<(PeerState, &'static [PeerAction])> {
+        use PeerAction::*;
+        use PeerEvent::*;
+        use PeerState::*;
+
+        let (next, actions): (PeerState, &'static [PeerAction]) = match (self.state, event) {
+            (Closed, Start) => (WaitConnAck, &[ISndConnReq]),
+            (Closed, RConnCer) => (ROpen, &[RAccept, ProcessCer, RSndCea]),
+
+            (WaitConnAck, ConnAck) => (WaitICea, &[ISndCer]),
+            (WaitConnAck, ConnFailed) => (Closed, &[Cleanup]),
+            (WaitConnAck, RConnCer) => (WaitConnAckElect, &[RAccept, ProcessCer]),
+            (WaitConnAck, Timeout) => (Closed, &[ReportError]),
+            (WaitConnAck, Stop) => (Closed, &[Cleanup]),
+
+            (WaitICea, RcvCeaSuccess) => (IOpen, &[ProcessCea]),
+            (WaitICea, RcvCeaFailure | RcvNonCea | Timeout) => (Closed, &[ReportError]),
+            (WaitICea, RConnCer) => (WaitReturns, &[RAccept, ProcessCer, Elect]),
+            (WaitICea, PeerDisconnected | Stop) => (Closed, &[IDisc]),
+
+            (WaitConnAckElect, ConnAck) => (WaitReturns, &[ISndCer, Elect]),
+            (WaitConnAckElect, ConnFailed | PeerDisconnected) => (ROpen, &[RSndCea]),
+            (WaitConnAckElect, RPeerDisconnected) => (WaitConnAck, &[RDisc]),
+            (WaitConnAckElect, RConnCer) => (WaitConnAckElect, &[RReject]),
+            (WaitConnAckElect, Timeout) => (Closed, &[ReportError]),
+
+            (WaitReturns, WinElection) => (ROpen, &[IDisc, RSndCea]),
+            // Our CEA failing leaves the peer's connection, as if ours dropped
+            (WaitReturns, PeerDisconnected | RcvCeaFailure) => (ROpen, &[IDisc, RSndCea]),
+            (WaitReturns, RcvCeaSuccess) => (IOpen, &[RDisc]),
+            (WaitReturns, RPeerDisconnected) => (WaitICea, &[RDisc]),
+            (WaitReturns, RConnCer) => (WaitReturns, &[RReject]),
+            (WaitReturns, Timeout) => (Closed, &[ReportError]),
+
+            (IOpen, RcvDwr) => (IOpen, &[ProcessDwr, ISndDwa]),
+            (IOpen, RcvDwa) => (IOpen, &[ProcessDwa]),
+            (IOpen, RConnCer) => (IOpen, &[RReject]),
+            (IOpen, Stop) => (Closing, &[ISndDpr]),
+            (IOpen, RcvDpr) => (Closing, &[ISndDpa]),
+            (IOpen, PeerDisconnected) => (Closed, &[IDisc]),
+            // The losing connection of an election going down
+            (IOpen, RPeerDisconnected) => (IOpen, &[]),
+
+            (ROpen, RRcvDwr) => (ROpen, &[ProcessDwr, RSndDwa]),
+            (ROpen, RRcvDwa) => (ROpen, &[ProcessDwa]),
+            (ROpen, RConnCer) => (ROpen, &[RReject]),
+            (ROpen, Stop) => (Closing, &[RSndDpr]),
+            (ROpen, RRcvDpr) => (Closing, &[RSndDpa]),
+            (ROpen, RPeerDisconnected) => (Closed, &[RDisc]),
+            (ROpen, PeerDisconnected) => (ROpen, &[]),
+
+            (Closing, RcvDpa | PeerDisconnected) => (Closed, &[IDisc]),
+            (Closing, RRcvDpa | RPeerDisconnected) => (Closed, &[RDisc]),
+            (Closing, Timeout) => (Closed, &[ReportError]),
+            // Watchdogs crossing our DPR
+            (Closing, RcvDwr | RcvDwa | RRcvDwr | RRcvDwa) => (Closing, &[]),
+
+            (_, PeerDisconnected) => (Closed, &[Cleanup]),
+            (state, event) => return Err(Error::InvalidPeerEvent { state, event }),
+        };
+        if next != self.state {
+            log::debug!("Peer state {} -> {} on {:?}", self.state, next, event);
+        }
+        self.state = next;
+        Ok((next, actions))
+    }
+
+    /// Runs the election between our connection and the peer's, both having
+    /// sent a CER (RFC 6733, section 5.6.4).
+    ///
+    /// The election is won, and the peer's connection kept, if our
+    /// Origin-Host is greater than the peer's, compared as octet strings.
+    pub fn wins_election(local_origin_host: &str, peer_origin_host: &str) -> bool {
+        local_origin_host.as_bytes() > peer_origin_host.as_bytes()
+    }
+
+    /// Checks that a request may be sent in the current state.
+    ///
+    /// Any request may be sent while open. Before that only the CER may be
+    /// sent, while waiting for its answer, and while `Closing` nothing may.
+    pub fn check_send(&self, req: &DiameterMessage) -> Result<()> {
+        let cer = req.get_command_code() == CommandCode::CapabilitiesExchange;
+        match self.state {
+            state if state.is_open() => Ok(()),
+            PeerState::WaitICea | PeerState::WaitReturns if cer => Ok(()),
+            state => Err(Error::PeerNotOpen {
+                state,
+                command_code: req.get_command_code(),
+            }),
+        }
+    }
+}
+
+impl Default for PeerStateMachine {
+    fn default() -> Self {
+        PeerStateMachine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp;
+    use crate::avp::flags::M;
+    use crate::avp::{Avp, Unsigned32};
+
+    fn message(code: CommandCode, flags: u8) -> DiameterMessage {
+        DiameterMessage::new(code, ApplicationId::Common, flags, 1, 1)
+    }
+
+    fn open() -> PeerStateMachine {
+        let mut peer = PeerStateMachine::new();
+        assert_eq!(
+            peer.handle(PeerEvent::Start).unwrap(),
+            PeerState::WaitConnAck
+        );
+        assert_eq!(
+            peer.handle(PeerEvent::ConnAck).unwrap(),
+            PeerState::WaitICea
+        );
+        assert_eq!(
+            peer.handle(PeerEvent::RcvCeaSuccess).unwrap(),
+            PeerState::IOpen
+        );
+        peer
+    }
+
+    #[test]
+    fn test_open_and_close() {
+        let mut peer = open();
+        assert_eq!(peer.handle(PeerEvent::RcvDwr).unwrap(), PeerState::IOpen);
+        assert_eq!(peer.handle(PeerEvent::Stop).unwrap(), PeerState::Closing);
+        assert_eq!(peer.handle(PeerEvent::RcvDpa).unwrap(), PeerState::Closed);
+
+        let mut peer = open();
+        assert_eq!(peer.handle(PeerEvent::RcvDpr).unwrap(), PeerState::Closing);
+        assert_eq!(
+            peer.handle(PeerEvent::PeerDisconnected).unwrap(),
+            PeerState::Closed
+        );
+    }
+
+    #[test]
+    fn test_responder() {
+        let mut peer = PeerStateMachine::new();
+        let (state, actions) = peer.transition(PeerEvent::RConnCer).unwrap();
+        assert_eq!(state, PeerState::ROpen);
+        assert_eq!(
+            actions,
+            &[
+                PeerAction::RAccept,
+                PeerAction::ProcessCer,
+                PeerAction::RSndCea
+            ]
+        );
+        assert_eq!(
+            peer.transition(PeerEvent::RConnCer).unwrap(),
+            (PeerState::ROpen, &[PeerAction::RReject][..])
+        );
+        assert_eq!(
+            peer.transition(PeerEvent::RRcvDwr).unwrap(),
+            (
+                PeerState::ROpen,
+                &[PeerAction::ProcessDwr, PeerAction::RSndDwa][..]
+            )
+        );
+        assert_eq!(
+            peer.transition(PeerEvent::Stop).unwrap(),
+            (PeerState::Closing, &[PeerAction::RSndDpr][..])
+        );
+        assert_eq!(peer.handle(PeerEvent::RRcvDpa).unwrap(), PeerState::Closed);
+    }
+
+    #[test]
+    fn test_election() {
+        assert!(PeerStateMachine::wins_election(
+            "b.example.com",
+            "a.example.com"
+        ));
+        assert!(!PeerStateMachine::wins_election(
+            "a.example.com",
+            "b.example.com"
+        ));
+        assert!(!PeerStateMachine::wins_election(
+            "a.example.com",
+            "a.example.com"
+        ));
+
+        // The peer's CER crosses ours and we win: our connection is dropped
+        let mut peer = PeerStateMachine::new();
+        peer.handle(PeerEvent::Start).unwrap();
+        peer.handle(PeerEvent::ConnAck).unwrap();
+        let (state, actions) = peer.transition(PeerEvent::RConnCer).unwrap();
+        assert_eq!(state, PeerState::WaitReturns);
+        assert_eq!(actions.last(), Some(&PeerAction::Elect));
+        assert_eq!(
+            peer.transition(PeerEvent::WinElection).unwrap(),
+            (
+                PeerState::ROpen,
+                &[PeerAction::IDisc, PeerAction::RSndCea][..]
+            )
+        );
+
+        // We lose, and our CEA arrives: the peer's connection is dropped
+        let mut peer = PeerStateMachine::new();
+        peer.handle(PeerEvent::Start).unwrap();
+        assert_eq!(
+            peer.handle(PeerEvent::RConnCer).unwrap(),
+            PeerState::WaitConnAckElect
+        );
+        let (state, actions) = peer.transition(PeerEvent::ConnAck).unwrap();
+        assert_eq!(state, PeerState::WaitReturns);
+        assert_eq!(actions, &[PeerAction::ISndCer, PeerAction::Elect]);
+        peer.check_send(&message(CommandCode::CapabilitiesExchange, flags::REQUEST))
+            .unwrap();
+        assert_eq!(
+            peer.transition(PeerEvent::RcvCeaSuccess).unwrap(),
+            (PeerState::IOpen, &[PeerAction::RDisc][..])
+        );
+
+        // Our connection fails while the peer's waits for the election
+        let mut peer = PeerStateMachine::new();
+        peer.handle(PeerEvent::Start).unwrap();
+        peer.handle(PeerEvent::RConnCer).unwrap();
+        assert_eq!(
+            peer.transition(PeerEvent::ConnFailed).unwrap(),
+            (PeerState::ROpen, &[PeerAction::RSndCea][..])
+        );
+    }
+
+    #[test]
+    fn test_failed_handshake() {
+        let mut peer = PeerStateMachine::new();
+        peer.handle(PeerEvent::Start).unwrap();
+        assert_eq!(
+            peer.handle(PeerEvent::ConnFailed).unwrap(),
+            PeerState::Closed
+        );
+
+        peer.handle(PeerEvent::Start).unwrap();
+        peer.handle(PeerEvent::ConnAck).unwrap();
+        assert_eq!(
+            peer.handle(PeerEvent::RcvCeaFailure).unwrap(),
+            PeerState::Closed
+        );
+
+        let mut peer = open();
+        assert_eq!(
+            peer.handle(PeerEvent::PeerDisconnected).unwrap(),
+            PeerState::Closed
+        );
+    }
+
+    #[test]
+    fn test_invalid_event() {
+        let mut peer = open();
+        match peer.handle(PeerEvent::RcvCeaSuccess) {
+            Err(Error::InvalidPeerEvent { state, event }) => {
+                assert_eq!(state, PeerState::IOpen);
+                assert_eq!(event, PeerEvent::RcvCeaSuccess);
+            }
+            _ => panic!("Expected an InvalidPeerEvent error"),
+        }
+        assert_eq!(peer.get_state(), PeerState::IOpen);
+        assert!(PeerStateMachine::new().handle(PeerEvent::ConnAck).is_err());
+    }
+
+    #[test]
+    fn test_check_send() {
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        let cer = message(CommandCode::CapabilitiesExchange, flags::REQUEST);
+
+        let mut peer = PeerStateMachine::new();
+        assert!(peer.check_send(&ccr).is_err());
+        peer.handle(PeerEvent::Start).unwrap();
+        peer.handle(PeerEvent::ConnAck).unwrap();
+        peer.check_send(&cer).unwrap();
+        match peer.check_send(&ccr) {
+            Err(Error::PeerNotOpen {
+                state,
+                command_code,
+            }) => {
+                assert_eq!(state, PeerState::WaitICea);
+                assert_eq!(command_code, CommandCode::CreditControl);
+            }
+            _ => panic!("Expected a PeerNotOpen error"),
+        }
+
+        peer.handle(PeerEvent::RcvCeaSuccess).unwrap();
+        peer.check_send(&ccr).unwrap();
+        peer.handle(PeerEvent::Stop).unwrap();
+        assert!(peer.check_send(&ccr).is_err());
+    }
+
+    #[test]
+    fn test_events_for_messages() {
+        let dwr = message(CommandCode::DeviceWatchdog, flags::REQUEST);
+        assert_eq!(PeerEvent::for_received(&dwr), Some(PeerEvent::RcvDwr));
+        let dpa = message(CommandCode::DisconnectPeer, 0);
+        assert_eq!(PeerEvent::for_received(&dpa), Some(PeerEvent::RcvDpa));
+        let cea = message(CommandCode::CapabilitiesExchange, 0);
+        assert_eq!(PeerEvent::for_received(&cea), None);
+
+        assert_eq!(PeerEvent::for_cea(&cea), PeerEvent::RcvCeaFailure);
+        let mut cea = cea;
+        cea.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+        assert_eq!(PeerEvent::for_cea(&cea), PeerEvent::RcvCeaSuccess);
+    }
+}
@@ -0,0 +1,147 @@
+//! Diameter Result-Code
+//!
+//! Named values for the Result-Code (268) AVP defined in RFC 6733, section 7.1.
+//! The thousands digit of a Result-Code gives its class, see [`ResultClass`].
+
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+/// Enumerates the Result-Code values defined in RFC 6733.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum ResultCode {
+    // Informational
+    MultiRoundAuth = 1001,
+    // Success
+    Success = 2001,
+    LimitedSuccess = 2002,
+    // Protocol errors
+    CommandUnsupported = 3001,
+    UnableToDeliver = 3002,
+    RealmNotServed = 3003,
+    TooBusy = 3004,
+    LoopDetected = 3005,
+    RedirectIndication = 3006,
+    ApplicationUnsupported = 3007,
+    InvalidHdrBits = 3008,
+    InvalidAvpBits = 3009,
+    UnknownPeer = 3010,
+    // Transient failures
+    AuthenticationRejected = 4001,
+    OutOfSpace = 4002,
+    ElectionLost = 4003,
+    // Permanent failures
+    AvpUnsupported = 5001,
+    UnknownSessionId = 5002,
+    AuthorizationRejected = 5003,
+    InvalidAvpValue = 5004,
+    MissingAvp = 5005,
+    ResourcesExceeded = 5006,
+    ContradictingAvps = 5007,
+    AvpNotAllowed = 5008,
+    AvpOccursTooManyTimes = 5009,
+    NoCommonApplication = 5010,
+    UnsupportedVersion = 5011,
+    UnableToComply = 5012,
+    InvalidBitInHeader = 5013,
+    InvalidAvpLength = 5014,
+    InvalidMessageLength = 5015,
+    InvalidAvpBitCombo = 5016,
+    NoCommonSecurity = 5017,
+}
+
+/// The class of a Result-Code, given by its thousands digit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultClass {
+    Informational,
+    Success,
+    ProtocolError,
+    TransientFailure,
+    PermanentFailure,
+}
+
+impl ResultCode {
+    pub fn from_u32(code: u32) -> Option<ResultCode> {
+        FromPrimitive::from_u32(code)
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn class(&self) -> ResultClass {
+        // Every defined value falls in one of the five classes
+        ResultClass::of(self.as_u32()).unwrap()
+    }
+}
+
+impl ResultClass {
+    /// Returns the class of a raw Result-Code, or `None` if it is outside 1xxx-5xxx.
+    pub fn of(code: u32) -> Option<ResultClass> {
+        match code / 1000 {
+            1 => Some(ResultClass::Informational),
+            2 => Some(ResultClass::Success),
+            3 => Some(ResultClass::ProtocolError),
+            4 => Some(ResultClass::TransientFailure),
+            5 => Some(ResultClass::PermanentFailure),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avp::flags::M;
+    use crate::avp::{Avp, Unsigned32};
+    use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+
+    #[test]
+    fn test_result_code_class() {
+        assert_eq!(
+            ResultCode::from_u32(1001).unwrap().class(),
+            ResultClass::Informational
+        );
+        assert_eq!(ResultCode::from_u32(2001), Some(ResultCode::Success));
+        assert_eq!(ResultCode::Success.class(), ResultClass::Success);
+        assert_eq!(ResultCode::UnableToDeliver.as_u32(), 3002);
+        assert_eq!(
+            ResultCode::UnableToDeliver.class(),
+            ResultClass::ProtocolError
+        );
+        assert_eq!(
+            ResultCode::OutOfSpace.class(),
+            ResultClass::TransientFailure
+        );
+        assert_eq!(ResultCode::AvpUnsupported.as_u32(), 5001);
+        assert_eq!(
+            ResultCode::AvpUnsupported.class(),
+            ResultClass::PermanentFailure
+        );
+        assert_eq!(ResultCode::from_u32(2999), None);
+    }
+
+    #[test]
+    fn test_result_class_of_raw_code() {
+        // Vendor-specific codes still have a class
+        assert_eq!(ResultClass::of(5030), Some(ResultClass::PermanentFailure));
+        assert_eq!(ResultClass::of(4010), Some(ResultClass::TransientFailure));
+        assert_eq!(ResultClass::of(0), None);
+        assert_eq!(ResultClass::of(6001), None);
+    }
+
+    #[test]
+    fn test_message_result_code() {
+        let mut cca = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+        );
+        assert_eq!(cca.result_code(), None);
+
+        cca.add_avp(Avp::new(268, None, M, Unsigned32::new(5001).into()));
+        assert_eq!(cca.result_code(), Some(ResultCode::AvpUnsupported));
+    }
+}
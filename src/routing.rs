@@ -0,0 +1,109 @@
+//! Diameter Request Routing
+//!
+//! Populates the routing AVPs of a request following RFC 6733, section 6.1.
+//! Every request carries Origin-Host, Origin-Realm and Destination-Realm.
+//! Destination-Host is only included once the request is bound to a specific
+//! server, e.g. for the continuation of a session; the initial request of a
+//! session omits it so any server in the destination realm can answer.
+
+use crate::avp::codes::ORIGIN_HOST;
+use crate::diameter::{DiameterMessage, DiameterMessageBuilder};
+
+/// Routing identities used when building requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routing {
+    pub origin_host: String,
+    pub origin_realm: String,
+    pub destination_realm: String,
+    pub destination_host: Option<String>,
+}
+
+impl Routing {
+    pub fn new(origin_host: &str, origin_realm: &str, destination_realm: &str) -> Routing {
+        Routing {
+            origin_host: origin_host.into(),
+            origin_realm: origin_realm.into(),
+            destination_realm: destination_realm.into(),
+            destination_host: None,
+        }
+    }
+
+    pub fn with_destination_host(mut self, destination_host: &str) -> Routing {
+        self.destination_host = Some(destination_host.into());
+        self
+    }
+
+    /// Binds subsequent requests to the server that sent `answer`, using
+    /// its Origin-Host as the Destination-Host.
+    pub fn update_from_answer(&mut self, answer: &DiameterMessage) {
+        if let Some(host) = answer
+            .get_avp(ORIGIN_HOST)
+            .and_then(|avp| avp.get_identity())
+        {
+            self.destination_host = Some(host.value().into());
+        }
+    }
+
+    /// Adds the routing AVPs to a request.
+    ///
+    /// Destination-Host is omitted when `initial` is true, and otherwise
+    /// added if a destination host is known.
+    pub fn apply(&self, builder: DiameterMessageBuilder, initial: bool) -> DiameterMessageBuilder {
+        let builder = builder
+            .origin(&self.origin_host, &self.origin_realm)
+            .destination_realm(&self.destination_realm);
+        match &self.destination_host {
+            Some(host) if !initial => builder.destination_host(host),
+            _ => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diameter::flags;
+    use crate::diameter::{ApplicationId, CommandCode};
+
+    fn ccr(routing: &Routing, initial: bool) -> DiameterMessage {
+        let builder =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST | flags::PROXYABLE);
+        routing.apply(builder, initial).build()
+    }
+
+    #[test]
+    fn test_initial_request_omits_destination_host() {
+        let routing = Routing::new("client.example.com", "example.com", "operator.net")
+            .with_destination_host("ocs1.operator.net");
+        let ccr = ccr(&routing, true);
+
+        assert_eq!(
+            ccr.get_avp(264).unwrap().get_identity().unwrap().value(),
+            "client.example.com"
+        );
+        assert_eq!(
+            ccr.get_avp(283).unwrap().get_identity().unwrap().value(),
+            "operator.net"
+        );
+        assert!(ccr.get_avp(293).is_none());
+    }
+
+    #[test]
+    fn test_continuation_includes_destination_host() {
+        let mut routing = Routing::new("client.example.com", "example.com", "operator.net");
+        assert!(ccr(&routing, false).get_avp(293).is_none());
+
+        let cca =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .origin("ocs2.operator.net", "operator.net")
+                .build();
+        routing.update_from_answer(&cca);
+
+        let ccr = ccr(&routing, false);
+        assert_eq!(
+            ccr.get_avp(293).unwrap().get_identity().unwrap().value(),
+            "ocs2.operator.net"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! `Serialize` and `Deserialize` for `DiameterMessage` and `Avp`, enabled by
+//! the `serde` feature.
+//!
+//! A message is rendered as its header fields and AVPs, and each AVP as its
+//! code, vendor id, flags and typed value, e.g. in JSON:
+//!
+//! ```json
+//! {"code":263,"vendor_id":null,"flags":64,"value":{"UTF8String":"ses;1"}}
+//! ```
+//!
+//! Lengths and padding are not serialized but recomputed on deserialization,
+//! so a round trip re-encodes to the same bytes. Octet strings, raw values
+//! and E.164 addresses are rendered as lowercase hex, times as RFC 3339.
+use crate::avp::address::Value;
+use crate::avp::{
+    Address, Avp, AvpValue, DiameterURI, Enumerated, Float32, Float64, Grouped, IPv4, IPv6,
+    Identity, Integer32, Integer64, OctetString, Time, UTF8String, Unsigned32, Unsigned64,
+};
+use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+use crate::hex;
+use chrono::{DateTime, Utc};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[derive(Serialize, Deserialize)]
+struct MessageRepr {
+    command_code: u32,
+    application_id: u32,
+    flags: u8,
+    hop_by_hop_id: u32,
+    end_to_end_id: u32,
+    avps: Vec<Avp>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AvpRepr {
+    code: u32,
+    vendor_id: Option<u32>,
+    flags: u8,
+    value: ValueRepr,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ValueRepr {
+    Address(AddressRepr),
+    AddressIPv4(Ipv4Addr),
+    AddressIPv6(Ipv6Addr),
+    Identity(String),
+    DiameterURI(String),
+    Enumerated(i32),
+    Float32(f32),
+    Float64(f64),
+    Grouped(Vec<Avp>),
+    Integer32(i32),
+    Integer64(i64),
+    OctetString(String),
+    Time(String),
+    Unsigned32(u32),
+    Unsigned64(u64),
+    UTF8String(String),
+    Raw(String),
+}
+
+#[derive(Serialize, Deserialize)]
+enum AddressRepr {
+    IPv4(Ipv4Addr),
+    IPv6(Ipv6Addr),
+    E164(String),
+}
+
+impl Serialize for DiameterMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MessageRepr {
+            command_code: self.get_command_code().as_u32(),
+            application_id: self.get_application_id().as_u32(),
+            flags: self.get_flags(),
+            hop_by_hop_id: self.get_hop_by_hop_id(),
+            end_to_end_id: self.get_end_to_end_id(),
+            avps: self.get_avps().clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DiameterMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DiameterMessage, D::Error> {
+        let repr = MessageRepr::deserialize(deserializer)?;
+        let mut message = DiameterMessage::new(
+            CommandCode::from_u32(repr.command_code),
+            ApplicationId::from_u32(repr.application_id),
+            repr.flags,
+            repr.hop_by_hop_id,
+            repr.end_to_end_id,
+        );
+        for avp in repr.avps {
+            message.add_avp(avp);
+        }
+        Ok(message)
+    }
+}
+
+impl Serialize for Avp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AvpRepr {
+            code: self.get_code(),
+            vendor_id: self.get_vendor_id(),
+            flags: self.get_flags().bits(),
+            value: ValueRepr::from(self.get_value()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Avp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Avp, D::Error> {
+        let repr = AvpRepr::deserialize(deserializer)?;
+        let value = repr.value.into_value().map_err(D::Error::custom)?;
+        Ok(Avp::new(repr.code, repr.vendor_id, repr.flags, value))
+    }
+}
+
+impl From<&AvpValue> for ValueRepr {
+    fn from(value: &AvpValue) -> ValueRepr {
+        match value {
+            AvpValue::Address(v) => ValueRepr::Address(match v.value() {
+                Value::IPv4(ip) => AddressRepr::IPv4(*ip),
+                Value::IPv6(ip) => AddressRepr::IPv6(*ip),
+                Value::E164(octets) => AddressRepr::E164(hex::encode(octets.value())),
+            }),
+            AvpValue::AddressIPv4(v) => ValueRepr::AddressIPv4(v.value()),
+            AvpValue::AddressIPv6(v) => ValueRepr::AddressIPv6(v.value()),
+            AvpValue::Identity(v) => ValueRepr::Identity(v.value().to_string()),
+            AvpValue::DiameterURI(v) => {
+                ValueRepr::DiameterURI(String::from_utf8_lossy(v.value()).into_owned())
+            }
+            AvpValue::Enumerated(v) => ValueRepr::Enumerated(v.value()),
+            AvpValue::Float32(v) => ValueRepr::Float32(v.value()),
+            AvpValue::Float64(v) => ValueRepr::Float64(v.value()),
+            AvpValue::Grouped(v) => ValueRepr::Grouped(v.avps().to_vec()),
+            AvpValue::Integer32(v) => ValueRepr::Integer32(v.value()),
+            AvpValue::Integer64(v) => ValueRepr::Integer64(v.value()),
+            AvpValue::OctetString(v) => ValueRepr::OctetString(hex::encode(v.value())),
+            AvpValue::Time(v) => ValueRepr::Time(v.value().to_rfc3339()),
+            AvpValue::Unsigned32(v) => ValueRepr::Unsigned32(v.value()),
+            AvpValue::Unsigned64(v) => ValueRepr::Unsigned64(v.value()),
+            AvpValue::UTF8String(v) => ValueRepr::UTF8String(v.value().to_string()),
+            AvpValue::Raw(v) => ValueRepr::Raw(hex::encode(v.value())),
+        }
+    }
+}
+
+impl ValueRepr {
+    fn into_value(self) -> Result<AvpValue, String> {
+        let value = match self {
+            ValueRepr::Address(AddressRepr::IPv4(ip)) => Address::from_ipv4(ip).into(),
+            ValueRepr::Address(AddressRepr::IPv6(ip)) => Address::from_ipv6(ip).into(),
+            ValueRepr::Address(AddressRepr::E164(hex)) => {
+                Address::from_e164(OctetString::new(from_hex(&hex)?)).into()
+            }
+            ValueRepr::AddressIPv4(ip) => IPv4::new(ip).into(),
+            ValueRepr::AddressIPv6(ip) => IPv6::new(ip).into(),
+            ValueRepr::Identity(v) => Identity::new(&v).into(),
+            ValueRepr::DiameterURI(v) => DiameterURI::new(v.into_bytes()).into(),
+            ValueRepr::Enumerated(v) => Enumerated::new(v).into(),
+            ValueRepr::Float32(v) => Float32::new(v).into(),
+            ValueRepr::Float64(v) => Float64::new(v).into(),
+            ValueRepr::Grouped(avps) => Grouped::new(avps).into(),
+            ValueRepr::Integer32(v) => Integer32::new(v).into(),
+            ValueRepr::Integer64(v) => Integer64::new(v).into(),
+            ValueRepr::OctetString(hex) => OctetString::new(from_hex(&hex)?).into(),
+            ValueRepr::Time(v) => {
+                let time = DateTime::parse_from_rfc3339(&v)
+                    .map_err(|e| format!("invalid time {}: {}", v, e))?;
+                Time::new(time.with_timezone(&Utc)).into()
+            }
+            ValueRepr::Unsigned32(v) => Unsigned32::new(v).into(),
+            ValueRepr::Unsigned64(v) => Unsigned64::new(v).into(),
+            ValueRepr::UTF8String(v) => UTF8String::new(&v).into(),
+            ValueRepr::Raw(hex) => AvpValue::Raw(OctetString::new(from_hex(&hex)?)),
+        };
+        Ok(value)
+    }
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    hex::decode(hex).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::avp;
+    use crate::avp::flags::M;
+    use crate::avp::{
+        Address, Avp, AvpValue, DiameterURI, Enumerated, Grouped, Identity, OctetString, Time,
+        UTF8String, Unsigned32, Unsigned64,
+    };
+    use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut message = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST | flags::PROXYABLE,
+            1123158611,
+            3102381851,
+        );
+        message.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        message.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
+        message.add_avp(avp!(
+            292,
+            None,
+            M,
+            DiameterURI::parse("aaa://host.example.com:3868").unwrap()
+        ));
+        message.add_avp(avp!(416, None, M, Enumerated::new(1)));
+        message.add_avp(avp!(257, None, M, Address::ipv4(10, 0, 0, 1)));
+        message.add_avp(avp!(
+            55,
+            None,
+            M,
+            Time::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap())
+        ));
+        message.add_avp(avp!(
+            456,
+            None,
+            M,
+            Grouped::new(vec![
+                avp!(432, None, M, Unsigned32::new(1000)),
+                avp!(421, None, M, Unsigned64::new(1 << 40)),
+            ])
+        ));
+        message.add_avp(avp!(1, Some(10415), 0, OctetString::new(vec![0, 1, 0xfe])));
+        message.add_avp(Avp::new(
+            9999,
+            None,
+            0,
+            AvpValue::Raw(OctetString::new(vec![0xde, 0xad])),
+        ));
+
+        let json = serde_json::to_string(&message).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["command_code"], 272);
+        assert_eq!(value["avps"][0]["code"], 263);
+        assert_eq!(value["avps"][0]["flags"], 64);
+        assert_eq!(value["avps"][0]["value"]["UTF8String"], "ses;12345888");
+        assert_eq!(value["avps"][7]["vendor_id"], 10415);
+        assert_eq!(value["avps"][7]["flags"], 128);
+        assert_eq!(value["avps"][7]["value"]["OctetString"], "0001fe");
+
+        let decoded: DiameterMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), message.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_json_invalid_value() {
+        let json = r#"{"code":268,"vendor_id":null,"flags":64,"value":{"OctetString":"0g"}}"#;
+        let err = serde_json::from_str::<Avp>(json).unwrap_err();
+        assert!(err.to_string().contains("invalid hex digits at offset 0"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Diameter Session-Id
+//!
+//! Helpers to generate and parse Session-Id (AVP 263) values using the format
+//! recommended by RFC 6733, section 8.8:
+//! ```text
+//! <DiameterIdentity>;<high 32 bits>;<low 32 bits>[;<optional value>]
+//! ```
+
+use crate::error::{Error, Result};
+use lazy_static::lazy_static;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // High 32 bits are seeded with the startup time, low 32 bits are a counter.
+    // Overflow of the low half carries into the high half.
+    static ref SESSION_COUNTER: AtomicU64 = {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        AtomicU64::new((now & 0xFFFF_FFFF) << 32)
+    };
+}
+
+/// A parsed Diameter Session-Id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionId {
+    pub origin_host: String,
+    pub high: u32,
+    pub low: u32,
+    pub optional: Option<String>,
+}
+
+impl SessionId {
+    /// Generates a new unique Session-Id for the given Origin-Host.
+    pub fn generate(origin_host: &str) -> String {
+        let id = SESSION_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let session_id = SessionId {
+            origin_host: origin_host.into(),
+            high: (id >> 32) as u32,
+            low: id as u32,
+            optional: None,
+        };
+        session_id.to_string()
+    }
+
+    /// Parses a Session-Id into its components.
+    pub fn parse(session_id: &str) -> Result<SessionId> {
+        let mut parts = session_id.splitn(4, ';');
+
+        let origin_host = match parts.next() {
+            Some(host) if !host.is_empty() => host.to_string(),
+            _ => {
+                return Err(Error::DecodeError(
+                    "invalid Session-Id, missing host".into(),
+                ))
+            }
+        };
+        let high = Self::parse_u32(parts.next(), session_id)?;
+        let low = Self::parse_u32(parts.next(), session_id)?;
+        let optional = parts.next().map(|s| s.to_string());
+
+        Ok(SessionId {
+            origin_host,
+            high,
+            low,
+            optional,
+        })
+    }
+
+    fn parse_u32(part: Option<&str>, session_id: &str) -> Result<u32> {
+        part.and_then(|s| s.parse::<u32>().ok())
+            .ok_or_else(|| Error::DecodeError(format!("invalid Session-Id: {}", session_id)))
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{};{};{}", self.origin_host, self.high, self.low)?;
+        if let Some(optional) = &self.optional {
+            write!(f, ";{}", optional)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_generate_unique() {
+        let ids: HashSet<String> = (0..1000)
+            .map(|_| SessionId::generate("host.example.com"))
+            .collect();
+        assert_eq!(ids.len(), 1000);
+    }
+
+    #[test]
+    fn test_generate_parse() {
+        let id = SessionId::generate("host.example.com");
+        let session_id = SessionId::parse(&id).unwrap();
+        assert_eq!(session_id.origin_host, "host.example.com");
+        assert_eq!(session_id.optional, None);
+        assert_eq!(session_id.to_string(), id);
+    }
+
+    #[test]
+    fn test_parse_optional() {
+        let session_id =
+            SessionId::parse("host.example.com;1876543210;523;mobile@200.1.1.88").unwrap();
+        assert_eq!(session_id.origin_host, "host.example.com");
+        assert_eq!(session_id.high, 1876543210);
+        assert_eq!(session_id.low, 523);
+        assert_eq!(session_id.optional, Some("mobile@200.1.1.88".into()));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(SessionId::parse("ses;12345888").is_err());
+        assert!(SessionId::parse(";1;2").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Incremental decoding of Diameter messages from a byte stream.
+//!
+//! [`StreamDecoder`] buffers bytes fed from any source, e.g. a blocking
+//! socket or a callback-based transport, and returns messages as they
+//! complete. It frames messages like the tokio `DiameterCodec`, without
+//! depending on tokio, so it is available without the `transport` feature.
+
+use crate::avp::DecodeOptions;
+use crate::diameter::{DiameterMessage, HEADER_LENGTH};
+use crate::error::{Error, Result};
+use bytes::{Bytes, BytesMut};
+
+/// Maximum size of a single Diameter message accepted by the decoder (1MB).
+pub const MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+
+/// A resumable decoder for messages arriving in arbitrary chunks.
+///
+/// ```
+/// use diameter::stream::StreamDecoder;
+/// # use diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+/// # let message = DiameterMessage::new(CommandCode::DeviceWatchdog, ApplicationId::Common, flags::REQUEST, 1, 1);
+/// # let bytes = message.to_bytes().unwrap();
+///
+/// let mut decoder = StreamDecoder::new();
+/// decoder.feed(&bytes[..7]);
+/// assert!(decoder.next_message().unwrap().is_none());
+/// decoder.feed(&bytes[7..]);
+/// assert!(decoder.next_message().unwrap().is_some());
+/// ```
+///
+/// A message whose header announces more than the maximum length, or less
+/// than a header, fails with an error; the bytes are then unframed, so the
+/// decoder keeps failing until `clear` is called. A complete message that
+/// fails to decode is consumed, and decoding continues with the next one.
+#[derive(Debug)]
+pub struct StreamDecoder {
+    buf: BytesMut,
+    max_length: usize,
+}
+
+impl StreamDecoder {
+    /// Creates a decoder accepting messages up to `MAX_MESSAGE_LENGTH` bytes.
+    pub fn new() -> StreamDecoder {
+        StreamDecoder::with_max_length(MAX_MESSAGE_LENGTH)
+    }
+
+    /// Creates a decoder accepting messages up to `max_length` bytes.
+    pub fn with_max_length(max_length: usize) -> StreamDecoder {
+        StreamDecoder {
+            buf: BytesMut::new(),
+            max_length,
+        }
+    }
+
+    /// Appends bytes read from the stream.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the next complete message, or `None` until more bytes are fed.
+    pub fn next_message(&mut self) -> Result<Option<DiameterMessage>> {
+        decode_frame(&mut self.buf, self.max_length)
+    }
+
+    /// Returns the number of buffered bytes not yet returned as a message.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Discards the buffered bytes, e.g. to resynchronize after an error.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        StreamDecoder::new()
+    }
+}
+
+// Splits the next complete message off `src` and decodes it, returning
+// `None` if `src` holds only part of one.
+pub(crate) fn decode_frame(
+    src: &mut BytesMut,
+    max_length: usize,
+) -> Result<Option<DiameterMessage>> {
+    if src.len() < 4 {
+        return Ok(None);
+    }
+
+    let length = frame_length(src, max_length)?;
+    if src.len() < length {
+        src.reserve(length - src.len());
+        return Ok(None);
+    }
+
+    decode_complete_frame(src.split_to(length).freeze()).map(Some)
+}
+
+// Returns the length announced by the header starting `src`, which holds at
+// least its first 4 bytes, checking it against `max_length` and the header
+// length.
+pub(crate) fn frame_length(src: &[u8], max_length: usize) -> Result<usize> {
+    let length = u32::from_be_bytes([0, src[1], src[2], src[3]]) as usize;
+
+    if length > max_length {
+        return Err(Error::MessageTooLarge {
+            size: length,
+            limit: max_length,
+        });
+    }
+    if length < HEADER_LENGTH as usize {
+        return Err(Error::DecodeError(
+            "invalid diameter message, length too short".into(),
+        ));
+    }
+    Ok(length)
+}
+
+// Decodes a frame holding exactly one message. OctetString payloads are
+// sliced from the frame rather than copied.
+pub(crate) fn decode_complete_frame(frame: Bytes) -> Result<DiameterMessage> {
+    DiameterMessage::decode_from_shared(&frame, &DecodeOptions::default())
+        .inspect_err(|e| log_decode_failure(&frame, e))
+}
+
+// Number of leading bytes logged for a frame whose header cannot be decoded.
+const LOGGED_PREFIX_LENGTH: usize = 32;
+
+// Logs a message that failed to decode with its hop-by-hop id, end-to-end id
+// and command code, so the failure can be correlated with a request. Frames
+// without a valid header are logged as hex instead.
+pub(crate) fn log_decode_failure(frame: &[u8], err: &Error) {
+    match DiameterMessage::decode_header(&mut &frame[..]) {
+        Ok(header) => log::error!(
+            "Failed to decode message; hop_by_hop_id {}, end_to_end_id {}, command code {}; error: {}",
+            header.get_hop_by_hop_id(),
+            header.get_end_to_end_id(),
+            header.get_command_code(),
+            err
+        ),
+        Err(_) => {
+            let prefix = &frame[..frame.len().min(LOGGED_PREFIX_LENGTH)];
+            let hex: Vec<String> = prefix.iter().map(|b| format!("{:02x}", b)).collect();
+            log::error!(
+                "Failed to decode message; first bytes {}; error: {}",
+                hex.join(" "),
+                err
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diameter::tests::ccr;
+
+    #[test]
+    fn test_split_at_every_boundary() {
+        let mut bytes = ccr(1).to_bytes().unwrap();
+        bytes.extend(ccr(2).to_bytes().unwrap());
+
+        for first in 0..bytes.len() {
+            for second in first..bytes.len() {
+                let mut decoder = StreamDecoder::new();
+                let mut decoded = vec![];
+                for chunk in [&bytes[..first], &bytes[first..second], &bytes[second..]] {
+                    decoder.feed(chunk);
+                    while let Some(msg) = decoder.next_message().unwrap() {
+                        decoded.push(msg.get_hop_by_hop_id());
+                    }
+                }
+                assert_eq!(decoded, vec![1, 2], "split at {} and {}", first, second);
+                assert_eq!(decoder.buffered(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_length() {
+        let bytes = ccr(1).to_bytes().unwrap();
+        let mut decoder = StreamDecoder::with_max_length(bytes.len() - 1);
+        decoder.feed(&bytes[..4]);
+        match decoder.next_message() {
+            Err(Error::MessageTooLarge { size, limit }) => {
+                assert_eq!(size, bytes.len());
+                assert_eq!(limit, bytes.len() - 1);
+            }
+            _ => panic!("Expected a MessageTooLarge error"),
+        }
+
+        decoder.clear();
+        assert_eq!(decoder.buffered(), 0);
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+}
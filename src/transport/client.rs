@@ -1,27 +1,232 @@
 //! Diameter Protocol Client
-use crate::diameter::DiameterMessage;
+use crate::avp::codes::RESULT_CODE;
+use crate::capabilities::PeerConfig;
+use crate::codec::DiameterCodec;
+use crate::diameter::flags;
+use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
 use crate::error::{Error, Result};
-use crate::transport::Codec;
-use std::collections::HashMap;
+use crate::peer::{PeerAction, PeerEvent, PeerState, PeerStateMachine};
+use crate::transport::connector::{Connector, DynConnector};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
-use std::ops::DerefMut;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::Receiver;
 use tokio::sync::oneshot::Sender;
 use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::codec::FramedRead;
+use tokio_util::sync::CancellationToken;
+
+/// Maximum time `DiameterClient::shutdown` waits for in-flight requests.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time a write to the socket may block before the connection is abandoned.
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default interval Tw at which a DWR is sent while the peer is open, as recommended by RFC 3539.
+pub const DEFAULT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default capacity of the queue of server-initiated requests.
+pub const DEFAULT_INCOMING_CAPACITY: usize = 1024;
+
+// Capacity of the connection event channel. Lagging subscribers miss the oldest events.
+const EVENTS_CAPACITY: usize = 16;
+
+// Number of hop-by-hop ids sent with `send_no_wait` that are remembered, so
+// their answers are dropped quietly. Older ids are forgotten first.
+const UNWAITED_CAPACITY: usize = 1024;
+
+/// A change in the state of the client's connection, reported by `DiameterClient::events`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// The client connected to the server.
+    Connected,
+    /// The capabilities exchange succeeded and the peer is open, if
+    /// `DiameterClientConfig::peer` is set.
+    Open,
+    /// The connection is down. Carries the reason, `Error::ConnectionClosed`
+    /// if the server closed the socket or the client was shut down.
+    Disconnected(Arc<Error>),
+    /// `DiameterClient::reconnect` is about to try connecting again.
+    Reconnecting,
+    /// A DWR was not answered in time, and the connection is being closed.
+    WatchdogFailed,
+}
 
 /// Configuration for a Diameter protocol client.
 ///
+/// Fields:
+///     use_tls: Whether to connect using TLS.
+///     verify_cert: Whether to verify the server certificate when using TLS.
+///     incoming_capacity: Maximum number of server-initiated requests queued for
+///         `incoming_requests`. Requests arriving while the queue is full are dropped.
+///     error_answers_as_err: Whether answers with the E flag set resolve to
+///         `Error::DiameterError` instead of `Ok` from `send_message` and `send_many`.
+///     tcp: Socket options applied to the TCP connection.
+///     write_timeout: Maximum time a write to the socket may block, e.g. on a peer
+///         that stopped reading. On expiry the writer is closed, and the requests
+///         awaiting an answer as well as every later send fail with `Error::Timeout`.
+///         `None` waits indefinitely.
+///     max_in_flight: Maximum number of requests awaiting an answer. `None` is
+///         unlimited.
+///     wait_for_permit: Whether a request over `max_in_flight` waits for an earlier
+///         one to complete instead of failing with `Error::TooManyRequests`.
+///     rate_limit: Maximum number of requests sent per second, see `rate_limit`.
+///         `None` is unlimited. Connecting fails if it is 0.
+///     wait_for_rate_limit: Whether a request over `rate_limit` waits for its turn
+///         instead of failing with `Error::RateLimited`.
+///     peer: If set, the identity sent in a CER on connect. The client then runs the
+///         peer state machine, and requests other than the CER fail until a
+///         successful CEA makes the peer `Open`. DWRs and DPRs from the server are
+///         answered by the client and not queued for `incoming_requests`. `None`
+///         sends no CER.
+///     watchdog_interval: With `peer` set, the interval at which a DWR is sent while
+///         the peer is open. A DWR not answered within the interval closes the
+///         connection. `None` sends no DWR.
 pub struct DiameterClientConfig {
     pub use_tls: bool,
     pub verify_cert: bool,
+    pub incoming_capacity: usize,
+    pub error_answers_as_err: bool,
+    pub tcp: TcpConfig,
+    pub write_timeout: Option<Duration>,
+    pub max_in_flight: Option<usize>,
+    pub wait_for_permit: bool,
+    pub rate_limit: Option<u32>,
+    pub wait_for_rate_limit: bool,
+    pub peer: Option<PeerConfig>,
+    pub watchdog_interval: Option<Duration>,
     // pub native_tls: Option<native_tls::Identity>, // Future Implementation
 }
 
+impl Default for DiameterClientConfig {
+    fn default() -> Self {
+        DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            incoming_capacity: DEFAULT_INCOMING_CAPACITY,
+            error_answers_as_err: false,
+            tcp: TcpConfig::default(),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+            max_in_flight: None,
+            wait_for_permit: true,
+            rate_limit: None,
+            wait_for_rate_limit: true,
+            peer: None,
+            watchdog_interval: Some(DEFAULT_WATCHDOG_INTERVAL),
+        }
+    }
+}
+
+impl DiameterClientConfig {
+    /// Limits the client to `rps` requests per second.
+    ///
+    /// Requests are paced one every `1 / rps` seconds, so no one-second
+    /// window holds more than `rps` of them, as carrier peers enforcing a
+    /// rate limit expect. A request over the limit waits for its turn, or
+    /// fails with `Error::RateLimited` if `wait_for_rate_limit` is false.
+    /// Answers and watchdogs sent via `send_answer` are not limited.
+    ///
+    /// Connecting fails with `Error::ValidationError` if `rps` is 0.
+    pub fn rate_limit(mut self, rps: u32) -> Self {
+        self.rate_limit = Some(rps);
+        self
+    }
+}
+
+// Paces requests to a fixed rate: a token bucket holding a single token,
+// tracked as the instant the next request may be sent.
+struct RateLimiter {
+    interval: Duration,
+    next: std::sync::Mutex<tokio::time::Instant>,
+}
+
+impl RateLimiter {
+    fn new(rps: u32) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs(1) / rps,
+            next: std::sync::Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    // Takes the next send slot, waiting for it if `wait`, otherwise failing
+    // with `RateLimited` if it is not due yet.
+    async fn acquire(&self, wait: bool) -> Result<()> {
+        let slot = {
+            let mut next = self.next.lock()?;
+            let now = tokio::time::Instant::now();
+            let slot = (*next).max(now);
+            if slot > now && !wait {
+                return Err(Error::RateLimited);
+            }
+            *next = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+        Ok(())
+    }
+}
+
+/// TCP socket options for a Diameter connection.
+///
+/// Fields:
+///     nodelay: Whether to set TCP_NODELAY, avoiding Nagle delays on small
+///         request/answer messages. Defaults to true.
+///     keepalive: If set, enables TCP keepalive with this idle time before the
+///         first probe, to detect dead peers at the socket layer.
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl TcpConfig {
+    fn apply(&self, stream: &TcpStream) -> Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            let keepalive = socket2::TcpKeepalive::new().with_time(time);
+            socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+// The transport a client opens its connections over.
+#[derive(Clone)]
+enum Transport {
+    // TCP, with the `TcpConfig` and native TLS of the client's config.
+    Tcp,
+    // rustls, with the config of the last `connect_tls`.
+    #[cfg(feature = "tls")]
+    Rustls(Arc<tokio_rustls::rustls::ClientConfig>),
+    // The connector of the last `connect_with`.
+    Connector(Arc<dyn DynConnector>),
+}
+
 /// A Diameter protocol client for sending and receiving Diameter messages.
 ///
 /// The client maintains a connection to a Diameter server and provides
@@ -29,16 +234,41 @@ pub struct DiameterClientConfig {
 ///
 /// Fields:
 ///     address: The address of the Diameter server to connect to.
-///     writer: An optional thread-safe writer for sending messages to the server.
+///     addresses: The addresses `reconnect` fails over across, in order of preference.
+///     transport: The transport `connect` and `reconnect` open connections over.
+///     writer: An optional handle to the writer task for sending messages to the server.
 ///     msg_caches: A shared, mutable hash map that maps message IDs to channels for sending responses back to the caller.
-///     seq_num: The next sequence number to use for a message.
-
+///     incoming_tx: A channel for forwarding server-initiated requests to the application.
+///     incoming_rx: The receiving end of `incoming_tx`, handed out by `incoming_requests`.
+///     incoming_dropped: The number of server-initiated requests dropped because the queue was full.
+///     shutdown: A signal used to stop the reader loop run by `handle`.
+///     drained: Notified whenever a pending request is answered, failed or removed.
+///     events: A channel broadcasting connection state changes.
+///     in_flight: Permits for the requests awaiting an answer, if `max_in_flight` is set.
+///     rate_limiter: Paces requests, if `rate_limit` is set.
+///     peer: The peer state machine, if `DiameterClientConfig::peer` is set.
+///     closed: Cancelled when the reader loop of the current connection stops; cancelling it closes that connection alone.
+///     seq_num: The last sequence number used for a message, shared with the watchdog.
 pub struct DiameterClient {
     config: DiameterClientConfig,
     address: String,
-    writer: Option<Arc<Mutex<dyn AsyncWrite + Send + Unpin>>>,
-    msg_caches: Arc<Mutex<HashMap<u32, Sender<DiameterMessage>>>>,
-    seq_num: u32,
+    addresses: Vec<String>,
+    transport: Transport,
+    writer: Option<MessageWriter>,
+    msg_caches: MsgCaches,
+    incoming_tx: mpsc::Sender<DiameterMessage>,
+    incoming_rx: Option<mpsc::Receiver<DiameterMessage>>,
+    incoming_dropped: Arc<AtomicU64>,
+    unwaited: Unwaited,
+    shutdown: Arc<Notify>,
+    drained: Arc<Notify>,
+    events: broadcast::Sender<ConnectionEvent>,
+    encode_buf: BytesMut,
+    in_flight: Option<Arc<Semaphore>>,
+    rate_limiter: Option<RateLimiter>,
+    peer: Option<PeerHandle>,
+    closed: CancellationToken,
+    seq_num: Arc<AtomicU32>,
 }
 
 impl DiameterClient {
@@ -53,54 +283,471 @@ impl DiameterClient {
     /// Returns:
     ///     A new instance of `DiameterClient`.
     pub fn new(addr: &str, config: DiameterClientConfig) -> DiameterClient {
+        let (incoming_tx, incoming_rx) = mpsc::channel(config.incoming_capacity);
+        let in_flight = config
+            .max_in_flight
+            .map(|max_in_flight| Arc::new(Semaphore::new(max_in_flight)));
+        let rate_limiter = config
+            .rate_limit
+            .filter(|&rps| rps > 0)
+            .map(RateLimiter::new);
+        let peer = config
+            .peer
+            .as_ref()
+            .map(|_| Arc::new(std::sync::Mutex::new(PeerStateMachine::new())));
         DiameterClient {
             config,
             address: addr.into(),
+            addresses: vec![addr.into()],
+            transport: Transport::Tcp,
             writer: None,
             msg_caches: Arc::new(Mutex::new(HashMap::new())),
-            seq_num: 0,
+            incoming_tx,
+            incoming_rx: Some(incoming_rx),
+            incoming_dropped: Arc::new(AtomicU64::new(0)),
+            unwaited: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            shutdown: Arc::new(Notify::new()),
+            drained: Arc::new(Notify::new()),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            encode_buf: BytesMut::new(),
+            in_flight,
+            rate_limiter,
+            peer,
+            closed: CancellationToken::new(),
+            seq_num: Arc::new(AtomicU32::new(0)),
         }
     }
 
     /// Establishes a connection to the Diameter server.
     ///
+    /// Connects over TCP, or as the last `connect_tls` or `connect_with` did.
+    ///
     /// Returns:
     ///    A `Result` containing a `ClientHandler` or an error if the connection cannot be established.
     pub async fn connect(&mut self) -> Result<ClientHandler> {
-        let stream = TcpStream::connect(self.address.clone()).await?;
+        self.validate_config()?;
+        let addr = self.address.clone();
+        self.connect_to(&addr).await
+    }
+
+    /// Establishes a connection to the Diameter server, giving up after `timeout`.
+    ///
+    /// `connect` waits as long as the OS does, which against an unreachable
+    /// host is often 20 seconds or more. The timeout covers the TLS handshake
+    /// if TLS is enabled.
+    ///
+    /// Args:
+    ///     timeout: The maximum time to wait for the connection.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler`, or `Error::Timeout` if the connection
+    ///    was not established in time.
+    pub async fn connect_timeout(&mut self, timeout: Duration) -> Result<ClientHandler> {
+        tokio::time::timeout(timeout, self.connect())
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Re-establishes the connection to the Diameter server, e.g. after a
+    /// `Disconnected` event.
+    ///
+    /// Sends a `Reconnecting` event before each attempt, and waits `delay`
+    /// after each failed one. Requests that were pending on the old
+    /// connection have already failed; the new connection starts empty.
+    /// Each attempt connects as `connect` does, over the same transport.
+    ///
+    /// The first attempt goes to the current address. After `connect_any`,
+    /// each failed attempt moves on to the next of its addresses, wrapping
+    /// around, so a peer that went down fails over to the next one.
+    ///
+    /// Args:
+    ///     attempts: The maximum number of connection attempts.
+    ///     delay: The time to wait between attempts.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler`, or the last connection error if every attempt failed.
+    pub async fn reconnect(&mut self, attempts: u32, delay: Duration) -> Result<ClientHandler> {
+        let mut last_err = Error::ClientError("No reconnect attempts given".into());
+        for attempt in 1..=attempts {
+            let _ = self.events.send(ConnectionEvent::Reconnecting);
+            match self.connect().await {
+                Ok(handler) => return Ok(handler),
+                Err(e) => {
+                    log::warn!(
+                        "Reconnect attempt {} to {} failed: {}",
+                        attempt,
+                        self.address,
+                        e
+                    );
+                    last_err = e;
+                    self.next_address();
+                }
+            }
+            if attempt < attempts {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Establishes a connection to the first reachable Diameter server.
+    ///
+    /// Tries each address in order until one connects. The address that
+    /// connected becomes the client's address, so a later `connect` goes
+    /// back to the live peer. The addresses are kept for `reconnect` to fail
+    /// over across.
+    ///
+    /// Args:
+    ///     addrs: The addresses of the Diameter servers, in order of preference.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler`, or the last connection error if no address could be reached.
+    pub async fn connect_any(&mut self, addrs: &[&str]) -> Result<ClientHandler> {
+        self.validate_config()?;
+        let mut last_err = Error::ClientError("No server addresses given".into());
+        if !addrs.is_empty() {
+            self.addresses = addrs.iter().map(|addr| addr.to_string()).collect();
+        }
+        for addr in addrs {
+            match self.connect_to(addr).await {
+                Ok(handler) => {
+                    self.address = addr.to_string();
+                    return Ok(handler);
+                }
+                Err(e) => {
+                    log::warn!("Failed to connect to {}: {}", addr, e);
+                    last_err = e;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Establishes a connection to the Diameter server from a specific local address.
+    ///
+    /// Binds the socket to `local` before connecting, e.g. to originate Diameter
+    /// traffic from a particular interface on a multi-homed host.
+    ///
+    /// Args:
+    ///     local: The local address to bind to. Use port 0 to let the OS pick a port.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler` or an error if the connection cannot be established.
+    pub async fn connect_from(&mut self, local: SocketAddr) -> Result<ClientHandler> {
+        self.validate_config()?;
+        let remote = tokio::net::lookup_host(self.address.clone())
+            .await?
+            .find(|addr| addr.is_ipv4() == local.is_ipv4())
+            .ok_or_else(|| {
+                Error::ClientError(format!(
+                    "No address of {} matches the family of {}",
+                    self.address, local
+                ))
+            })?;
+
+        let socket = if local.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(local)?;
+        let stream = socket.connect(remote).await?;
+        let addr = self.address.clone();
+        self.connect_stream(stream, &addr).await
+    }
+
+    /// Establishes a TLS connection to a Diameter server with rustls.
+    ///
+    /// The server certificate is validated against the roots and verifier of
+    /// `config`, for the host part of `addr`. As with `connect_any`, `addr`
+    /// becomes the client's address. `use_tls` and `verify_cert`, which apply
+    /// to the native TLS connection made by `connect`, are ignored. The client
+    /// keeps `config`, so later `connect`, `connect_any` and `reconnect` calls
+    /// run the rustls handshake too.
+    ///
+    /// Args:
+    ///     addr: The address of the Diameter server, e.g. "aaa.example.com:3868".
+    ///     config: The rustls client configuration.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler` or an error if the connection or the
+    ///    TLS handshake fails.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        &mut self,
+        addr: &str,
+        config: Arc<tokio_rustls::rustls::ClientConfig>,
+    ) -> Result<ClientHandler> {
+        self.validate_config()?;
+        self.transport = Transport::Rustls(config);
+        let handler = self.connect_to(addr).await?;
+        self.address = addr.to_string();
+        Ok(handler)
+    }
+
+    /// Establishes a connection to the Diameter server over the transport of
+    /// `connector`, e.g. `SctpConnector` with the `sctp` feature.
+    ///
+    /// The stream is used as the connector returns it: `tcp`, `use_tls` and
+    /// `verify_cert`, which apply to the TCP connection made by `connect`,
+    /// are ignored. The client keeps the connector, so later `connect`,
+    /// `connect_any` and `reconnect` calls open their connections over it too.
+    ///
+    /// Args:
+    ///     connector: The `Connector` opening the stream to the client's address.
+    ///
+    /// Returns:
+    ///    A `Result` containing a `ClientHandler` or an error if the connection cannot be established.
+    pub async fn connect_with<C>(&mut self, connector: &C) -> Result<ClientHandler>
+    where
+        C: Connector + Clone + Send + Sync + 'static,
+    {
+        self.validate_config()?;
+        self.transport = Transport::Connector(Arc::new(connector.clone()));
+        let addr = self.address.clone();
+        self.connect_to(&addr).await
+    }
 
+    // Connects to `addr` over the client's transport.
+    async fn connect_to(&mut self, addr: &str) -> Result<ClientHandler> {
+        match self.transport.clone() {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(addr).await?;
+                self.connect_stream(stream, addr).await
+            }
+            #[cfg(feature = "tls")]
+            Transport::Rustls(config) => {
+                let server_name = tls_server_name(addr)?;
+                let stream = TcpStream::connect(addr).await?;
+                self.config.tcp.apply(&stream)?;
+                let connector = tokio_rustls::TlsConnector::from(config);
+                let tls_stream = connector.connect(server_name, stream).await?;
+                self.finish_connect(tls_stream).await
+            }
+            Transport::Connector(connector) => {
+                let stream = connector.connect_boxed(addr).await?;
+                self.finish_connect(stream).await
+            }
+        }
+    }
+
+    // Applies the socket options and, if `use_tls` is set, runs the TLS
+    // handshake for the host of `addr` before handing the stream to
+    // `finish_connect`.
+    async fn connect_stream(&mut self, stream: TcpStream, addr: &str) -> Result<ClientHandler> {
+        self.config.tcp.apply(&stream)?;
         if self.config.use_tls {
             let tls_connector = tokio_native_tls::TlsConnector::from(
                 native_tls::TlsConnector::builder()
                     .danger_accept_invalid_certs(!self.config.verify_cert)
                     .build()?,
             );
-            let tls_stream = tls_connector.connect(&self.address.clone(), stream).await?;
-            let (reader, writer) = tokio::io::split(tls_stream);
-
-            // writer
-            let writer = Arc::new(Mutex::new(writer));
-            self.writer = Some(writer);
-
-            // reader
-            let msg_caches = Arc::clone(&self.msg_caches);
-            Ok(ClientHandler {
-                reader: Box::new(reader),
-                msg_caches,
-            })
+            let tls_stream = tls_connector.connect(addr, stream).await?;
+            self.finish_connect(tls_stream).await
         } else {
-            let (reader, writer) = tokio::io::split(stream);
+            self.finish_connect(stream).await
+        }
+    }
 
-            // writer
-            let writer = Arc::new(Mutex::new(writer));
-            self.writer = Some(writer);
+    // Moves to the address after the current one in `addresses`, wrapping around.
+    fn next_address(&mut self) {
+        if let Some(i) = self.addresses.iter().position(|addr| *addr == self.address) {
+            self.address = self.addresses[(i + 1) % self.addresses.len()].clone();
+        }
+    }
 
-            // reader
-            let msg_caches = Arc::clone(&self.msg_caches);
-            Ok(ClientHandler {
-                reader: Box::new(reader),
-                msg_caches,
-            })
+    // Rejects settings no connection can be made with, before connecting.
+    fn validate_config(&self) -> Result<()> {
+        if self.config.rate_limit == Some(0) {
+            return Err(Error::ValidationError(
+                "rate_limit must allow at least 1 request per second".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Completes a connection attempt over a stream that is ready for
+    // Diameter messages, sending the CER if the peer state machine is
+    // running. The peer is only started here, so a connection attempt that
+    // fails or is cancelled before leaves it untouched.
+    async fn finish_connect<S>(&mut self, stream: S) -> Result<ClientHandler>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut guard = PeerConnectGuard::start(&self.peer)?;
+        let handler = self.split(stream);
+        guard.conn_ack();
+        self.send_cer().await?;
+        guard.disarm();
+        Ok(handler)
+    }
+
+    // Sends the CER if `DiameterClientConfig::peer` is set. A task waits for
+    // the CEA, opening the peer and starting the watchdog on success and
+    // closing the connection otherwise.
+    async fn send_cer(&mut self) -> Result<()> {
+        let (peer, writer) = match (&self.peer, &self.writer) {
+            (Some(peer), Some(writer)) => (Arc::clone(peer), writer.clone()),
+            _ => return Ok(()),
+        };
+        let config = match &self.config.peer {
+            Some(config) => config.clone(),
+            None => return Ok(()),
+        };
+        let seq_num = self.get_next_seq_num();
+        let cer = config.cer(seq_num, seq_num);
+        let rx = Self::register(&self.msg_caches, &self.drained, &cer, seq_num, None).await?;
+        writer.send_with_buffer(&cer, &mut self.encode_buf)?;
+
+        let events = self.events.clone();
+        let watchdog = self.config.watchdog_interval.map(|interval| Watchdog {
+            interval,
+            config,
+            peer: Arc::clone(&peer),
+            writer: writer.clone(),
+            msg_caches: Arc::clone(&self.msg_caches),
+            drained: Arc::clone(&self.drained),
+            seq_num: Arc::clone(&self.seq_num),
+            events: self.events.clone(),
+            closed: self.closed.clone(),
+        });
+        let closed = self.closed.clone();
+        tokio::spawn(async move {
+            let event = match rx.await {
+                Ok(Ok(cea)) => PeerEvent::for_cea(&cea),
+                // The connection went down first
+                _ => return,
+            };
+            apply_peer_event(&Some(peer), event);
+            if event == PeerEvent::RcvCeaSuccess {
+                let _ = events.send(ConnectionEvent::Open);
+                if let Some(watchdog) = watchdog {
+                    watchdog.run().await;
+                }
+            } else {
+                log::error!("Capabilities exchange failed, closing the connection");
+                closed.cancel();
+            }
+        });
+        Ok(())
+    }
+
+    // Splits the stream, keeping the writer on the client and returning
+    // a `ClientHandler` that owns the reader.
+    fn split<S>(&mut self, stream: S) -> ClientHandler
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+
+        // writer
+        let (tx, rx) = mpsc::unbounded_channel();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        tokio::spawn(Self::write_loop(
+            writer,
+            rx,
+            self.config.write_timeout,
+            Arc::clone(&timed_out),
+            Arc::clone(&self.msg_caches),
+        ));
+        let writer = MessageWriter { tx, timed_out };
+        self.writer = Some(writer.clone());
+        self.closed = CancellationToken::new();
+
+        let _ = self.events.send(ConnectionEvent::Connected);
+
+        // reader
+        let msg_caches = Arc::clone(&self.msg_caches);
+        ClientHandler {
+            reader: Box::new(reader),
+            msg_caches,
+            incoming_tx: self.incoming_tx.clone(),
+            incoming_dropped: Arc::clone(&self.incoming_dropped),
+            unwaited: Arc::clone(&self.unwaited),
+            shutdown: Arc::clone(&self.shutdown),
+            events: self.events.clone(),
+            peer: self.peer.clone(),
+            peer_config: self.config.peer.clone(),
+            writer,
+            closed: self.closed.clone(),
+        }
+    }
+
+    // Writes queued messages to the stream. Messages queued while a write is
+    // in progress are coalesced into a single buffer and written together.
+    // A write blocked for longer than `write_timeout` sets `timed_out` and
+    // stops the task, so later sends fail instead of queueing forever, and
+    // fails every request awaiting an answer, since their writes may never
+    // have reached the peer.
+    async fn write_loop<W>(
+        mut writer: W,
+        mut rx: UnboundedReceiver<WriterCommand>,
+        write_timeout: Option<Duration>,
+        timed_out: Arc<AtomicBool>,
+        msg_caches: MsgCaches,
+    ) where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = Vec::new();
+        while let Some(command) = rx.recv().await {
+            // Coalesce queued writes, flushing once at the end if any asked to
+            let mut command = Some(command);
+            let mut flush = false;
+            loop {
+                match command {
+                    Some(WriterCommand::Write(bytes)) => {
+                        buf.extend_from_slice(&bytes);
+                        flush = true;
+                    }
+                    Some(WriterCommand::WriteNoFlush(bytes)) => buf.extend_from_slice(&bytes),
+                    _ => break,
+                }
+                command = rx.try_recv().ok();
+            }
+            if let Some(WriterCommand::Flush(_)) = command {
+                flush = true;
+            }
+
+            if !buf.is_empty() || flush {
+                let write = async {
+                    writer.write_all(&buf).await?;
+                    if flush {
+                        writer.flush().await?;
+                    }
+                    Ok::<(), std::io::Error>(())
+                };
+                let result = match write_timeout {
+                    Some(write_timeout) => tokio::time::timeout(write_timeout, write).await,
+                    None => Ok(write.await),
+                };
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        log::error!("Failed to write to socket; error: {:?}", e);
+                        return;
+                    }
+                    Err(_) => {
+                        log::error!(
+                            "Write to socket blocked for more than {:?}, closing writer",
+                            write_timeout.unwrap_or_default()
+                        );
+                        timed_out.store(true, Ordering::SeqCst);
+                        rx.close();
+                        for (_, pending) in msg_caches.lock().await.drain() {
+                            let _ = pending.sender.send(Err(Error::Timeout));
+                        }
+                        return;
+                    }
+                }
+                buf.clear();
+            }
+
+            if let Some(WriterCommand::Flush(done)) = command {
+                let _ = done.send(Ok(()));
+            } else if let Some(WriterCommand::Shutdown(done)) = command {
+                let _ = done.send(writer.shutdown().await.map_err(Error::from));
+                return;
+            }
         }
     }
 
@@ -118,7 +765,7 @@ impl DiameterClient {
     ///
     ///    #[tokio::main]
     ///    async fn main() {
-    ///        let config = DiameterClientConfig { use_tls: false, verify_cert: false };
+    ///        let config = DiameterClientConfig { use_tls: false, verify_cert: false, ..Default::default() };
     ///        let mut client = DiameterClient::new("localhost:3868", config);
     ///        let mut handler = client.connect().await.unwrap();
     ///        tokio::spawn(async move {
@@ -127,28 +774,174 @@ impl DiameterClient {
     ///    }
     ///    ```
     pub async fn handle(handler: &mut ClientHandler) {
+        let reason = Self::read_loop(handler).await;
+        handler.closed.cancel();
+        apply_peer_event(&handler.peer, PeerEvent::PeerDisconnected);
+
+        // No answer can arrive any more, so fail the requests still awaiting one
+        let pending: Vec<PendingRequest> = handler
+            .msg_caches
+            .lock()
+            .await
+            .drain()
+            .map(|(_, pending)| pending)
+            .collect();
+        if !pending.is_empty() {
+            log::warn!(
+                "Failing {} pending requests, connection down; reason: {}",
+                pending.len(),
+                reason
+            );
+        }
+        for pending in pending {
+            let _ = pending.sender.send(Err(Error::ConnectionClosed));
+        }
+
+        let _ = handler
+            .events
+            .send(ConnectionEvent::Disconnected(Arc::new(reason)));
+    }
+
+    // Reads and dispatches messages until the connection is closed, returning the reason.
+    async fn read_loop(handler: &mut ClientHandler) -> Error {
+        let mut frames = FramedRead::new(&mut handler.reader, DiameterCodec::new());
         loop {
-            match Codec::decode(&mut handler.reader).await {
+            let frame = tokio::select! {
+                _ = handler.shutdown.notified() => {
+                    log::info!("Client shutdown, stopping reader");
+                    return Error::ConnectionClosed;
+                }
+                _ = handler.closed.cancelled() => {
+                    log::info!("Connection closed, stopping reader");
+                    return Error::ConnectionClosed;
+                }
+                frame = frames.next() => match frame {
+                    Some(frame) => frame,
+                    None => return Error::ConnectionClosed,
+                },
+            };
+            match frame {
                 Ok(res) => {
-                    if let Err(e) = Self::process_decoded_msg(handler.msg_caches.clone(), res).await
-                    {
-                        log::error!("Failed to process response; error: {:?}", e);
-                        return;
+                    if let Some(event) = PeerEvent::for_received(&res) {
+                        if Self::handle_peer_message(
+                            &handler.peer,
+                            &handler.peer_config,
+                            &handler.writer,
+                            event,
+                            &res,
+                        ) {
+                            continue;
+                        }
                     }
+                    Self::process_decoded_msg(
+                        handler.msg_caches.clone(),
+                        &handler.incoming_tx,
+                        &handler.incoming_dropped,
+                        &handler.unwaited,
+                        res,
+                    )
+                    .await
+                }
+                // The stream ended without the codec seeing it, e.g. a TLS
+                // close; a truncated frame is a `DecodeError` from the codec
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Error::ConnectionClosed;
                 }
                 Err(e) => {
                     log::error!("Failed to read message from socket; error: {:?}", e);
-                    return;
+                    return e;
                 }
             }
         }
     }
 
+    // Applies the event of a received DWR, DWA, DPR or DPA to the peer state
+    // machine, if there is one. The client then owns the peer, so it sends
+    // the DWA or DPA the state machine asks for, and the request is not
+    // forwarded to `incoming_requests`. Returns whether `msg` was consumed.
+    fn handle_peer_message(
+        peer: &Option<PeerHandle>,
+        peer_config: &Option<PeerConfig>,
+        writer: &MessageWriter,
+        event: PeerEvent,
+        msg: &DiameterMessage,
+    ) -> bool {
+        let (Some(peer), Some(config)) = (peer, peer_config) else {
+            return false;
+        };
+        let actions: &[PeerAction] = match peer.lock() {
+            Ok(mut peer) => match peer.transition(event) {
+                Ok((_, actions)) => actions,
+                Err(e) => {
+                    log::warn!("Ignoring peer event; {}", e);
+                    &[]
+                }
+            },
+            Err(e) => {
+                log::error!("Failed to lock peer state; error: {}", e);
+                &[]
+            }
+        };
+
+        let answer = if actions.contains(&PeerAction::ISndDwa) {
+            Some(config.dwa(msg))
+        } else if actions.contains(&PeerAction::ISndDpa) {
+            Some(config.dpa(msg))
+        } else {
+            None
+        };
+        if let Some(answer) = answer {
+            if let Err(e) = writer.send(&answer) {
+                log::error!(
+                    "Failed to answer peer request; hop_by_hop_id {}, command code {}, error: {}",
+                    msg.get_hop_by_hop_id(),
+                    msg.get_command_code(),
+                    e
+                );
+            }
+        }
+        msg.get_flags() & flags::REQUEST != 0
+    }
+
+    // Dispatches an inbound message. An answer that cannot be delivered is
+    // logged and dropped, so one stray answer does not stop the reader.
     async fn process_decoded_msg(
-        msg_caches: Arc<Mutex<HashMap<u32, Sender<DiameterMessage>>>>,
+        msg_caches: MsgCaches,
+        incoming_tx: &mpsc::Sender<DiameterMessage>,
+        incoming_dropped: &AtomicU64,
+        unwaited: &Unwaited,
         res: DiameterMessage,
-    ) -> Result<()> {
+    ) {
+        trace_message("Received", &res);
+
+        // Server-initiated requests are not matched against pending requests
+        if res.get_flags() & flags::REQUEST != 0 {
+            match incoming_tx.try_send(res) {
+                Ok(()) => {}
+                Err(TrySendError::Full(res)) => {
+                    incoming_dropped.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "Dropping server-initiated request, queue full; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+                        res.get_hop_by_hop_id(),
+                        res.get_end_to_end_id(),
+                        res.get_command_code()
+                    );
+                }
+                Err(TrySendError::Closed(res)) => {
+                    log::warn!(
+                        "Dropping server-initiated request, no receiver; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+                        res.get_hop_by_hop_id(),
+                        res.get_end_to_end_id(),
+                        res.get_command_code()
+                    );
+                }
+            }
+            return;
+        }
+
         let hop_by_hop = res.get_hop_by_hop_id();
+        let end_to_end = res.get_end_to_end_id();
+        let command_code = res.get_command_code();
 
         let sender_opt = {
             let mut msg_caches = msg_caches.lock().await;
@@ -156,19 +949,47 @@ impl DiameterClient {
             msg_caches.remove(&hop_by_hop)
         };
         match sender_opt {
-            Some(sender) => {
-                sender.send(res).map_err(|e| {
-                    Error::ClientError(format!("Failed to send response; error: {:?}", e))
-                })?;
+            Some(pending) => {
+                // Fail the request rather than deliver an answer to a different command
+                let res = if res.get_application_id() != pending.application_id
+                    || res.get_command_code() != pending.command_code
+                {
+                    Err(Error::AnswerMismatch {
+                        hop_by_hop_id: hop_by_hop,
+                        application_id: res.get_application_id(),
+                        command_code: res.get_command_code(),
+                        expected_application_id: pending.application_id,
+                        expected_command_code: pending.command_code,
+                    })
+                } else {
+                    Ok(res)
+                };
+                if pending.sender.send(res).is_err() {
+                    log::warn!(
+                        "Dropping answer, request no longer awaited; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+                        hop_by_hop,
+                        end_to_end,
+                        command_code
+                    );
+                }
+            }
+            None if take_unwaited(unwaited, hop_by_hop) => {
+                log::debug!(
+                    "Dropping answer to a request sent without waiting; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+                    hop_by_hop,
+                    end_to_end,
+                    command_code
+                );
             }
             None => {
-                Err(Error::ClientError(format!(
-                    "No request found for hop_by_hop_id {}",
-                    hop_by_hop
-                )))?;
+                log::warn!(
+                    "Dropping answer, no request found; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+                    hop_by_hop,
+                    end_to_end,
+                    command_code
+                );
             }
         };
-        Ok(())
     }
 
     /// Sends a Diameter message and returns a future for receiving the response.
@@ -181,24 +1002,418 @@ impl DiameterClient {
     ///
     pub async fn send_message(&mut self, req: DiameterMessage) -> Result<ResponseFuture> {
         if let Some(writer) = &self.writer {
-            let (tx, rx) = oneshot::channel();
-            let hop_by_hop = req.get_hop_by_hop_id();
+            self.check_peer_send(&req)?;
+            let permit = self.acquire_permit().await?;
+            let rx = Self::register(
+                &self.msg_caches,
+                &self.drained,
+                &req,
+                req.get_hop_by_hop_id(),
+                permit,
+            )
+            .await?;
+            if let Err(e) = writer.send_with_buffer(&req, &mut self.encode_buf) {
+                self.msg_caches
+                    .lock()
+                    .await
+                    .remove(&req.get_hop_by_hop_id());
+                return Err(e);
+            }
+            Ok(ResponseFuture {
+                receiver: rx,
+                error_answers_as_err: self.config.error_answers_as_err,
+            })
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Sends a Diameter message without waiting for its answer, e.g. for
+    /// fire-and-forget accounting.
+    ///
+    /// No response channel is registered, so nothing is left pending if the
+    /// answer never comes; an answer that does come is dropped.
+    ///
+    /// Args:
+    ///     req: The Diameter message to send.
+    ///
+    /// Returns:
+    ///     A `Result` indicating whether the message was queued for writing.
+    pub async fn send_no_wait(&mut self, req: DiameterMessage) -> Result<()> {
+        if let Some(writer) = &self.writer {
+            self.check_peer_send(&req)?;
+            self.acquire_rate_limit().await?;
+            // Remembered before writing, since the answer may come before the
+            // write returns
             {
-                let mut msg_caches = self.msg_caches.lock().await;
-                msg_caches.insert(hop_by_hop, tx);
+                let mut unwaited = self.unwaited.lock()?;
+                if unwaited.len() == UNWAITED_CAPACITY {
+                    unwaited.pop_front();
+                }
+                unwaited.push_back(req.get_hop_by_hop_id());
+            }
+            let result = writer.send_with_buffer(&req, &mut self.encode_buf);
+            if result.is_err() {
+                take_unwaited(&self.unwaited, req.get_hop_by_hop_id());
             }
-            let mut writer = writer.lock().await;
-            Codec::encode(&mut writer.deref_mut(), &req).await?;
-            Ok(ResponseFuture { receiver: rx })
+            result
         } else {
-            Err(Error::ClientError("Not connected".into()))
+            Err(Error::NotConnected)
         }
     }
 
+    /// Waits until every message sent so far is written and flushed to the
+    /// transport, e.g. after a batch of `DiameterRequest::send_no_flush`.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of the flush.
+    pub async fn flush(&self) -> Result<()> {
+        match &self.writer {
+            Some(writer) => writer.flush().await,
+            None => Err(Error::NotConnected),
+        }
+    }
+
+    /// Sends an answer to a server-initiated request, such as a Re-Auth-Answer.
+    ///
+    /// Args:
+    ///     answer: The answer, with the Hop-by-Hop Identifier of the request it answers.
+    ///
+    /// Returns:
+    ///     A `Result` indicating whether the answer was queued for writing.
+    pub async fn send_answer(&mut self, answer: DiameterMessage) -> Result<()> {
+        if answer.get_flags() & flags::REQUEST != 0 {
+            return Err(Error::ClientError(format!(
+                "send_answer called with a request; hop_by_hop_id {}",
+                answer.get_hop_by_hop_id()
+            )));
+        }
+        match &self.writer {
+            Some(writer) => writer.send_with_buffer(&answer, &mut self.encode_buf),
+            None => Err(Error::NotConnected),
+        }
+    }
+
+    /// Sends multiple Diameter messages and waits for all of their responses.
+    ///
+    /// Each response channel is registered before its request is written, and
+    /// the requests are written back-to-back, so the responses are awaited
+    /// concurrently instead of one round trip at a time. With `max_in_flight`
+    /// set, a request waiting for a permit holds back the ones after it.
+    ///
+    /// Args:
+    ///     reqs: The Diameter messages to send.
+    ///
+    /// Returns:
+    ///     The result for each request, in the same order as `reqs`.
+    pub async fn send_many(&mut self, reqs: Vec<DiameterMessage>) -> Vec<Result<DiameterMessage>> {
+        let writer = match &self.writer {
+            Some(writer) => writer.clone(),
+            None => return reqs.iter().map(|_| Err(Error::NotConnected)).collect(),
+        };
+
+        let mut receivers = Vec::with_capacity(reqs.len());
+        for req in &reqs {
+            if let Err(e) = self.check_peer_send(req) {
+                receivers.push(Err(e));
+                continue;
+            }
+            let rx = match self.acquire_permit().await {
+                Ok(permit) => {
+                    Self::register(
+                        &self.msg_caches,
+                        &self.drained,
+                        req,
+                        req.get_hop_by_hop_id(),
+                        permit,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            let rx = match rx {
+                Ok(rx) => match writer.send_with_buffer(req, &mut self.encode_buf) {
+                    Ok(()) => Ok(rx),
+                    Err(e) => {
+                        self.msg_caches
+                            .lock()
+                            .await
+                            .remove(&req.get_hop_by_hop_id());
+                        Err(e)
+                    }
+                },
+                Err(e) => Err(e),
+            };
+            receivers.push(rx);
+        }
+
+        let error_answers_as_err = self.config.error_answers_as_err;
+        futures::future::join_all(receivers.into_iter().map(|rx| async move {
+            ResponseFuture {
+                receiver: rx?,
+                error_answers_as_err,
+            }
+            .await
+        }))
+        .await
+    }
+
+    /// Initiates a Diameter request.
+    ///
+    /// This method creates and caches a request, readying it for sending to the server.
+    ///
+    /// Args:
+    ///     req: The Diameter message to send as a request.
+    ///
+    /// Returns:
+    ///     A `Result` containing a `DiameterRequest`, or an error if the client is not
+    ///     connected or a request with the same hop-by-hop id is already in flight.
+    pub async fn request(&mut self, req: DiameterMessage) -> Result<DiameterRequest> {
+        if let Some(writer) = &self.writer {
+            self.check_peer_send(&req)?;
+            let permit = self.acquire_permit().await?;
+            let rx = Self::register(
+                &self.msg_caches,
+                &self.drained,
+                &req,
+                req.get_hop_by_hop_id(),
+                permit,
+            )
+            .await?;
+            Ok(DiameterRequest::new(
+                req,
+                rx,
+                writer.clone(),
+                Arc::clone(&self.msg_caches),
+            ))
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Retransmits a request, e.g. when failing over to another connection.
+    ///
+    /// The request is re-sent on this client with the T flag set, the same
+    /// End-to-End Identifier and a new Hop-by-Hop Identifier taken from
+    /// `get_next_seq_num`. The pending response channel is moved to the new
+    /// Hop-by-Hop Identifier, so `response` resolves with the retransmitted answer.
+    ///
+    /// Args:
+    ///     request: The `DiameterRequest` to retransmit.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of resending the request.
+    pub async fn retransmit(&mut self, request: &mut DiameterRequest) -> Result<()> {
+        if self.writer.is_none() {
+            return Err(Error::NotConnected);
+        }
+        self.check_peer_send(&request.request)?;
+        let hop_by_hop = self.get_next_seq_num();
+        let original = request.request.get_hop_by_hop_id();
+
+        // On the same client the permit of the original request moves to the
+        // retransmission; on another client it is released. The permit, send
+        // slot and new hop-by-hop id are taken before the original entry is
+        // removed, so a rejected retransmission leaves the original pending.
+        let reuse_permit = Arc::ptr_eq(&request.msg_caches, &self.msg_caches)
+            && request
+                .msg_caches
+                .lock()
+                .await
+                .get(&original)
+                .is_some_and(|pending| pending.permit.is_some());
+        let permit = if reuse_permit {
+            self.acquire_rate_limit().await?;
+            None
+        } else {
+            self.acquire_permit().await?
+        };
+        let rx = Self::register(
+            &self.msg_caches,
+            &self.drained,
+            &request.request,
+            hop_by_hop,
+            permit,
+        )
+        .await?;
+        let pending = request.msg_caches.lock().await.remove(&original);
+        if let Some(pending) = pending.filter(|_| reuse_permit) {
+            if let Some(retransmission) = self.msg_caches.lock().await.get_mut(&hop_by_hop) {
+                retransmission.permit = pending.permit;
+            }
+        }
+        request.request.set_retransmit(true);
+        request.request.set_hop_by_hop_id(hop_by_hop);
+        request.receiver = Arc::new(Mutex::new(Some(rx)));
+        request.msg_caches = Arc::clone(&self.msg_caches);
+        if let Some(writer) = &self.writer {
+            request.writer = writer.clone();
+        }
+
+        request.send().await
+    }
+
+    // Fails if the peer state machine is running and does not allow sending
+    // `req`. Sending a DPR starts closing the peer.
+    fn check_peer_send(&self, req: &DiameterMessage) -> Result<()> {
+        if let Some(peer) = &self.peer {
+            let mut peer = peer.lock()?;
+            peer.check_send(req)?;
+            if req.get_application_id() == ApplicationId::Common
+                && req.get_command_code() == CommandCode::DisconnectPeer
+            {
+                peer.handle(PeerEvent::Stop)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes an in-flight permit if `max_in_flight` is set, waiting for one or
+    // failing with `TooManyRequests` when none is free, then waits for a send
+    // slot if `rate_limit` is set.
+    async fn acquire_permit(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let permit = self.acquire_in_flight().await?;
+        self.acquire_rate_limit().await?;
+        Ok(permit)
+    }
+
+    async fn acquire_rate_limit(&self) -> Result<()> {
+        match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter.acquire(self.config.wait_for_rate_limit).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn acquire_in_flight(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let in_flight = match &self.in_flight {
+            Some(in_flight) => Arc::clone(in_flight),
+            None => return Ok(None),
+        };
+        let permit = if self.config.wait_for_permit {
+            in_flight
+                .acquire_owned()
+                .await
+                .map_err(|_| Error::ConnectionClosed)?
+        } else {
+            in_flight
+                .try_acquire_owned()
+                .map_err(|_| Error::TooManyRequests)?
+        };
+        Ok(Some(permit))
+    }
+
+    // Registers a response channel for the given hop-by-hop id, rejecting ids
+    // that are already in flight rather than silently dropping the earlier sender.
+    // The permit is released, and `drained` notified, when the pending request
+    // is removed.
+    async fn register(
+        msg_caches: &MsgCaches,
+        drained: &Arc<Notify>,
+        req: &DiameterMessage,
+        hop_by_hop: u32,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<Receiver<Result<DiameterMessage>>> {
+        let mut msg_caches = msg_caches.lock().await;
+        if msg_caches.contains_key(&hop_by_hop) {
+            return Err(Error::DuplicateHopByHopId(hop_by_hop));
+        }
+        let (tx, rx) = oneshot::channel();
+        msg_caches.insert(
+            hop_by_hop,
+            PendingRequest {
+                sender: tx,
+                application_id: req.get_application_id(),
+                command_code: req.get_command_code(),
+                permit,
+                _drained: DrainSignal(Arc::clone(drained)),
+            },
+        );
+        Ok(rx)
+    }
+
+    /// Shuts down the client gracefully.
+    ///
+    /// Waits up to `DEFAULT_DRAIN_TIMEOUT` for in-flight requests to be answered,
+    /// then stops the reader loop run by `handle` and closes the writer.
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown_timeout(DEFAULT_DRAIN_TIMEOUT).await
+    }
+
+    /// Shuts down the client gracefully, waiting up to `timeout` for
+    /// in-flight requests to be answered.
+    pub async fn shutdown_timeout(self, timeout: Duration) -> Result<()> {
+        let drain = async {
+            loop {
+                // Registered before checking, so a removal in between is not missed
+                let drained = self.drained.notified();
+                tokio::pin!(drained);
+                drained.as_mut().enable();
+                if self.msg_caches.lock().await.is_empty() {
+                    return;
+                }
+                drained.await;
+            }
+        };
+        if tokio::time::timeout(timeout, drain).await.is_err() {
+            log::warn!("Shutdown deadline reached with requests still in flight");
+        }
+
+        self.shutdown.notify_one();
+
+        if let Some(writer) = &self.writer {
+            let (tx, rx) = oneshot::channel();
+            if writer.tx.send(WriterCommand::Shutdown(tx)).is_ok() {
+                rx.await.map_err(|_| Error::ConnectionClosed)??;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a receiver for requests initiated by the server, such as Re-Auth-Request.
+    ///
+    /// Inbound messages with the R flag set are forwarded to this receiver instead of
+    /// being matched against pending requests. The receiver can only be taken once;
+    /// subsequent calls return `None`. With `DiameterClientConfig::peer` set, DWRs
+    /// and DPRs are answered by the client instead.
+    ///
+    /// The queue holds up to `DiameterClientConfig::incoming_capacity` requests;
+    /// requests arriving while it is full are dropped and counted in
+    /// `incoming_dropped`.
+    pub fn incoming_requests(&mut self) -> Option<mpsc::Receiver<DiameterMessage>> {
+        self.incoming_rx.take()
+    }
+
+    /// Returns the number of server-initiated requests dropped because the
+    /// incoming queue was full.
+    pub fn incoming_dropped(&self) -> u64 {
+        self.incoming_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the address of the Diameter server the client connects to.
+    pub fn get_address(&self) -> &str {
+        &self.address
+    }
+
+    /// Subscribes to connection state changes.
+    ///
+    /// Subscribe before calling `connect` to also receive the `Connected` event.
+    /// `Disconnected` is sent when the reader loop run by `handle` stops,
+    /// after `WatchdogFailed` if the watchdog closed the connection, and
+    /// `Reconnecting` before each attempt of `reconnect`.
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the state of the peer, if `DiameterClientConfig::peer` is set.
+    pub fn peer_state(&self) -> Option<PeerState> {
+        self.peer
+            .as_ref()
+            .and_then(|peer| peer.lock().ok().map(|peer| peer.get_state()))
+    }
+
     // Returns the next sequence number.
     pub fn get_next_seq_num(&mut self) -> u32 {
-        self.seq_num += 1;
-        self.seq_num
+        next_seq_num(&self.seq_num)
     }
 }
 
@@ -207,14 +1422,425 @@ impl DiameterClient {
 pub struct ClientHandler {
     // reader: ReadHalf<TcpStream>,
     reader: Box<dyn AsyncRead + Send + Unpin>,
-    msg_caches: Arc<Mutex<HashMap<u32, Sender<DiameterMessage>>>>,
+    msg_caches: MsgCaches,
+    incoming_tx: mpsc::Sender<DiameterMessage>,
+    incoming_dropped: Arc<AtomicU64>,
+    unwaited: Unwaited,
+    shutdown: Arc<Notify>,
+    events: broadcast::Sender<ConnectionEvent>,
+    peer: Option<PeerHandle>,
+    peer_config: Option<PeerConfig>,
+    writer: MessageWriter,
+    closed: CancellationToken,
+}
+
+// Increments the shared sequence number, returning the new value.
+fn next_seq_num(seq_num: &AtomicU32) -> u32 {
+    seq_num.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+}
+
+// Sends a DWR every `interval` while the peer is open, as described in
+// RFC 6733, section 5.5. A DWR not answered within the interval closes the
+// connection; the DWA itself reaches the peer state machine through the
+// reader loop.
+struct Watchdog {
+    interval: Duration,
+    config: PeerConfig,
+    peer: PeerHandle,
+    writer: MessageWriter,
+    msg_caches: MsgCaches,
+    drained: Arc<Notify>,
+    seq_num: Arc<AtomicU32>,
+    events: broadcast::Sender<ConnectionEvent>,
+    closed: CancellationToken,
+}
+
+impl Watchdog {
+    async fn run(self) {
+        let mut buf = BytesMut::new();
+        loop {
+            tokio::select! {
+                _ = self.closed.cancelled() => return,
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+            if !self
+                .peer
+                .lock()
+                .is_ok_and(|peer| peer.get_state().is_open())
+            {
+                return;
+            }
+
+            let seq_num = next_seq_num(&self.seq_num);
+            let dwr = self.config.dwr(seq_num, seq_num);
+            let rx = match DiameterClient::register(
+                &self.msg_caches,
+                &self.drained,
+                &dwr,
+                seq_num,
+                None,
+            )
+            .await
+            {
+                Ok(rx) => rx,
+                Err(e) => {
+                    log::warn!("Skipping DWR; {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.writer.send_with_buffer(&dwr, &mut buf) {
+                log::error!("Failed to send DWR; error: {}", e);
+                return;
+            }
+
+            let answered = tokio::select! {
+                _ = self.closed.cancelled() => return,
+                result = tokio::time::timeout(self.interval, rx) => matches!(result, Ok(Ok(Ok(_)))),
+            };
+            if !answered {
+                self.msg_caches.lock().await.remove(&seq_num);
+                log::error!(
+                    "DWR not answered within {:?}, closing the connection",
+                    self.interval
+                );
+                let _ = self.events.send(ConnectionEvent::WatchdogFailed);
+                self.closed.cancel();
+                return;
+            }
+        }
+    }
+}
+
+// Returns the name the server certificate is validated against: the host
+// part of `addr`, which may be a DNS name or an IP address.
+#[cfg(feature = "tls")]
+fn tls_server_name(addr: &str) -> Result<tokio_rustls::rustls::pki_types::ServerName<'static>> {
+    let host = match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => match addr.rsplit_once(':') {
+            Some((host, _)) => host.to_string(),
+            None => addr.to_string(),
+        },
+    };
+    tokio_rustls::rustls::pki_types::ServerName::try_from(host)
+        .map_err(|e| Error::ClientError(format!("invalid TLS server name in {}: {}", addr, e)))
+}
+
+// The peer state machine, shared by the client, its reader and the task
+// awaiting the CEA.
+type PeerHandle = Arc<std::sync::Mutex<PeerStateMachine>>;
+
+// Applies `event` to the peer state machine, if there is one. Events that
+// are invalid in the current state are logged and ignored.
+fn apply_peer_event(peer: &Option<PeerHandle>, event: PeerEvent) {
+    if let Some(peer) = peer {
+        match peer.lock() {
+            Ok(mut peer) => {
+                if let Err(e) = peer.handle(event) {
+                    log::warn!("Ignoring peer event; {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to lock peer state; error: {}", e),
+        }
+    }
+}
+
+// Drives the peer state machine through a connection attempt. Until
+// disarmed, dropping the guard, e.g. when sending the CER fails or
+// `connect_timeout` cancels the attempt, applies `ConnFailed` before the
+// connection is acknowledged and `PeerDisconnected` after, so the peer is
+// `Closed` again and the next attempt can start.
+struct PeerConnectGuard {
+    peer: Option<PeerHandle>,
+    on_drop: Option<PeerEvent>,
+}
+
+impl PeerConnectGuard {
+    fn start(peer: &Option<PeerHandle>) -> Result<PeerConnectGuard> {
+        if let Some(peer) = peer {
+            peer.lock()?.handle(PeerEvent::Start)?;
+        }
+        Ok(PeerConnectGuard {
+            peer: peer.clone(),
+            on_drop: Some(PeerEvent::ConnFailed),
+        })
+    }
+
+    fn conn_ack(&mut self) {
+        apply_peer_event(&self.peer, PeerEvent::ConnAck);
+        self.on_drop = Some(PeerEvent::PeerDisconnected);
+    }
+
+    fn disarm(mut self) {
+        self.on_drop = None;
+    }
+}
+
+impl Drop for PeerConnectGuard {
+    fn drop(&mut self) {
+        if let Some(event) = self.on_drop.take() {
+            apply_peer_event(&self.peer, event);
+        }
+    }
+}
+
+// A request awaiting its answer, with the application id and command code
+// the answer must carry, and its in-flight permit if `max_in_flight` is set.
+pub(crate) struct PendingRequest {
+    sender: Sender<Result<DiameterMessage>>,
+    application_id: ApplicationId,
+    command_code: CommandCode,
+    permit: Option<OwnedSemaphorePermit>,
+    _drained: DrainSignal,
+}
+
+// Wakes the waiters of `DiameterClient::shutdown` when its pending request
+// is dropped, so they check again whether any request is left.
+struct DrainSignal(Arc<Notify>);
+
+impl Drop for DrainSignal {
+    fn drop(&mut self) {
+        self.0.notify_waiters();
+    }
+}
+
+// Pending requests keyed by hop-by-hop id.
+type MsgCaches = Arc<Mutex<HashMap<u32, PendingRequest>>>;
+
+// Hop-by-hop ids of the most recent requests sent with `send_no_wait`.
+type Unwaited = Arc<std::sync::Mutex<VecDeque<u32>>>;
+
+// Forgets `hop_by_hop` if it was sent with `send_no_wait`, returning whether it was.
+fn take_unwaited(unwaited: &Unwaited, hop_by_hop: u32) -> bool {
+    let Ok(mut unwaited) = unwaited.lock() else {
+        return false;
+    };
+    match unwaited.iter().position(|id| *id == hop_by_hop) {
+        Some(i) => {
+            unwaited.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+enum WriterCommand {
+    Write(Bytes),
+    // Written without flushing the transport, until a later `Write` or `Flush`
+    WriteNoFlush(Bytes),
+    Flush(Sender<Result<()>>),
+    Shutdown(Sender<Result<()>>),
+}
+
+// Handle to the writer task spawned by `DiameterClient::connect`.
+#[derive(Clone)]
+pub(crate) struct MessageWriter {
+    tx: UnboundedSender<WriterCommand>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl MessageWriter {
+    // Encodes the message and queues it for the writer task.
+    fn send(&self, msg: &DiameterMessage) -> Result<()> {
+        trace_message("Sending", msg);
+        let bytes = msg.to_bytes()?;
+        self.tx
+            .send(WriterCommand::Write(bytes.into()))
+            .map_err(|_| self.closed_error())
+    }
+
+    // Like `send`, but the writer task does not flush the transport after it.
+    fn send_no_flush(&self, msg: &DiameterMessage) -> Result<()> {
+        trace_message("Sending", msg);
+        let bytes = msg.to_bytes()?;
+        self.tx
+            .send(WriterCommand::WriteNoFlush(bytes.into()))
+            .map_err(|_| self.closed_error())
+    }
+
+    // Waits until everything queued before the call is written and flushed.
+    async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(WriterCommand::Flush(tx))
+            .map_err(|_| self.closed_error())?;
+        rx.await.map_err(|_| self.closed_error())?
+    }
+
+    // Encodes the message into `buf` and queues it for the writer task. The
+    // writer task drops the bytes once copied to its own buffer, so the next
+    // call reclaims the same allocation instead of allocating a new one.
+    fn send_with_buffer(&self, msg: &DiameterMessage, buf: &mut BytesMut) -> Result<()> {
+        trace_message("Sending", msg);
+        buf.clear();
+        buf.reserve(msg.get_length() as usize);
+        msg.encode_into(buf)?;
+        self.tx
+            .send(WriterCommand::Write(buf.split().freeze()))
+            .map_err(|_| self.closed_error())
+    }
+
+    // The error for a send after the writer task has stopped.
+    fn closed_error(&self) -> Error {
+        if self.timed_out.load(Ordering::SeqCst) {
+            Error::Timeout
+        } else {
+            Error::ConnectionClosed
+        }
+    }
+}
+
+// Dumps the whole message at trace level. The dump names AVPs from the
+// default dictionary, so it is only formatted when trace logging is enabled.
+fn trace_message(direction: &str, msg: &DiameterMessage) {
+    if log::log_enabled!(log::Level::Trace) {
+        log::trace!("{} message:\n{}", direction, msg);
+    }
+}
+
+/// Represents a single Diameter request and its associated response channel.
+///
+/// This structure is used to manage the lifecycle of a Diameter request,
+/// including sending the request and receiving the response.
+///
+/// Fields:
+///     request: The Diameter message representing the request.
+///     receiver: A channel for receiving the response to the request.
+///     writer: A handle to the writer task for sending the request to the server.
+///     msg_caches: The pending request map the response channel is registered in.
+pub struct DiameterRequest {
+    request: DiameterMessage,
+    receiver: Arc<Mutex<Option<Receiver<Result<DiameterMessage>>>>>,
+    writer: MessageWriter,
+    msg_caches: MsgCaches,
+}
+
+impl DiameterRequest {
+    /// Creates a new `DiameterRequest`.
+    ///
+    /// Args:
+    ///     request: The Diameter message to be sent as a request.
+    ///     receiver: The channel receiver for receiving the response.
+    ///     writer: A handle to the writer task for sending the request.
+    ///     msg_caches: The pending request map the receiver is registered in.
+    ///
+    /// Returns:
+    ///     A new instance of `DiameterRequest`.
+    pub(crate) fn new(
+        request: DiameterMessage,
+        receiver: Receiver<Result<DiameterMessage>>,
+        writer: MessageWriter,
+        msg_caches: MsgCaches,
+    ) -> Self {
+        DiameterRequest {
+            request,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+            writer,
+            msg_caches,
+        }
+    }
+
+    /// Returns a reference to the request message.
+    pub fn get_request(&self) -> &DiameterMessage {
+        &self.request
+    }
+
+    /// Sends the request to the Diameter server.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of sending the request.
+    pub async fn send(&mut self) -> Result<()> {
+        self.writer.send(&self.request)
+    }
+
+    /// Sends the request without flushing the transport, for batching.
+    ///
+    /// The request is flushed by the next `send` on the connection or by
+    /// `DiameterClient::flush`.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of queueing the request.
+    pub async fn send_no_flush(&mut self) -> Result<()> {
+        self.writer.send_no_flush(&self.request)
+    }
+
+    /// Sends the request to the Diameter server, encoding it into `buf`.
+    ///
+    /// Reuse the same `buf` across sends to avoid allocating an encode buffer
+    /// per request; its allocation is reclaimed once the previously sent bytes
+    /// have been written.
+    ///
+    /// Args:
+    ///     buf: The encode buffer kept by the caller across sends.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of sending the request.
+    pub async fn send_into(&mut self, buf: &mut BytesMut) -> Result<()> {
+        self.writer.send_with_buffer(&self.request, buf)
+    }
+
+    /// Waits for and returns the response to the request.
+    ///
+    /// Returns:
+    ///     A `Result` containing the response `DiameterMessage` or an error if the response cannot be received.
+    pub async fn response(&self) -> Result<DiameterMessage> {
+        let rx = self
+            .receiver
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| Error::ClientError("Response already taken".into()))?;
+
+        let res = rx.await.map_err(|_| Error::ConnectionClosed)??;
+
+        Ok(res)
+    }
+
+    /// Waits for the response to the request, returning early with
+    /// `Error::Cancelled` if `token` is cancelled first.
+    ///
+    /// On cancellation the request is removed from the pending requests,
+    /// so a late answer is no longer delivered.
+    ///
+    /// Args:
+    ///     token: The `CancellationToken` used to abandon the request.
+    ///
+    /// Returns:
+    ///     A `Result` containing the response `DiameterMessage`, or an error if the
+    ///     request was cancelled or the response cannot be received.
+    pub async fn response_with_cancel(&self, token: &CancellationToken) -> Result<DiameterMessage> {
+        tokio::select! {
+            res = self.response() => res,
+            _ = token.cancelled() => {
+                self.remove_pending().await;
+                Err(Error::Cancelled)
+            }
+        }
+    }
+
+    /// Cancels the request, removing it from the pending requests and
+    /// dropping the response channel.
+    pub async fn cancel(self) {
+        self.remove_pending().await;
+    }
+
+    async fn remove_pending(&self) {
+        self.msg_caches
+            .lock()
+            .await
+            .remove(&self.request.get_hop_by_hop_id());
+        self.receiver.lock().await.take();
+    }
 }
 
 /// A future for receiving a Diameter message response.
 ///
+/// Answers with the E flag set resolve to `Error::DiameterError` when the
+/// client was configured with `error_answers_as_err`.
 #[derive(Debug)]
 pub struct ResponseFuture {
-    pub receiver: Receiver<DiameterMessage>,
+    pub receiver: Receiver<Result<DiameterMessage>>,
+    pub(crate) error_answers_as_err: bool,
 }
 
 impl Future for ResponseFuture {
@@ -226,12 +1852,326 @@ impl Future for ResponseFuture {
     ) -> std::task::Poll<Self::Output> {
         match Pin::new(&mut self.receiver).poll(ctx) {
             std::task::Poll::Ready(result) => match result {
-                Ok(response) => std::task::Poll::Ready(Ok(response)),
-                Err(_) => std::task::Poll::Ready(Err(Error::ClientError(
-                    "Response channel closed".into(),
-                ))),
+                Ok(Ok(response)) if self.error_answers_as_err && response.is_error() => {
+                    let result_code = response
+                        .get_avp(RESULT_CODE)
+                        .and_then(|avp| avp.get_unsigned32())
+                        .or_else(|| response.experimental_result().map(|(_, code)| code))
+                        .unwrap_or_default();
+                    std::task::Poll::Ready(Err(Error::DiameterError { result_code }))
+                }
+                Ok(response) => std::task::Poll::Ready(response),
+                Err(_) => std::task::Poll::Ready(Err(Error::ConnectionClosed)),
             },
             std::task::Poll::Pending => std::task::Poll::Pending,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diameter::{ApplicationId, CommandCode};
+    use std::sync::atomic::AtomicUsize;
+    use std::task::{Context, Poll};
+
+    // Records every chunk passed to `poll_write`.
+    #[derive(Clone, Default)]
+    struct RecordingWriter {
+        writes: Arc<AtomicUsize>,
+        data: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncWrite for RecordingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.data.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn new_request(
+        msg_caches: &MsgCaches,
+        tx: &UnboundedSender<WriterCommand>,
+        hop_by_hop: u32,
+    ) -> DiameterRequest {
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            hop_by_hop,
+            hop_by_hop,
+        );
+        let rx = DiameterClient::register(msg_caches, &Default::default(), &ccr, hop_by_hop, None)
+            .await
+            .unwrap();
+        DiameterRequest::new(
+            ccr,
+            rx,
+            MessageWriter {
+                tx: tx.clone(),
+                timed_out: Default::default(),
+            },
+            Arc::clone(msg_caches),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cancel_request() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let msg_caches = Arc::new(Mutex::new(HashMap::new()));
+
+        let request = new_request(&msg_caches, &tx, 1).await;
+        assert!(msg_caches.lock().await.contains_key(&1));
+        request.cancel().await;
+        assert!(!msg_caches.lock().await.contains_key(&1));
+
+        let request = new_request(&msg_caches, &tx, 2).await;
+        let token = CancellationToken::new();
+        let child = token.clone();
+        tokio::spawn(async move { child.cancel() });
+        match request.response_with_cancel(&token).await {
+            Err(Error::Cancelled) => {}
+            _ => panic!("Expected a Cancelled error"),
+        }
+        assert!(msg_caches.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_wakes_on_drain() {
+        let client = DiameterClient::new("127.0.0.1:3868", Default::default());
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        let _rx = DiameterClient::register(&client.msg_caches, &client.drained, &ccr, 1, None)
+            .await
+            .unwrap();
+        let msg_caches = Arc::clone(&client.msg_caches);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            msg_caches.lock().await.remove(&1);
+        });
+
+        // Returns once the request is removed, well before the deadline
+        let start = std::time::Instant::now();
+        client
+            .shutdown_timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_incoming_queue_bounded() {
+        let (incoming_tx, mut incoming_rx) = mpsc::channel(2);
+        let incoming_dropped = AtomicU64::new(0);
+        let msg_caches = Arc::new(Mutex::new(HashMap::new()));
+
+        for seq_num in 1..=5 {
+            let rar = DiameterMessage::new(
+                CommandCode::ReAuth,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                seq_num,
+                seq_num,
+            );
+            DiameterClient::process_decoded_msg(
+                Arc::clone(&msg_caches),
+                &incoming_tx,
+                &incoming_dropped,
+                &Default::default(),
+                rar,
+            )
+            .await;
+        }
+
+        assert_eq!(incoming_dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(incoming_rx.recv().await.unwrap().get_hop_by_hop_id(), 1);
+        assert_eq!(incoming_rx.recv().await.unwrap().get_hop_by_hop_id(), 2);
+        assert!(incoming_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_no_wait() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = crate::transport::Codec::decode(&mut stream).await {
+                crate::transport::Codec::encode(&mut stream, &req.to_answer())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), Default::default());
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let acr = DiameterMessage::new(
+            CommandCode::Accounting,
+            ApplicationId::Accounting,
+            flags::REQUEST,
+            7,
+            7,
+        );
+        client.send_no_wait(acr).await.unwrap();
+        assert!(client.msg_caches.lock().await.is_empty());
+
+        // The answer is matched against the unwaited id and dropped
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !client.unwaited.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("The answer was not received");
+        assert!(client.msg_caches.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_loop_coalesces_burst() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = MessageWriter {
+            tx,
+            timed_out: Default::default(),
+        };
+
+        let mut expected = Vec::new();
+        for seq_num in 1..=100 {
+            let ccr = DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                seq_num,
+                seq_num,
+            );
+            expected.extend(ccr.to_bytes().unwrap());
+            writer.send(&ccr).unwrap();
+        }
+        let (done_tx, done_rx) = oneshot::channel();
+        writer.tx.send(WriterCommand::Shutdown(done_tx)).ok();
+
+        let recorder = RecordingWriter::default();
+        DiameterClient::write_loop(
+            recorder.clone(),
+            rx,
+            None,
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+        done_rx.await.unwrap().unwrap();
+
+        assert_eq!(recorder.writes.load(Ordering::SeqCst), 1);
+        assert_eq!(*recorder.data.lock().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_send_no_flush() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = MessageWriter {
+            tx,
+            timed_out: Default::default(),
+        };
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(DiameterClient::write_loop(
+            tokio::io::BufWriter::new(client),
+            rx,
+            None,
+            Default::default(),
+            Default::default(),
+        ));
+
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        writer.send_no_flush(&ccr).unwrap();
+
+        // Held in the buffered transport until flushed
+        tokio::task::yield_now().await;
+        let read = tokio::time::timeout(
+            Duration::from_millis(50),
+            crate::transport::Codec::decode(&mut server),
+        );
+        assert!(read.await.is_err());
+
+        writer.flush().await.unwrap();
+        let received = crate::transport::Codec::decode(&mut server).await.unwrap();
+        assert_eq!(received.get_hop_by_hop_id(), 1);
+
+        // A plain send flushes
+        let mut cca = ccr.to_answer();
+        cca.set_hop_by_hop_id(2);
+        writer.send(&cca).unwrap();
+        let received = crate::transport::Codec::decode(&mut server).await.unwrap();
+        assert_eq!(received.get_hop_by_hop_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_into_reuses_buffer() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let msg_caches = Arc::new(Mutex::new(HashMap::new()));
+        let mut buf = BytesMut::new();
+
+        let mut sent = Vec::new();
+        for hop_by_hop in 1..=3 {
+            let mut request = new_request(&msg_caches, &tx, hop_by_hop).await;
+            request.send_into(&mut buf).await.unwrap();
+
+            // Stand in for the writer task, which drops the bytes once written
+            match rx.recv().await {
+                Some(WriterCommand::Write(bytes)) => {
+                    assert_eq!(bytes, request.get_request().to_bytes().unwrap());
+                    sent.push(bytes.as_ptr());
+                }
+                _ => panic!("Expected a Write command"),
+            }
+        }
+        assert!(sent.iter().all(|ptr| *ptr == sent[0]));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_config_applied() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let config = TcpConfig {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+        };
+        config.apply(&stream).unwrap();
+
+        assert!(stream.nodelay().unwrap());
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(30)
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Diameter Transport Connectors
+//!
+//! A [`Connector`] opens the byte stream a `DiameterClient` runs over, so the
+//! same client works over TCP or, with the `sctp` feature, SCTP. See
+//! `DiameterClient::connect_with`.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// The future returned by `Connector::connect`.
+pub type ConnectFuture<'a, S> = Pin<Box<dyn Future<Output = io::Result<S>> + Send + 'a>>;
+
+/// Opens connections to Diameter peers over a particular transport.
+pub trait Connector {
+    /// The connected stream.
+    type Stream: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// Connects to `addr`, e.g. "aaa.example.com:3868".
+    fn connect<'a>(&'a self, addr: &'a str) -> ConnectFuture<'a, Self::Stream>;
+}
+
+// A connected stream of any transport.
+pub(crate) trait TransportStream: AsyncRead + AsyncWrite + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Send> TransportStream for S {}
+
+pub(crate) type BoxedStream = Pin<Box<dyn TransportStream>>;
+
+// `Connector` with the stream boxed, so a client can keep a connector of any
+// transport to reconnect over.
+pub(crate) trait DynConnector: Send + Sync {
+    fn connect_boxed<'a>(&'a self, addr: &'a str) -> ConnectFuture<'a, BoxedStream>;
+}
+
+impl<C> DynConnector for C
+where
+    C: Connector + Send + Sync,
+{
+    fn connect_boxed<'a>(&'a self, addr: &'a str) -> ConnectFuture<'a, BoxedStream> {
+        Box::pin(async move {
+            let stream = self.connect(addr).await?;
+            Ok(Box::pin(stream) as BoxedStream)
+        })
+    }
+}
+
+/// Connects over TCP, without the `TcpConfig` or TLS applied by
+/// `DiameterClient::connect`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnector;
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    fn connect<'a>(&'a self, addr: &'a str) -> ConnectFuture<'a, TcpStream> {
+        Box::pin(TcpStream::connect(addr))
+    }
+}
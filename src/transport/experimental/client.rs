@@ -2,6 +2,7 @@
 use crate::diameter::DiameterMessage;
 use crate::error::{Error, Result};
 use crate::transport::Codec;
+use bytes::BytesMut;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::DerefMut;
@@ -24,7 +25,6 @@ use tokio::sync::Mutex;
 ///     writer: An optional thread-safe writer for sending messages to the server.
 ///     msg_caches: A shared, mutable hash map that maps message IDs to channels for sending responses back to the caller.
 ///     seq_num: The next sequence number to use for a message.
-
 pub struct DiameterClient {
     address: String,
     writer: Option<Arc<Mutex<OwnedWriteHalf>>>,
@@ -70,14 +70,10 @@ impl DiameterClient {
 
         let msg_caches = Rc::clone(&self.msg_caches);
         tokio::task::spawn_local(async move {
+            let mut buf = BytesMut::new();
             loop {
-                match Codec::decode(&mut reader).await {
-                    Ok(res) => {
-                        if let Err(e) = Self::process_decoded_msg(msg_caches.clone(), res).await {
-                            log::error!("Failed to process response; error: {:?}", e);
-                            return;
-                        }
-                    }
+                match Codec::decode_with_buffer(&mut reader, &mut buf).await {
+                    Ok(res) => Self::process_decoded_msg(msg_caches.clone(), res).await,
                     Err(e) => {
                         log::error!("Failed to read message from socket; error: {:?}", e);
                         return;
@@ -89,28 +85,31 @@ impl DiameterClient {
         Ok(())
     }
 
+    // Answers that cannot be delivered are logged and dropped.
     async fn process_decoded_msg(
         msg_caches: Rc<RefCell<HashMap<u32, Sender<DiameterMessage>>>>,
         res: DiameterMessage,
-    ) -> Result<()> {
+    ) {
         let hop_by_hop = res.get_hop_by_hop_id();
         let mut msg_caches = msg_caches.borrow_mut();
         let sender_opt = msg_caches.remove(&hop_by_hop);
 
         match sender_opt {
             Some(sender) => {
-                sender.send(res).map_err(|e| {
-                    Error::ClientError(format!("Failed to send response; error: {:?}", e))
-                })?;
+                if sender.send(res).is_err() {
+                    log::warn!(
+                        "Dropping answer, request no longer awaited; hop_by_hop_id {}",
+                        hop_by_hop
+                    );
+                }
             }
             None => {
-                Err(Error::ClientError(format!(
-                    "No request found for hop_by_hop_id {}",
+                log::warn!(
+                    "Dropping answer, no request found; hop_by_hop_id {}",
                     hop_by_hop
-                )))?;
+                );
             }
         };
-        Ok(())
     }
 
     /// Initiates a Diameter request.
@@ -127,11 +126,14 @@ impl DiameterClient {
             let (tx, rx) = oneshot::channel();
             let hop_by_hop = req.get_hop_by_hop_id();
             let mut msg_caches = self.msg_caches.borrow_mut();
+            if msg_caches.contains_key(&hop_by_hop) {
+                return Err(Error::DuplicateHopByHopId(hop_by_hop));
+            }
             msg_caches.insert(hop_by_hop, tx);
 
-            Ok(DiameterRequest::new(req, rx, Arc::clone(&writer)))
+            Ok(DiameterRequest::new(req, rx, Arc::clone(writer)))
         } else {
-            Err(Error::ClientError("Not connected".into()))
+            Err(Error::NotConnected)
         }
     }
 
@@ -146,7 +148,7 @@ impl DiameterClient {
     ///     A `Result` containing the response `DiameterMessage` or an error.
     pub async fn send_message(&mut self, req: DiameterMessage) -> Result<DiameterMessage> {
         let mut request = self.request(req).await?;
-        let _ = request.send().await?;
+        request.send().await?;
         let response = request.response().await?;
         Ok(response)
     }
@@ -230,9 +232,7 @@ impl DiameterRequest {
             .take()
             .ok_or_else(|| Error::ClientError("Response already taken".into()))?;
 
-        let res = rx.await.map_err(|e| {
-            Error::ClientError(format!("Failed to receive response; error: {:?}", e))
-        })?;
+        let res = rx.await.map_err(|_| Error::ConnectionClosed)?;
 
         Ok(res)
     }
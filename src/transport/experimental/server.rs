@@ -2,6 +2,7 @@
 use crate::diameter::DiameterMessage;
 use crate::error::Result;
 use crate::transport::Codec;
+use bytes::BytesMut;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
 
@@ -78,9 +79,10 @@ impl DiameterServer {
         F: Fn(DiameterMessage) -> Result<DiameterMessage> + Clone + Send + 'static,
     {
         let (mut reader, mut writer) = stream.split();
+        let mut buf = BytesMut::new();
         loop {
             // Read and decode the request
-            let req = match Codec::decode(&mut reader).await {
+            let req = match Codec::decode_with_buffer(&mut reader, &mut buf).await {
                 Ok(req) => req,
                 Err(e) => match e {
                     crate::error::Error::IoError(ref e)
@@ -1,19 +1,35 @@
 //! Diameter Protocol Transport
 
 pub mod client;
+pub mod connector;
 pub mod experimental;
+pub mod pool;
+pub mod router;
+#[cfg(all(feature = "sctp", any(target_os = "linux", target_os = "freebsd")))]
+pub mod sctp;
 pub mod server;
 
+pub use crate::transport::client::ConnectionEvent;
 pub use crate::transport::client::DiameterClient;
 pub use crate::transport::client::DiameterClientConfig;
+pub use crate::transport::client::TcpConfig;
+pub use crate::transport::connector::Connector;
+pub use crate::transport::connector::TcpConnector;
+pub use crate::transport::pool::DiameterClientPool;
+pub use crate::transport::router::Router;
 pub use crate::transport::server::DiameterServer;
 pub use crate::transport::server::DiameterServerConfig;
+pub use crate::transport::server::LocalIdentity;
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
 
+use crate::codec::MAX_MESSAGE_LENGTH;
 use crate::diameter::DiameterMessage;
-use crate::error::{Error, Result};
-use std::io::Cursor;
-use tokio::io::AsyncReadExt;
+use crate::error::Result;
+use crate::stream::{decode_complete_frame, frame_length};
+use bytes::BytesMut;
 use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 /// Codec provides encoding and decoding functionality for Diameter messages
 /// over the TCP transport layer.
@@ -25,29 +41,44 @@ impl Codec {
     /// Reads from `reader`, decodes according to Diameter protocol standards, and returns a DiameterMessage.
     ///
     /// # Arguments
-    /// * `reader` - A mutable reference to an object implementing `AsyncReadExt` and `Unpin`.
+    /// * `reader` - A mutable reference to an object implementing `AsyncRead` and `Unpin`,
+    ///   e.g. a TCP or TLS stream half, or a `tokio::io::DuplexStream` in tests.
     pub async fn decode<R>(reader: &mut R) -> Result<DiameterMessage>
     where
-        R: AsyncReadExt + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        Self::decode_with_buffer(reader, &mut BytesMut::new()).await
+    }
+
+    /// Asynchronously decodes a DiameterMessage from a reader, reading into `buf`.
+    ///
+    /// Reuse the same `buf` for every message on a connection: once the previously
+    /// decoded message has been dropped its allocation is reclaimed instead of
+    /// allocating a new buffer per message. OctetString payloads are sliced from
+    /// the buffer, see `DiameterMessage::decode_from_shared`.
+    ///
+    /// # Arguments
+    /// * `reader` - A mutable reference to an object implementing `AsyncRead` and `Unpin`.
+    /// * `buf` - The read buffer kept by the caller across messages.
+    pub async fn decode_with_buffer<R>(
+        reader: &mut R,
+        buf: &mut BytesMut,
+    ) -> Result<DiameterMessage>
+    where
+        R: AsyncRead + Unpin,
     {
         let mut b = [0; 4];
         reader.read_exact(&mut b).await?;
-        let length = u32::from_be_bytes([0, b[1], b[2], b[3]]);
-
-        // Limit to 1MB
-        if length as usize > 1024 * 1024 {
-            return Err(Error::ClientError("Message too large to read".into()));
-        }
+        let length = frame_length(&b, MAX_MESSAGE_LENGTH)?;
 
         // Read the rest of the message
-        let mut buffer = Vec::with_capacity(length as usize);
-        buffer.extend_from_slice(&b);
-        buffer.resize(length as usize, 0);
-        reader.read_exact(&mut buffer[4..]).await?;
+        buf.clear();
+        buf.reserve(length);
+        buf.extend_from_slice(&b);
+        buf.resize(length, 0);
+        reader.read_exact(&mut buf[4..]).await?;
 
-        // Decode Response
-        let mut cursor = Cursor::new(buffer);
-        DiameterMessage::decode_from(&mut cursor)
+        decode_complete_frame(buf.split().freeze())
     }
 
     /// Asynchronously encodes a DiameterMessage and writes it to a writer.
@@ -78,24 +109,101 @@ mod tests {
     use crate::avp::enumerated::Enumerated;
     use crate::avp::flags::M;
     use crate::avp::identity::Identity;
+    use crate::avp::octetstring::OctetString;
     use crate::avp::unsigned32::Unsigned32;
     use crate::avp::utf8string::UTF8String;
     use crate::avp::Avp;
-    use crate::avp::Unsigned64;
     use crate::diameter::flags;
+    use crate::diameter::tests::ccr;
     use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+    use crate::error::{Error, Result};
+    use crate::transport::Codec;
+    use crate::transport::ConnectionEvent;
     use crate::transport::DiameterClient;
     use crate::transport::DiameterClientConfig;
+    use crate::transport::DiameterClientPool;
     use crate::transport::DiameterServer;
     use crate::transport::DiameterServerConfig;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot};
+
+    // Spawns a `DiameterServer` on a port picked by the OS, returning its address.
+    async fn serve<F, Fut>(config: DiameterServerConfig, handler: F) -> String
+    where
+        F: Fn(DiameterMessage) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<DiameterMessage>> + Send + 'static,
+    {
+        let mut server = DiameterServer::new("127.0.0.1:0", config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.listen(handler).await.unwrap() });
+        addr.to_string()
+    }
+
+    // Spawns a server answering every request on its first connection with
+    // `to_answer`, returning its address.
+    async fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            }
+        });
+        addr.to_string()
+    }
+
+    // Connects a client to `addr` and spawns its reader loop.
+    async fn connected_client(addr: &str, config: DiameterClientConfig) -> DiameterClient {
+        let mut client = DiameterClient::new(addr, config);
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn test_codec_duplex() {
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1123158611,
+            3102381851,
+        );
+        ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+        ccr.add_avp(avp!(264, None, M, Identity::new("host.example.com")));
+        ccr.add_avp(avp!(416, None, M, Enumerated::new(1)));
+
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let expected = ccr.to_bytes().unwrap();
+        tokio::spawn(async move { Codec::encode(&mut writer, &ccr).await.unwrap() });
+
+        let decoded = Codec::decode(&mut reader).await.unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), expected);
+        assert_eq!(decoded.get_hop_by_hop_id(), 1123158611);
+        assert_eq!(
+            decoded
+                .get_avp(263)
+                .unwrap()
+                .get_utf8string()
+                .unwrap()
+                .value(),
+            "ses;12345888"
+        );
+    }
 
     #[tokio::test]
     async fn test_diameter_transport() {
         // Diameter Server
-        let mut server =
-            DiameterServer::new("0.0.0.0:3868", DiameterServerConfig { native_tls: None })
-                .await
-                .unwrap();
+        let mut server = DiameterServer::new("0.0.0.0:3868", DiameterServerConfig::default())
+            .await
+            .unwrap();
 
         tokio::spawn(async move {
             server
@@ -125,12 +233,9 @@ mod tests {
         let client_config = DiameterClientConfig {
             use_tls: false,
             verify_cert: false,
+            ..Default::default()
         };
-        let mut client = DiameterClient::new("localhost:3868", client_config);
-        let mut handler = client.connect().await.unwrap();
-        tokio::spawn(async move {
-            DiameterClient::handle(&mut handler).await;
-        });
+        let mut client = connected_client("localhost:3868", client_config).await;
 
         // Send Single CCR
         let mut ccr = DiameterMessage::new(
@@ -172,7 +277,7 @@ mod tests {
             ccr.add_avp(avp!(296, None, M, Identity::new("realm.example.com")));
             ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
             ccr.add_avp(avp!(416, None, M, Enumerated::new(1)));
-            ccr.add_avp(avp!(415, None, M, Unsigned64::new(1000)));
+            ccr.add_avp(avp!(415, None, M, Unsigned32::new(1000)));
             let response = client.send_message(ccr).await.unwrap();
             let handle = tokio::spawn(async move {
                 let cca = response.await.unwrap();
@@ -190,4 +295,1575 @@ mod tests {
             handle.await.unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn test_incoming_server_initiated_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Server pushes a Re-Auth-Request as soon as the client connects
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut rar = DiameterMessage::new(
+                CommandCode::ReAuth,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                42,
+                43,
+            );
+            rar.add_avp(avp!(264, None, M, Identity::new("server.example.com")));
+            rar.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+            Codec::encode(&mut stream, &rar).await.unwrap();
+            // Keep the connection open until the client is done
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut incoming = client.incoming_requests().unwrap();
+        assert!(client.incoming_requests().is_none());
+
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let rar = incoming.recv().await.unwrap();
+        assert_eq!(rar.get_command_code(), CommandCode::ReAuth);
+        assert_eq!(rar.get_hop_by_hop_id(), 42);
+        assert_eq!(rar.get_end_to_end_id(), 43);
+    }
+
+    #[tokio::test]
+    async fn test_reauth_answer() {
+        use crate::applications::reauth::{self, ReAuthRequestType};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut rar = DiameterMessage::new(
+                CommandCode::ReAuth,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                42,
+                43,
+            );
+            rar.add_avp(avp!(263, None, M, UTF8String::new("ses;12345888")));
+            rar.add_avp(avp!(264, None, M, Identity::new("server.example.com")));
+            rar.add_avp(avp!(296, None, M, Identity::new("operator.net")));
+            rar.add_avp(avp!(
+                reauth::RE_AUTH_REQUEST_TYPE,
+                None,
+                M,
+                Enumerated::new(ReAuthRequestType::AuthorizeOnly as i32)
+            ));
+            Codec::encode(&mut stream, &rar).await.unwrap();
+            Codec::decode(&mut stream).await.unwrap()
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), Default::default());
+        let mut incoming = client.incoming_requests().unwrap();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let rar = incoming.recv().await.unwrap();
+        assert_eq!(
+            reauth::request_type(&rar),
+            Some(ReAuthRequestType::AuthorizeOnly)
+        );
+        match client.send_answer(rar.clone()).await {
+            Err(Error::ClientError(msg)) => {
+                assert_eq!(msg, "send_answer called with a request; hop_by_hop_id 42")
+            }
+            other => panic!("Expected a ClientError, got {:?}", other),
+        }
+        let raa = reauth::raa(&rar, 2001, "client.example.com", "example.com");
+        client.send_answer(raa).await.unwrap();
+
+        let raa = server.await.unwrap();
+        assert_eq!(raa.get_command_code(), CommandCode::ReAuth);
+        assert_eq!(raa.get_flags() & flags::REQUEST, 0);
+        assert_eq!(raa.get_hop_by_hop_id(), 42);
+        assert_eq!(raa.get_end_to_end_id(), 43);
+        assert_eq!(raa.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+        assert_eq!(
+            raa.get_avp(263).unwrap().get_utf8string().unwrap().value(),
+            "ses;12345888"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_from() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let _handler = client
+            .connect_from("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.ip().to_string(), "127.0.0.1");
+        assert_ne!(peer.port(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // Once the accept backlog is full the listener stops answering SYNs,
+        // like an unreachable host
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _queued = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+        let mut client = DiameterClient::new(&addr.to_string(), Default::default());
+        let start = std::time::Instant::now();
+        match client.connect_timeout(Duration::from_millis(200)).await {
+            Err(Error::Timeout) => {}
+            Err(e) => panic!("Expected a Timeout error, got {:?}", e),
+            Ok(_) => panic!("Expected a Timeout error, got a connection"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_failover() {
+        // Reserve a port, then close it so connecting to it is refused.
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap().to_string();
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&refused, client_config);
+        let _handler = client.connect_any(&[&refused, &live]).await.unwrap();
+        assert_eq!(client.get_address(), live);
+        listener.accept().await.unwrap();
+
+        assert!(client.connect_any(&[&refused]).await.is_err());
+        assert!(client.connect_any(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_failover() {
+        let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary.local_addr().unwrap().to_string();
+        let secondary_addr = echo_server().await;
+
+        let mut client = DiameterClient::new(&primary_addr, DiameterClientConfig::default());
+        let mut handler = client
+            .connect_any(&[&primary_addr, &secondary_addr])
+            .await
+            .unwrap();
+        assert_eq!(client.get_address(), primary_addr);
+
+        // The primary goes down
+        let (stream, _) = primary.accept().await.unwrap();
+        drop(stream);
+        drop(primary);
+        DiameterClient::handle(&mut handler).await;
+
+        let mut handler = client
+            .reconnect(2, Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert_eq!(client.get_address(), secondary_addr);
+
+        let cca = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_event() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Close the socket as soon as the client connects
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), DiameterClientConfig::default());
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        match events.recv().await.unwrap() {
+            ConnectionEvent::Disconnected(e) => assert!(matches!(*e, Error::ConnectionClosed)),
+            e => panic!("Expected a Disconnected event, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Close the first connection, answer on the second
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            }
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), DiameterClientConfig::default());
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(_)
+        ));
+
+        let mut handler = client
+            .reconnect(3, Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Reconnecting
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+
+        let cca = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_fails() {
+        // Find a port nothing listens on
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut client = DiameterClient::new(&addr.to_string(), DiameterClientConfig::default());
+        let mut events = client.events();
+        let result = client.reconnect(2, Duration::from_millis(10)).await;
+        assert!(matches!(result, Err(Error::IoError(_))));
+        for _ in 0..2 {
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                ConnectionEvent::Reconnecting
+            ));
+        }
+    }
+
+    // Sends two requests to a server that answers the first, optionally writes
+    // the start of the second answer, then half-closes the connection.
+    // Returns both results and the reason of the `Disconnected` event.
+    async fn half_close(
+        truncated: bool,
+    ) -> (Result<DiameterMessage>, Result<DiameterMessage>, Arc<Error>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let first = Codec::decode(&mut stream).await.unwrap();
+            let second = Codec::decode(&mut stream).await.unwrap();
+            Codec::encode(&mut stream, &first.to_answer())
+                .await
+                .unwrap();
+            if truncated {
+                let answer = second.to_answer().to_bytes().unwrap();
+                stream.write_all(&answer[..10]).await.unwrap();
+            }
+            stream.shutdown().await.unwrap();
+            // Keep reading until the client closes its side
+            while Codec::decode(&mut stream).await.is_ok() {}
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), DiameterClientConfig::default());
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+
+        let first = client.send_message(ccr(1)).await.unwrap();
+        let second = client.send_message(ccr(2)).await.unwrap();
+        let (first, second) = tokio::time::timeout(Duration::from_secs(5), async {
+            (first.await, second.await)
+        })
+        .await
+        .expect("pending requests hung after the peer closed");
+
+        let reason = match events.recv().await.unwrap() {
+            ConnectionEvent::Disconnected(reason) => reason,
+            e => panic!("Expected a Disconnected event, got {:?}", e),
+        };
+        (first, second, reason)
+    }
+
+    #[tokio::test]
+    async fn test_half_close_fails_pending() {
+        let (first, second, reason) = half_close(false).await;
+        assert_eq!(first.unwrap().get_hop_by_hop_id(), 1);
+        assert!(matches!(second, Err(Error::ConnectionClosed)));
+        assert!(matches!(*reason, Error::ConnectionClosed));
+    }
+
+    #[tokio::test]
+    async fn test_half_close_mid_frame() {
+        let (first, second, reason) = half_close(true).await;
+        assert_eq!(first.unwrap().get_hop_by_hop_id(), 1);
+        assert!(matches!(second, Err(Error::ConnectionClosed)));
+        match &*reason {
+            Error::DecodeError(msg) => assert_eq!(
+                msg,
+                "connection closed mid-frame, 10 bytes of a partial message"
+            ),
+            e => panic!("Expected a DecodeError, got {:?}", e),
+        }
+    }
+
+    // Sends a request for `realm` through the pool, returning the Origin-Host
+    // of the answer.
+    async fn answered_by(
+        pool: &mut DiameterClientPool,
+        realm: Option<&str>,
+        seq_num: u32,
+    ) -> String {
+        let mut builder =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST)
+                .hop_by_hop_id(seq_num)
+                .end_to_end_id(seq_num);
+        if let Some(realm) = realm {
+            builder = builder.destination_realm(realm);
+        }
+        let answer = pool
+            .send_message(builder.build())
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let host = answer.get_avp(264).unwrap().get_identity().unwrap();
+        host.value().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_pool_routes_by_realm() {
+        // Each peer answers with its own Origin-Host
+        let mut peers = vec![];
+        for host in ["ocs.operator.net", "ocs.partner.net"] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            peers.push(listener.local_addr().unwrap().to_string());
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                while let Ok(req) = Codec::decode(&mut stream).await {
+                    let mut answer = req.to_answer();
+                    answer.add_avp(avp!(264, None, M, Identity::new(host)));
+                    Codec::encode(&mut stream, &answer).await.unwrap();
+                }
+            });
+        }
+
+        let mut clients = vec![];
+        for peer in &peers {
+            let client = connected_client(peer, DiameterClientConfig::default()).await;
+            clients.push(client);
+        }
+        let partner = clients.pop().unwrap();
+        let operator = clients.pop().unwrap();
+        let mut pool = DiameterClientPool::new()
+            .realm("operator.net", operator)
+            .default_peer(partner);
+
+        assert_eq!(
+            answered_by(&mut pool, Some("operator.net"), 1).await,
+            "ocs.operator.net"
+        );
+        assert_eq!(
+            answered_by(&mut pool, Some("OPERATOR.NET"), 2).await,
+            "ocs.operator.net"
+        );
+        assert_eq!(
+            answered_by(&mut pool, Some("other.net"), 3).await,
+            "ocs.partner.net"
+        );
+        assert_eq!(answered_by(&mut pool, None, 4).await, "ocs.partner.net");
+
+        let mut pool = DiameterClientPool::new();
+        let req =
+            DiameterMessage::builder(CommandCode::CreditControl, ApplicationId::CreditControl)
+                .flags(flags::REQUEST)
+                .destination_realm("operator.net")
+                .build();
+        match pool.send_message(req).await {
+            Err(Error::ClientError(msg)) => {
+                assert_eq!(msg, "no peer for Destination-Realm operator.net")
+            }
+            _ => panic!("Expected a ClientError"),
+        }
+    }
+
+    fn peer_config() -> crate::capabilities::PeerConfig {
+        crate::capabilities::PeerConfig {
+            origin_host: "client.example.com".into(),
+            origin_realm: "example.com".into(),
+            host_ip_addresses: vec!["127.0.0.1".parse().unwrap()],
+            vendor_id: 10415,
+            product_name: "diameter-rs".into(),
+            origin_state_id: None,
+            auth_application_ids: vec![4],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_state_machine() {
+        use crate::peer::PeerState;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (cer_tx, cer_rx) = oneshot::channel();
+        let (cea_tx, cea_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let cer = Codec::decode(&mut stream).await.unwrap();
+            cer_tx.send(cer.get_command_code()).unwrap();
+
+            // Hold the CEA back until the test has tried to send early
+            cea_rx.await.unwrap();
+            let mut cea = cer.to_answer();
+            cea.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            Codec::encode(&mut stream, &cea).await.unwrap();
+
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            }
+        });
+
+        let client_config = DiameterClientConfig {
+            peer: Some(peer_config()),
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut events = client.events();
+        assert_eq!(client.peer_state(), Some(PeerState::Closed));
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        assert_eq!(cer_rx.await.unwrap(), CommandCode::CapabilitiesExchange);
+        assert_eq!(client.peer_state(), Some(PeerState::WaitICea));
+        match client.send_message(ccr(10)).await {
+            Err(Error::PeerNotOpen {
+                state: PeerState::WaitICea,
+                command_code: CommandCode::CreditControl,
+            }) => {}
+            _ => panic!("Expected a PeerNotOpen error"),
+        }
+
+        cea_tx.send(()).unwrap();
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Open
+        ));
+        assert_eq!(client.peer_state(), Some(PeerState::IOpen));
+
+        let cca = client.send_message(ccr(10)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 10);
+
+        // Sending a DPR closes the peer once answered
+        let dpr = DiameterMessage::new(
+            CommandCode::DisconnectPeer,
+            ApplicationId::Common,
+            flags::REQUEST,
+            11,
+            11,
+        );
+        let dpa = client.send_message(dpr).await.unwrap();
+        assert_eq!(client.peer_state(), Some(PeerState::Closing));
+        dpa.await.unwrap();
+        assert_eq!(client.peer_state(), Some(PeerState::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_peer_connect_timeout_during_handshake() {
+        use crate::peer::PeerState;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let identity = native_tls::Identity::from_pkcs8(
+            cert.cert.pem().as_bytes(),
+            cert.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        let acceptor =
+            tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Stall the first TLS handshake, complete the second
+            let (_stalled, _) = listener.accept().await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                let mut answer = req.to_answer();
+                answer.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+                Codec::encode(&mut stream, &answer).await.unwrap();
+            }
+        });
+
+        let client_config = DiameterClientConfig {
+            use_tls: true,
+            verify_cert: false,
+            peer: Some(peer_config()),
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut events = client.events();
+        match client.connect_timeout(Duration::from_millis(200)).await {
+            Err(Error::Timeout) => {}
+            Err(e) => panic!("Expected a Timeout error, got {:?}", e),
+            Ok(_) => panic!("Expected a Timeout error, got a connection"),
+        }
+        assert_eq!(client.peer_state(), Some(PeerState::Closed));
+
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Open
+        ));
+        assert_eq!(client.peer_state(), Some(PeerState::IOpen));
+    }
+
+    #[tokio::test]
+    async fn test_peer_cea_failure() {
+        use crate::peer::PeerState;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let cer = Codec::decode(&mut stream).await.unwrap();
+            let mut cea = cer.to_answer();
+            cea.add_avp(avp!(268, None, M, Unsigned32::new(5010)));
+            Codec::encode(&mut stream, &cea).await.unwrap();
+            while Codec::decode(&mut stream).await.is_ok() {}
+        });
+
+        let client_config = DiameterClientConfig {
+            peer: Some(peer_config()),
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(_)
+        ));
+        assert_eq!(client.peer_state(), Some(PeerState::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_peer_watchdog() {
+        use crate::peer::PeerState;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (dwr_tx, mut dwr_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                if req.get_command_code() == CommandCode::DeviceWatchdog {
+                    dwr_tx.send(req.get_hop_by_hop_id()).unwrap();
+                }
+                let mut answer = req.to_answer();
+                answer.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+                Codec::encode(&mut stream, &answer).await.unwrap();
+            }
+        });
+
+        let client_config = DiameterClientConfig {
+            peer: Some(peer_config()),
+            watchdog_interval: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let client = connected_client(&addr.to_string(), client_config).await;
+
+        // The CER took hop-by-hop id 1
+        assert_eq!(dwr_rx.recv().await.unwrap(), 2);
+        assert_eq!(dwr_rx.recv().await.unwrap(), 3);
+        assert_eq!(client.peer_state(), Some(PeerState::IOpen));
+    }
+
+    #[tokio::test]
+    async fn test_peer_watchdog_failed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Answer the CER, then never the DWR
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let cer = Codec::decode(&mut stream).await.unwrap();
+            let mut cea = cer.to_answer();
+            cea.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            Codec::encode(&mut stream, &cea).await.unwrap();
+            while Codec::decode(&mut stream).await.is_ok() {}
+        });
+
+        let client_config = DiameterClientConfig {
+            peer: Some(peer_config()),
+            watchdog_interval: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Open
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::WatchdogFailed
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_peer_answers_dwr() {
+        use crate::peer::PeerState;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (open_tx, open_rx) = oneshot::channel::<()>();
+        let (dwa_tx, dwa_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let cer = Codec::decode(&mut stream).await.unwrap();
+            let mut cea = cer.to_answer();
+            cea.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            Codec::encode(&mut stream, &cea).await.unwrap();
+
+            // Send the DWR once the client has opened the peer
+            open_rx.await.unwrap();
+            let dwr = DiameterMessage::new(
+                CommandCode::DeviceWatchdog,
+                ApplicationId::Common,
+                flags::REQUEST,
+                100,
+                100,
+            );
+            Codec::encode(&mut stream, &dwr).await.unwrap();
+            dwa_tx
+                .send(Codec::decode(&mut stream).await.unwrap())
+                .unwrap();
+            while Codec::decode(&mut stream).await.is_ok() {}
+        });
+
+        let client_config = DiameterClientConfig {
+            peer: Some(peer_config()),
+            watchdog_interval: None,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let mut events = client.events();
+        let mut incoming = client.incoming_requests().unwrap();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Connected
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Open
+        ));
+        open_tx.send(()).unwrap();
+
+        let dwa = dwa_rx.await.unwrap();
+        assert_eq!(dwa.get_command_code(), CommandCode::DeviceWatchdog);
+        assert_eq!(dwa.get_flags() & flags::REQUEST, 0);
+        assert_eq!(dwa.get_hop_by_hop_id(), 100);
+        assert_eq!(dwa.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+        assert_eq!(
+            dwa.get_avp(264).unwrap().get_identity().unwrap().value(),
+            "client.example.com"
+        );
+        assert!(incoming.try_recv().is_err());
+        assert_eq!(client.peer_state(), Some(PeerState::IOpen));
+    }
+
+    #[tokio::test]
+    async fn test_not_connected() {
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new("127.0.0.1:3868", client_config);
+        match client.send_message(ccr(1)).await {
+            Err(Error::NotConnected) => {}
+            _ => panic!("Expected a NotConnected error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_hop_by_hop_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let _handler = client.connect().await.unwrap();
+
+        let _request = client.request(ccr(1000)).await.unwrap();
+        assert!(matches!(
+            client.request(ccr(1000)).await,
+            Err(Error::DuplicateHopByHopId(1000))
+        ));
+        assert!(matches!(
+            client.send_message(ccr(1000)).await,
+            Err(Error::DuplicateHopByHopId(1000))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send_many() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut requests = vec![];
+            for _ in 0..10 {
+                requests.push(Codec::decode(&mut stream).await.unwrap());
+            }
+            // Answer in reverse order to exercise hop-by-hop demultiplexing
+            for req in requests.iter().rev() {
+                let mut answer = req.to_answer();
+                answer.add_avp(avp!(415, None, M, Unsigned32::new(req.get_hop_by_hop_id())));
+                Codec::encode(&mut stream, &answer).await.unwrap();
+            }
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = connected_client(&addr.to_string(), client_config).await;
+
+        let reqs = (1..=10).map(ccr).collect();
+        let responses = client.send_many(reqs).await;
+
+        assert_eq!(responses.len(), 10);
+        for (seq_num, res) in (1..=10).zip(responses) {
+            let res = res.unwrap();
+            assert_eq!(res.get_hop_by_hop_id(), seq_num);
+            assert_eq!(res.get_avp(415).unwrap().get_unsigned32(), Some(seq_num));
+        }
+    }
+
+    async fn error_answer_client(error_answers_as_err: bool) -> Result<DiameterMessage> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let req = Codec::decode(&mut stream).await.unwrap();
+            let mut answer = req.to_answer();
+            answer.set_error(true);
+            answer.add_avp(avp!(268, None, M, Unsigned32::new(3002)));
+            Codec::encode(&mut stream, &answer).await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            error_answers_as_err,
+            ..Default::default()
+        };
+        let mut client = connected_client(&addr.to_string(), client_config).await;
+
+        client.send_message(ccr(1)).await.unwrap().await
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight_rejects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            max_in_flight: Some(2),
+            wait_for_permit: false,
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let _handler = client.connect().await.unwrap();
+
+        let first = client.request(ccr(1)).await.unwrap();
+        let _second = client.send_message(ccr(2)).await.unwrap();
+        match client.request(ccr(3)).await {
+            Err(Error::TooManyRequests) => {}
+            _ => panic!("Expected a TooManyRequests error"),
+        }
+
+        // Cancelling a request releases its permit
+        first.cancel().await;
+        let _third = client.request(ccr(3)).await.unwrap();
+        match client.send_message(ccr(4)).await {
+            Err(Error::TooManyRequests) => {}
+            _ => panic!("Expected a TooManyRequests error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight_waits() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                // The next request must not arrive before this one is answered
+                let early = tokio::time::timeout(Duration::from_millis(50), async {
+                    Codec::decode(&mut stream).await
+                })
+                .await
+                .is_ok();
+                seen_tx.send((req.get_hop_by_hop_id(), early)).unwrap();
+                Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            }
+        });
+
+        let client_config = DiameterClientConfig {
+            max_in_flight: Some(1),
+            ..Default::default()
+        };
+        let mut client = connected_client(&addr.to_string(), client_config).await;
+
+        let reqs = (1..=3).map(ccr).collect();
+        let responses = client.send_many(reqs).await;
+        for (seq_num, res) in (1..=3).zip(responses) {
+            assert_eq!(res.unwrap().get_hop_by_hop_id(), seq_num);
+        }
+
+        for seq_num in 1..=3 {
+            assert_eq!(seen_rx.recv().await, Some((seq_num, false)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                seen_tx.send(std::time::Instant::now()).unwrap();
+                Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            }
+        });
+
+        let client_config = DiameterClientConfig::default().rate_limit(20);
+        let mut client = connected_client(&addr.to_string(), client_config).await;
+
+        let reqs = (1..=6).map(ccr).collect();
+        let start = std::time::Instant::now();
+        for res in client.send_many(reqs).await {
+            res.unwrap();
+        }
+        // 20 per second is one every 50ms, the first one going out at once
+        assert!(start.elapsed() >= Duration::from_millis(250));
+
+        // Request n is not sent before its slot, (n - 1) * 50ms after the
+        // start; an earlier one reaching the server late only narrows a gap
+        for slot in 0..6 {
+            let seen = seen_rx.recv().await.unwrap();
+            assert!(seen.duration_since(start) >= Duration::from_millis(50 * slot));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while Codec::decode(&mut stream).await.is_ok() {}
+        });
+
+        let client_config = DiameterClientConfig {
+            wait_for_rate_limit: false,
+            ..DiameterClientConfig::default().rate_limit(10)
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let _handler = client.connect().await.unwrap();
+
+        let _first = client.send_message(ccr(1)).await.unwrap();
+        match client.send_message(ccr(2)).await {
+            Err(Error::RateLimited) => {}
+            _ => panic!("Expected a RateLimited error"),
+        }
+
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        let _second = client.send_message(ccr(2)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_zero() {
+        let client_config = DiameterClientConfig::default().rate_limit(0);
+        let mut client = DiameterClient::new("127.0.0.1:0", client_config);
+        match client.connect().await {
+            Err(Error::ValidationError(msg)) => {
+                assert_eq!(msg, "rate_limit must allow at least 1 request per second")
+            }
+            _ => panic!("Expected a ValidationError"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_answer_keeps_reader() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(req) = Codec::decode(&mut stream).await {
+                let answer = req.to_answer();
+                Codec::encode(&mut stream, &answer).await.unwrap();
+                Codec::encode(&mut stream, &answer).await.unwrap();
+            }
+        });
+
+        let mut client = DiameterClient::new(&addr.to_string(), Default::default());
+        let mut events = client.events();
+        let mut handler = client.connect().await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert!(matches!(
+            events.recv().await,
+            Ok(ConnectionEvent::Connected)
+        ));
+
+        for seq_num in 1..=3 {
+            let cca = client
+                .send_message(ccr(seq_num))
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+            assert_eq!(cca.get_hop_by_hop_id(), seq_num);
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_error_answer_relaxed() {
+        let cca = error_answer_client(false).await.unwrap();
+        assert!(cca.is_error());
+        assert_eq!(cca.get_avp(268).unwrap().get_unsigned32(), Some(3002));
+    }
+
+    #[tokio::test]
+    async fn test_error_answer_as_err() {
+        match error_answer_client(true).await {
+            Err(Error::DiameterError { result_code }) => assert_eq!(result_code, 3002),
+            _ => panic!("Expected a DiameterError"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_answer_application_id_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let req = Codec::decode(&mut stream).await.unwrap();
+            let answer = DiameterMessage::new(
+                req.get_command_code(),
+                ApplicationId::Gx,
+                0,
+                req.get_hop_by_hop_id(),
+                req.get_end_to_end_id(),
+            );
+            Codec::encode(&mut stream, &answer).await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let mut client = connected_client(&addr.to_string(), Default::default()).await;
+
+        match client.send_message(ccr(1)).await.unwrap().await {
+            Err(Error::AnswerMismatch {
+                hop_by_hop_id,
+                application_id,
+                command_code,
+                expected_application_id,
+                expected_command_code,
+            }) => {
+                assert_eq!(hop_by_hop_id, 1);
+                assert_eq!(application_id, ApplicationId::Gx);
+                assert_eq!(command_code, CommandCode::CreditControl);
+                assert_eq!(expected_application_id, ApplicationId::CreditControl);
+                assert_eq!(expected_command_code, CommandCode::CreditControl);
+            }
+            other => panic!("Expected an AnswerMismatch error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_message_dump() {
+        crate::codec::tests::captured_logs();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let req = Codec::decode(&mut stream).await.unwrap();
+            let mut answer = req.to_answer();
+            answer.add_avp(avp!(263, None, M, UTF8String::new("ses;trace-answer")));
+            Codec::encode(&mut stream, &answer).await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let mut client = connected_client(&addr.to_string(), Default::default()).await;
+
+        let mut ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        ccr.add_avp(avp!(263, None, M, UTF8String::new("ses;trace-request")));
+        client.send_message(ccr).await.unwrap().await.unwrap();
+
+        let logs = crate::codec::tests::captured_logs();
+        assert!(logs
+            .iter()
+            .any(|line| line.starts_with("Sending message:\n")
+                && line.contains("Session-Id")
+                && line.contains("ses;trace-request")));
+        assert!(logs.iter().any(
+            |line| line.starts_with("Received message:\n") && line.contains("ses;trace-answer")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client_config = DiameterClientConfig {
+            write_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let mut client = DiameterClient::new(&addr.to_string(), client_config);
+        let _handler = client.connect().await.unwrap();
+        // Accept but never read, so the socket buffers fill up
+        let (_stream, _) = listener.accept().await.unwrap();
+
+        let mut hop_by_hop = 0;
+        let mut large_ccr = || {
+            hop_by_hop += 1;
+            let mut ccr = DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                hop_by_hop,
+                hop_by_hop,
+            );
+            ccr.add_avp(avp!(
+                25,
+                None,
+                M,
+                OctetString::new(vec![0; 4 * 1024 * 1024])
+            ));
+            ccr
+        };
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let mut responses = Vec::new();
+        loop {
+            match client.send_message(large_ccr()).await {
+                Ok(response) => responses.push(response),
+                Err(Error::Timeout) => break,
+                Err(e) => panic!("Expected a Timeout error, got {:?}", e),
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "write never timed out"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // The requests sent before, including the one whose write stalled, fail too
+        assert!(!responses.is_empty());
+        for response in responses {
+            assert!(matches!(response.await, Err(Error::Timeout)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retransmit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Ignore the original request, answer the retransmission
+            let original = Codec::decode(&mut stream).await.unwrap();
+            let retransmitted = Codec::decode(&mut stream).await.unwrap();
+            let answer = retransmitted.to_answer();
+            Codec::encode(&mut stream, &answer).await.unwrap();
+            tx.send((original, retransmitted)).unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+
+        let client_config = DiameterClientConfig {
+            use_tls: false,
+            verify_cert: false,
+            ..Default::default()
+        };
+        let mut client = connected_client(&addr.to_string(), client_config).await;
+
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            500,
+            600,
+        );
+        let mut request = client.request(ccr).await.unwrap();
+        request.send().await.unwrap();
+        client.retransmit(&mut request).await.unwrap();
+
+        let (original, retransmitted) = rx.recv().await.unwrap();
+        assert_eq!(original.get_flags(), flags::REQUEST);
+        assert_eq!(
+            retransmitted.get_flags(),
+            flags::REQUEST | flags::RETRANSMIT
+        );
+        assert_eq!(retransmitted.get_end_to_end_id(), 600);
+        assert_ne!(retransmitted.get_hop_by_hop_id(), 500);
+
+        let answer = request.response().await.unwrap();
+        assert_eq!(
+            answer.get_hop_by_hop_id(),
+            retransmitted.get_hop_by_hop_id()
+        );
+        assert_eq!(answer.get_end_to_end_id(), 600);
+    }
+
+    // Serves one connection, holding the answer to its first request until
+    // the returned sender fires.
+    async fn held_answer_server() -> (String, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (answer_tx, answer_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let req = Codec::decode(&mut stream).await.unwrap();
+            answer_rx.await.unwrap();
+            Codec::encode(&mut stream, &req.to_answer()).await.unwrap();
+            let _ = Codec::decode(&mut stream).await;
+        });
+        (addr.to_string(), answer_tx)
+    }
+
+    #[tokio::test]
+    async fn test_retransmit_rejected() {
+        let (addr, answer_tx) = held_answer_server().await;
+        let client_config = DiameterClientConfig {
+            wait_for_rate_limit: false,
+            ..DiameterClientConfig::default().rate_limit(1)
+        };
+        let mut client = connected_client(&addr, client_config).await;
+
+        // Hold the answer until the retransmission has been rejected
+        let mut request = client.request(ccr(1)).await.unwrap();
+        request.send().await.unwrap();
+        match client.retransmit(&mut request).await {
+            Err(Error::RateLimited) => {}
+            _ => panic!("Expected a RateLimited error"),
+        }
+
+        answer_tx.send(()).unwrap();
+        let answer = request.response().await.unwrap();
+        assert_eq!(answer.get_hop_by_hop_id(), 1);
+        assert_eq!(answer.get_flags() & flags::RETRANSMIT, 0);
+
+        // The next Hop-by-Hop Identifier, 1, is taken by a pending request
+        let (addr, answer_tx) = held_answer_server().await;
+        let mut client = connected_client(&addr, Default::default()).await;
+        let _pending = client.request(ccr(1)).await.unwrap();
+        let mut request = client.request(ccr(2)).await.unwrap();
+        request.send().await.unwrap();
+        match client.retransmit(&mut request).await {
+            Err(Error::DuplicateHopByHopId(1)) => {}
+            _ => panic!("Expected a DuplicateHopByHopId error"),
+        }
+
+        answer_tx.send(()).unwrap();
+        let answer = request.response().await.unwrap();
+        assert_eq!(answer.get_hop_by_hop_id(), 2);
+        assert_eq!(answer.get_flags() & flags::RETRANSMIT, 0);
+    }
+
+    #[tokio::test]
+    async fn test_server_local_identity() {
+        let config =
+            DiameterServerConfig::default().local_identity("ocs.operator.net", "operator.net");
+        let addr = serve(config, |req| async move {
+            // Origin-Host copied from the request by mistake
+            let mut res = req.to_answer();
+            res.add_avp(req.get_avp(264).unwrap().clone());
+            Ok(res)
+        })
+        .await;
+        let mut client = connected_client(&addr, Default::default()).await;
+
+        let mut ccr = ccr(1);
+        ccr.add_avp(avp!(264, None, M, Identity::new("client.example.com")));
+        ccr.add_avp(avp!(296, None, M, Identity::new("example.com")));
+        let cca = client.send_message(ccr).await.unwrap().await.unwrap();
+
+        assert_eq!(cca.get_avps_by_code(264).len(), 1);
+        assert_eq!(
+            cca.get_avp(264).unwrap().get_identity().unwrap().value(),
+            "ocs.operator.net"
+        );
+        assert_eq!(
+            cca.get_avp(296).unwrap().get_identity().unwrap().value(),
+            "operator.net"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_dedup() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let config = DiameterServerConfig::default().dedup_window(Duration::from_secs(5));
+        let handler_calls = Arc::clone(&calls);
+        let addr = serve(config, move |req| {
+            let calls = Arc::clone(&handler_calls);
+            async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst) as u32 + 1;
+                let mut res = req.to_answer();
+                res.add_avp(avp!(415, None, M, Unsigned32::new(call)));
+                Ok(res)
+            }
+        })
+        .await;
+        let mut client = connected_client(&addr, Default::default()).await;
+
+        let ccr = |hop_by_hop, end_to_end| {
+            let mut ccr = DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                hop_by_hop,
+                end_to_end,
+            );
+            ccr.add_avp(avp!(264, None, M, Identity::new("client.example.com")));
+            ccr
+        };
+
+        let first = client
+            .send_message(ccr(1, 100))
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        // Retransmitted with a new hop-by-hop id
+        let mut retransmit = ccr(2, 100);
+        retransmit.set_retransmit(true);
+        let second = client
+            .send_message(retransmit)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let other = client
+            .send_message(ccr(3, 101))
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(first.get_avp(415).unwrap().get_unsigned32(), Some(1));
+        assert_eq!(second.get_hop_by_hop_id(), 2);
+        assert_eq!(second.get_avp(415).unwrap().get_unsigned32(), Some(1));
+        assert_eq!(other.get_avp(415).unwrap().get_unsigned32(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_server_handler_timeout() {
+        let config = DiameterServerConfig::default().handler_timeout(Duration::from_millis(100));
+        let addr = serve(config, |req| async move {
+            // The first request is stuck, the second is answered at once
+            if req.get_hop_by_hop_id() == 1 {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            let mut res = req.to_answer();
+            res.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            Ok(res)
+        })
+        .await;
+        let mut client = connected_client(&addr, Default::default()).await;
+
+        let start = std::time::Instant::now();
+        let slow = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(slow.get_hop_by_hop_id(), 1);
+        assert_eq!(slow.get_end_to_end_id(), 1);
+        assert_eq!(slow.get_avp(268).unwrap().get_unsigned32(), Some(5012));
+
+        let fast = client.send_message(ccr(2)).await.unwrap().await.unwrap();
+        assert_eq!(fast.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_rustls() {
+        use crate::transport::rustls;
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+        let server_tls = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert.der().clone()], key)
+            .unwrap();
+        let config = DiameterServerConfig {
+            tls: Some(Arc::new(server_tls)),
+            ..Default::default()
+        };
+        let addr = serve(config, |req| async move {
+            let mut res = req.to_answer();
+            res.add_avp(avp!(268, None, M, Unsigned32::new(2001)));
+            Ok(res)
+        })
+        .await;
+        // A client stalling its handshake does not hold up the next one
+        let _stalled = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        // The certificate is issued for localhost
+        let addr = addr.replace("127.0.0.1", "localhost");
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_tls = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut client = DiameterClient::new(&addr, Default::default());
+        let mut handler = client
+            .connect_tls(&addr, Arc::new(client_tls))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let cca = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+
+        // Reconnecting runs the rustls handshake again
+        let mut handler = client
+            .reconnect(1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        let cca = client.send_message(ccr(2)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_avp(268).unwrap().get_unsigned32(), Some(2001));
+
+        // A certificate not signed by a trusted root fails the handshake
+        let untrusted = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let mut client = DiameterClient::new(&addr, Default::default());
+        match client.connect_tls(&addr, Arc::new(untrusted)).await {
+            Err(Error::IoError(e)) => assert!(e.to_string().contains("UnknownIssuer")),
+            _ => panic!("Expected the handshake to fail"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_tcp_connector() {
+        let addr = echo_server().await;
+        let mut client = DiameterClient::new(&addr, Default::default());
+        let mut handler = client
+            .connect_with(&crate::transport::TcpConnector)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let cca = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+    }
+
+    // Connects over in-memory streams to an echo peer, counting the connections.
+    #[derive(Clone, Default)]
+    struct DuplexConnector {
+        connects: Arc<AtomicUsize>,
+    }
+
+    impl crate::transport::Connector for DuplexConnector {
+        type Stream = tokio::io::DuplexStream;
+
+        fn connect<'a>(
+            &'a self,
+            _addr: &'a str,
+        ) -> crate::transport::connector::ConnectFuture<'a, Self::Stream> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            let (stream, mut peer) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(async move {
+                while let Ok(req) = Codec::decode(&mut peer).await {
+                    Codec::encode(&mut peer, &req.to_answer()).await.unwrap();
+                }
+            });
+            Box::pin(async move { Ok(stream) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_with_connector() {
+        // Not resolvable, so a reconnect over TCP would fail.
+        let mut client = DiameterClient::new("peer.invalid:3868", Default::default());
+        let connector = DuplexConnector::default();
+        let mut handler = client.connect_with(&connector).await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let mut handler = client
+            .reconnect(1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+        assert_eq!(connector.connects.load(Ordering::SeqCst), 2);
+
+        let cca = client.send_message(ccr(1)).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_shutdown() {
+        let addr = serve(DiameterServerConfig::default(), |req| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok(req.to_answer())
+        })
+        .await;
+
+        let mut client = DiameterClient::new(&addr, DiameterClientConfig::default());
+        let mut handler = client.connect().await.unwrap();
+        let reader = tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let response = client.send_message(ccr(1)).await.unwrap();
+
+        // In-flight request is drained before the reader stops
+        client.shutdown().await.unwrap();
+        let cca = response.await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), reader)
+            .await
+            .expect("reader task did not terminate")
+            .unwrap();
+    }
 }
@@ -0,0 +1,89 @@
+//! Diameter Client Pool
+//!
+//! Routes requests over a set of client connections by the realm they are
+//! addressed to, as a basic realm-routing agent (RFC 6733, section 6.1.4).
+//! Requests for a realm without a connection of its own go to the default
+//! peer, if any.
+
+use crate::avp::codes::DESTINATION_REALM;
+use crate::diameter::DiameterMessage;
+use crate::error::{Error, Result};
+use crate::transport::client::ResponseFuture;
+use crate::transport::DiameterClient;
+use std::collections::HashMap;
+
+/// Client connections keyed by Destination-Realm, with a default peer.
+///
+/// The clients are connected, and their `ClientHandler`s run, before they are
+/// added to the pool.
+///
+/// Example:
+///    ```no_run
+///    use diameter::transport::{DiameterClient, DiameterClientPool};
+///
+///    let operator = DiameterClient::new("ocs.operator.net:3868", Default::default());
+///    let partner = DiameterClient::new("ocs.partner.net:3868", Default::default());
+///    let pool = DiameterClientPool::new()
+///        .realm("operator.net", operator)
+///        .default_peer(partner);
+///    ```
+#[derive(Default)]
+pub struct DiameterClientPool {
+    realms: HashMap<String, DiameterClient>,
+    default_peer: Option<DiameterClient>,
+}
+
+impl DiameterClientPool {
+    pub fn new() -> DiameterClientPool {
+        DiameterClientPool::default()
+    }
+
+    /// Routes requests whose Destination-Realm is `realm` to `client`.
+    ///
+    /// Realms are compared case-insensitively, as DiameterIdentity values are.
+    pub fn realm(mut self, realm: &str, client: DiameterClient) -> Self {
+        self.realms.insert(realm.to_ascii_lowercase(), client);
+        self
+    }
+
+    /// Routes requests for realms without a connection of their own, and
+    /// requests without a Destination-Realm, to `client`.
+    pub fn default_peer(mut self, client: DiameterClient) -> Self {
+        self.default_peer = Some(client);
+        self
+    }
+
+    /// Returns the client a request for `realm` is sent on, if any.
+    pub fn get_client(&mut self, realm: Option<&str>) -> Option<&mut DiameterClient> {
+        let client = match realm {
+            Some(realm) => self.realms.get_mut(&realm.to_ascii_lowercase()),
+            None => None,
+        };
+        match client {
+            Some(client) => Some(client),
+            None => self.default_peer.as_mut(),
+        }
+    }
+
+    /// Sends a request on the client for its Destination-Realm (283).
+    ///
+    /// Args:
+    ///     req: The Diameter request to send.
+    ///
+    /// Returns:
+    ///     A `ResponseFuture` for the answer, or `Error::ClientError` if no
+    ///     client serves the realm and there is no default peer.
+    pub async fn send_message(&mut self, req: DiameterMessage) -> Result<ResponseFuture> {
+        let realm = req
+            .get_avp(DESTINATION_REALM)
+            .and_then(|avp| avp.get_identity())
+            .map(|realm| realm.value().to_string());
+        match self.get_client(realm.as_deref()) {
+            Some(client) => client.send_message(req).await,
+            None => Err(Error::ClientError(format!(
+                "no peer for Destination-Realm {}",
+                realm.as_deref().unwrap_or("(none)")
+            ))),
+        }
+    }
+}
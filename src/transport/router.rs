@@ -0,0 +1,132 @@
+//! Diameter Request Router
+//!
+//! Dispatches inbound requests to handlers registered per
+//! `(ApplicationId, CommandCode)`. Requests without a registered handler are
+//! answered with DIAMETER_COMMAND_UNSUPPORTED (3001).
+
+use crate::avp::codes::RESULT_CODE;
+use crate::avp::flags::M;
+use crate::avp::{Avp, Unsigned32};
+use crate::diameter::{ApplicationId, CommandCode, DiameterMessage};
+use crate::error::Result;
+use crate::result_code::ResultCode;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+type BoxFuture = Pin<Box<dyn Future<Output = Result<DiameterMessage>> + Send>>;
+type Handler = Box<dyn Fn(DiameterMessage) -> BoxFuture + Send + Sync>;
+
+/// Maps `(ApplicationId, CommandCode)` to async request handlers.
+///
+/// Example:
+///    ```
+///    use diameter::transport::Router;
+///    use diameter::{ApplicationId, CommandCode};
+///
+///    let router = Router::new().route(
+///        ApplicationId::CreditControl,
+///        CommandCode::CreditControl,
+///        |req| async move { Ok(req.to_answer()) },
+///    );
+///    ```
+#[derive(Default)]
+pub struct Router {
+    routes: HashMap<(ApplicationId, CommandCode), Handler>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Registers the handler for requests with the given application id and
+    /// command code, replacing any handler already registered for them.
+    pub fn route<F, Fut>(
+        mut self,
+        application_id: ApplicationId,
+        command_code: CommandCode,
+        handler: F,
+    ) -> Router
+    where
+        F: Fn(DiameterMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<DiameterMessage>> + Send + 'static,
+    {
+        self.routes.insert(
+            (application_id, command_code),
+            Box::new(move |req| Box::pin(handler(req))),
+        );
+        self
+    }
+
+    /// Passes the request to its registered handler, or answers it with
+    /// DIAMETER_COMMAND_UNSUPPORTED if no handler is registered.
+    pub async fn dispatch(&self, req: DiameterMessage) -> Result<DiameterMessage> {
+        let key = (req.get_application_id(), req.get_command_code());
+        match self.routes.get(&key) {
+            Some(handler) => handler(req).await,
+            None => {
+                let mut res = req.to_answer();
+                res.set_error(true);
+                res.add_avp(Avp::new(
+                    RESULT_CODE,
+                    None,
+                    M,
+                    Unsigned32::new(ResultCode::CommandUnsupported.as_u32()).into(),
+                ));
+                Ok(res)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diameter::flags;
+
+    fn request(application_id: ApplicationId, command_code: CommandCode) -> DiameterMessage {
+        DiameterMessage::new(command_code, application_id, flags::REQUEST, 1, 1)
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let router = Router::new().route(
+            ApplicationId::CreditControl,
+            CommandCode::CreditControl,
+            |req| async move {
+                let mut res = req.to_answer();
+                res.add_avp(Avp::new(
+                    268,
+                    None,
+                    M,
+                    Unsigned32::new(ResultCode::Success.as_u32()).into(),
+                ));
+                Ok(res)
+            },
+        );
+
+        let cca = router
+            .dispatch(request(
+                ApplicationId::CreditControl,
+                CommandCode::CreditControl,
+            ))
+            .await
+            .unwrap();
+        assert!(!cca.is_error());
+        assert_eq!(cca.result_code(), Some(ResultCode::Success));
+
+        let asa = router
+            .dispatch(request(
+                ApplicationId::CreditControl,
+                CommandCode::AbortSession,
+            ))
+            .await
+            .unwrap();
+        assert!(asa.is_error());
+        assert_eq!(asa.get_command_code(), CommandCode::AbortSession);
+        assert_eq!(asa.result_code(), Some(ResultCode::CommandUnsupported));
+    }
+}
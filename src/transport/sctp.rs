@@ -0,0 +1,171 @@
+//! Diameter over SCTP
+//!
+//! RFC 6733, section 2.1 lets Diameter run over SCTP as well as TCP. This
+//! uses a one-to-one style SCTP socket (RFC 6458, section 4), which reads
+//! and writes like a TCP stream: every message goes on stream 0, and
+//! multi-homing is left to the defaults of the OS. Enabled by the `sctp`
+//! feature on Linux and FreeBSD.
+
+use crate::transport::connector::{ConnectFuture, Connector};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A connected one-to-one style SCTP socket.
+pub struct SctpStream {
+    inner: AsyncFd<Socket>,
+}
+
+impl SctpStream {
+    /// Opens an SCTP association to `addr`.
+    pub async fn connect(addr: SocketAddr) -> io::Result<SctpStream> {
+        // Connects in blocking mode, since std has no stable error kind for
+        // the EINPROGRESS of a non-blocking connect
+        let socket = tokio::task::spawn_blocking(move || {
+            let socket = Socket::new(
+                Domain::for_address(addr),
+                Type::STREAM,
+                Some(Protocol::SCTP),
+            )?;
+            socket.connect(&addr.into())?;
+            socket.set_nonblocking(true)?;
+            Ok::<_, io::Error>(socket)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(SctpStream {
+            inner: AsyncFd::new(socket)?,
+        })
+    }
+
+    /// Returns the local address of the association.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner
+            .get_ref()
+            .local_addr()?
+            .as_socket()
+            .ok_or_else(|| io::Error::other("SCTP socket has no IP address"))
+    }
+}
+
+impl AsyncRead for SctpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| inner.get_ref().read(unfilled)) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for SctpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.inner.get_ref().shutdown(Shutdown::Write))
+    }
+}
+
+/// Connects over SCTP, trying each address `addr` resolves to in turn.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SctpConnector;
+
+impl Connector for SctpConnector {
+    type Stream = SctpStream;
+
+    fn connect<'a>(&'a self, addr: &'a str) -> ConnectFuture<'a, SctpStream> {
+        Box::pin(async move {
+            let mut last_err = io::Error::other(format!("{} resolved to no addresses", addr));
+            for addr in tokio::net::lookup_host(addr).await? {
+                match SctpStream::connect(addr).await {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => last_err = e,
+                }
+            }
+            Err(last_err)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+    use crate::stream::StreamDecoder;
+    use crate::transport::DiameterClient;
+
+    // Answers one request on a blocking SCTP listener.
+    fn answer_one(listener: Socket) {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut decoder = StreamDecoder::new();
+        let mut buf = [0; 1024];
+        let req = loop {
+            let n = socket.read(&mut buf).unwrap();
+            decoder.feed(&buf[..n]);
+            if let Some(req) = decoder.next_message().unwrap() {
+                break req;
+            }
+        };
+        socket
+            .write_all(&req.to_answer().to_bytes().unwrap())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a kernel with SCTP support, which containers often lack"]
+    async fn test_sctp_connector() {
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::SCTP)).unwrap();
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        listener.bind(&addr.into()).unwrap();
+        listener.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        std::thread::spawn(move || answer_one(listener));
+
+        let mut client = DiameterClient::new(&addr.to_string(), Default::default());
+        let mut handler = client.connect_with(&SctpConnector).await.unwrap();
+        tokio::spawn(async move {
+            DiameterClient::handle(&mut handler).await;
+        });
+
+        let ccr = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+        );
+        let cca = client.send_message(ccr).await.unwrap().await.unwrap();
+        assert_eq!(cca.get_hop_by_hop_id(), 1);
+    }
+}
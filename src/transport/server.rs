@@ -1,17 +1,177 @@
 //! Diameter Protocol Server
+use crate::avp::codes::{ORIGIN_HOST, ORIGIN_REALM, RESULT_CODE};
+use crate::avp::flags::M;
+use crate::avp::{Avp, Identity, Unsigned32};
 use crate::diameter::DiameterMessage;
 use crate::error::Result;
+use crate::result_code::ResultCode;
 use crate::transport::Codec;
+use crate::transport::Router;
+use bytes::BytesMut;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 
 /// Configuration for the Diameter server.
 ///
+/// Fields:
+///     native_tls: The TLS identity to accept connections with, or `None` for plain TCP.
+///     tls: The rustls configuration to accept connections with, taking precedence
+///         over `native_tls`. Requires the `tls` feature.
+///     local_identity: If set, the Origin-Host and Origin-Realm stamped onto every answer.
+///     dedup_window: If set, how long answers are kept to answer duplicate requests.
+///     handler_timeout: If set, how long the handler may take before the request is
+///         answered with DIAMETER_UNABLE_TO_COMPLY instead.
+#[derive(Default)]
 pub struct DiameterServerConfig {
     pub native_tls: Option<native_tls::Identity>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<tokio_rustls::rustls::ServerConfig>>,
+    pub local_identity: Option<LocalIdentity>,
+    pub dedup_window: Option<Duration>,
+    pub handler_timeout: Option<Duration>,
+}
+
+impl DiameterServerConfig {
+    /// Sets the Origin-Host and Origin-Realm of the server.
+    ///
+    /// Every answer sent by the server carries them, replacing any
+    /// Origin-Host or Origin-Realm set by the handler, e.g. copied from the
+    /// request.
+    pub fn local_identity(mut self, host: &str, realm: &str) -> Self {
+        self.local_identity = Some(LocalIdentity {
+            host: host.into(),
+            realm: realm.into(),
+        });
+        self
+    }
+
+    /// Enables duplicate request detection (RFC 6733, section 5.1).
+    ///
+    /// A request with the Origin-Host and End-to-End Identifier of one
+    /// answered within `window`, on any connection, gets the cached answer
+    /// with its own Hop-by-Hop Identifier instead of running the handler
+    /// again. Duplicates arriving before the first answer is sent still run
+    /// the handler.
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Bounds the time the handler may take to answer a request.
+    ///
+    /// When the handler has not answered within `timeout`, it is cancelled
+    /// and the request is answered with DIAMETER_UNABLE_TO_COMPLY (5012),
+    /// so a stuck handler does not hold up the connection. Such answers are
+    /// not kept for duplicate detection, so a retransmission runs the
+    /// handler again.
+    pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = Some(timeout);
+        self
+    }
+}
+
+/// The Diameter identity of a server, see `DiameterServerConfig::local_identity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalIdentity {
+    pub host: String,
+    pub realm: String,
+}
+
+impl LocalIdentity {
+    // Sets Origin-Host (264) and Origin-Realm (296) on the answer.
+    fn stamp(&self, answer: &mut DiameterMessage) {
+        answer.set_avp(Avp::new(
+            ORIGIN_HOST,
+            None,
+            M,
+            Identity::new(&self.host).into(),
+        ));
+        answer.set_avp(Avp::new(
+            ORIGIN_REALM,
+            None,
+            M,
+            Identity::new(&self.realm).into(),
+        ));
+    }
+}
+
+// Recent answers keyed by the Origin-Host and End-to-End Identifier of
+// their request. Entries are expired in insertion order, which is also
+// their expiry order.
+struct DedupCache {
+    window: Duration,
+    entries: Mutex<DedupEntries>,
+}
+
+#[derive(Default)]
+struct DedupEntries {
+    answers: HashMap<(String, u32), DiameterMessage>,
+    order: VecDeque<(Instant, (String, u32))>,
+}
+
+impl DedupCache {
+    fn new(window: Duration) -> DedupCache {
+        DedupCache {
+            window,
+            entries: Mutex::new(DedupEntries::default()),
+        }
+    }
+
+    // Origin-Host and End-to-End Identifier of a request, if it has an Origin-Host.
+    fn key(req: &DiameterMessage) -> Option<(String, u32)> {
+        let origin_host = req
+            .get_avp(ORIGIN_HOST)?
+            .get_identity()?
+            .value()
+            .to_string();
+        Some((origin_host, req.get_end_to_end_id()))
+    }
+
+    // Returns the cached answer to a duplicate of `req`, for the `req` hop-by-hop id.
+    fn get(&self, req: &DiameterMessage) -> Result<Option<DiameterMessage>> {
+        let Some(key) = Self::key(req) else {
+            return Ok(None);
+        };
+        let mut entries = self.entries.lock()?;
+        entries.expire(self.window);
+        Ok(entries.answers.get(&key).map(|answer| {
+            let mut answer = answer.clone();
+            answer.set_hop_by_hop_id(req.get_hop_by_hop_id());
+            answer
+        }))
+    }
+
+    fn insert(&self, key: (String, u32), answer: &DiameterMessage) -> Result<()> {
+        let mut entries = self.entries.lock()?;
+        entries.expire(self.window);
+        if entries
+            .answers
+            .insert(key.clone(), answer.clone())
+            .is_none()
+        {
+            entries.order.push_back((Instant::now(), key));
+        }
+        Ok(())
+    }
+}
+
+impl DedupEntries {
+    fn expire(&mut self, window: Duration) {
+        while let Some((inserted, _)) = self.order.front() {
+            if inserted.elapsed() < window {
+                break;
+            }
+            if let Some((_, key)) = self.order.pop_front() {
+                self.answers.remove(&key);
+            }
+        }
+    }
 }
 
 /// A Diameter protocol server for handling Diameter requests and responses.
@@ -37,6 +197,12 @@ impl DiameterServer {
         Ok(DiameterServer { listener, config })
     }
 
+    /// Returns the address the server listens on, e.g. to find the port the
+    /// OS picked when bound to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
     /// Listens for incoming connections and processes Diameter messages.
     ///
     /// This method continuously accepts new connections, reads incoming Diameter messages,
@@ -59,7 +225,41 @@ impl DiameterServer {
         F: Fn(DiameterMessage) -> Fut + Clone + Send + 'static,
         Fut: Future<Output = Result<DiameterMessage>> + Send + 'static,
     {
+        let local_identity = self.config.local_identity.clone().map(Arc::new);
+        let dedup = self
+            .config
+            .dedup_window
+            .map(|window| Arc::new(DedupCache::new(window)));
+        let handler_timeout = self.config.handler_timeout;
         loop {
+            #[cfg(feature = "tls")]
+            if let Some(ref config) = self.config.tls {
+                let acceptor = tokio_rustls::TlsAcceptor::from(Arc::clone(config));
+                let (stream, peer_addr) = self.listener.accept().await?;
+                let handler = handler.clone();
+                let local_identity = local_identity.clone();
+                let dedup = dedup.clone();
+                // Handshake off the accept loop, so a client that never
+                // completes it does not hold up other connections
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            Self::handle_peer(
+                                peer_addr,
+                                stream,
+                                handler,
+                                local_identity,
+                                dedup,
+                                handler_timeout,
+                            );
+                        }
+                        Err(e) => {
+                            log::error!("[{}] TLS handshake failed: {:?}", peer_addr, e);
+                        }
+                    }
+                });
+                continue;
+            }
             match self.config.native_tls {
                 Some(ref identity) => {
                     let acceptor = native_tls::TlsAcceptor::new(identity.clone())?;
@@ -67,7 +267,14 @@ impl DiameterServer {
                     let (stream, peer_addr) = self.listener.accept().await?;
                     match acceptor.accept(stream).await {
                         Ok(stream) => {
-                            Self::handle_peer(peer_addr, stream, handler.clone());
+                            Self::handle_peer(
+                                peer_addr,
+                                stream,
+                                handler.clone(),
+                                local_identity.clone(),
+                                dedup.clone(),
+                                handler_timeout,
+                            );
                         }
                         Err(e) => {
                             log::error!("TLS handshake failed: {:?}", e);
@@ -76,21 +283,58 @@ impl DiameterServer {
                 }
                 None => {
                     let (stream, peer_addr) = self.listener.accept().await?;
-                    Self::handle_peer(peer_addr, stream, handler.clone());
+                    Self::handle_peer(
+                        peer_addr,
+                        stream,
+                        handler.clone(),
+                        local_identity.clone(),
+                        dedup.clone(),
+                        handler_timeout,
+                    );
                 }
             };
         }
     }
 
-    fn handle_peer<F, Fut, S>(peer_addr: SocketAddr, stream: S, handler: F)
-    where
+    /// Listens for incoming connections and dispatches each request through `router`.
+    ///
+    /// Args:
+    ///     router: The `Router` mapping application ids and command codes to handlers.
+    ///
+    /// Returns:
+    ///     A `Result` indicating the success or failure of the operation.
+    pub async fn listen_router(&mut self, router: Router) -> Result<()> {
+        let router = Arc::new(router);
+        self.listen(move |req| {
+            let router = Arc::clone(&router);
+            async move { router.dispatch(req).await }
+        })
+        .await
+    }
+
+    fn handle_peer<F, Fut, S>(
+        peer_addr: SocketAddr,
+        stream: S,
+        handler: F,
+        local_identity: Option<Arc<LocalIdentity>>,
+        dedup: Option<Arc<DedupCache>>,
+        handler_timeout: Option<Duration>,
+    ) where
         F: Fn(DiameterMessage) -> Fut + Clone + Send + 'static,
         Fut: Future<Output = Result<DiameterMessage>> + Send + 'static,
         S: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
     {
         tokio::spawn(async move {
             log::info!("[{}] Connection established", peer_addr);
-            match Self::process_incoming_message(stream, handler).await {
+            match Self::process_incoming_message(
+                stream,
+                handler,
+                local_identity,
+                dedup,
+                handler_timeout,
+            )
+            .await
+            {
                 Ok(_) => {
                     log::info!("[{}] Connection closed", peer_addr);
                 }
@@ -101,15 +345,22 @@ impl DiameterServer {
         });
     }
 
-    async fn process_incoming_message<F, Fut, S>(mut stream: S, handler: F) -> Result<()>
+    async fn process_incoming_message<F, Fut, S>(
+        mut stream: S,
+        handler: F,
+        local_identity: Option<Arc<LocalIdentity>>,
+        dedup: Option<Arc<DedupCache>>,
+        handler_timeout: Option<Duration>,
+    ) -> Result<()>
     where
         F: Fn(DiameterMessage) -> Fut,
         Fut: Future<Output = Result<DiameterMessage>>,
         S: AsyncReadExt + AsyncWriteExt + Unpin,
     {
+        let mut buf = BytesMut::new();
         loop {
             // Read and decode the request
-            let req = match Codec::decode(&mut stream).await {
+            let req = match Codec::decode_with_buffer(&mut stream, &mut buf).await {
                 Ok(req) => req,
                 Err(e) => match e {
                     crate::error::Error::IoError(ref e)
@@ -123,11 +374,63 @@ impl DiameterServer {
                 },
             };
 
+            // Answer a duplicate from the cache
+            let cached = match &dedup {
+                Some(dedup) => dedup.get(&req)?,
+                None => None,
+            };
+            if let Some(res) = cached {
+                log::debug!(
+                    "Answering duplicate request from the cache; hop_by_hop_id {}, end_to_end_id {}",
+                    req.get_hop_by_hop_id(),
+                    req.get_end_to_end_id()
+                );
+                Codec::encode(&mut stream, &res).await?;
+                continue;
+            }
+
             // Process the request using the handler
-            let res = handler(req).await?;
+            let mut dedup_key = dedup.as_ref().and_then(|_| DedupCache::key(&req));
+            let mut res = match handler_timeout {
+                Some(timeout) => {
+                    let fallback = req.to_answer();
+                    match tokio::time::timeout(timeout, handler(req)).await {
+                        Ok(res) => res?,
+                        Err(_) => {
+                            dedup_key = None;
+                            Self::timeout_answer(fallback, timeout)
+                        }
+                    }
+                }
+                None => handler(req).await?,
+            };
+            if let Some(local_identity) = &local_identity {
+                local_identity.stamp(&mut res);
+            }
+            if let (Some(dedup), Some(key)) = (&dedup, dedup_key) {
+                dedup.insert(key, &res)?;
+            }
 
             // Encode and send the response
             Codec::encode(&mut stream, &res).await?;
         }
     }
+
+    // Completes the answer to a request whose handler exceeded `timeout`.
+    fn timeout_answer(mut answer: DiameterMessage, timeout: Duration) -> DiameterMessage {
+        log::warn!(
+            "Handler did not answer within {:?}, answering with DIAMETER_UNABLE_TO_COMPLY; hop_by_hop_id {}, end_to_end_id {}, command code {}",
+            timeout,
+            answer.get_hop_by_hop_id(),
+            answer.get_end_to_end_id(),
+            answer.get_command_code()
+        );
+        answer.add_avp(Avp::new(
+            RESULT_CODE,
+            None,
+            M,
+            Unsigned32::new(ResultCode::UnableToComply.as_u32()).into(),
+        ));
+        answer
+    }
 }